@@ -0,0 +1,669 @@
+// ベンチマークコマンドで1操作ごとの所要時間を集計するための仕組み
+use serde_json::{self, Map, Value};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use csv;
+use rng::Rng;
+
+// 1つの2のべき乗の範囲を分割するバケットの数 (2^SUB_BUCKET_BITS)
+// 記録した値の相対誤差は 1/128 未満になる
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = ((64 - SUB_BUCKET_BITS + 1) << SUB_BUCKET_BITS) as usize;
+
+// 操作ごとの所要時間 (ナノ秒) のヒストグラム
+// サンプルを個別には保持しないため、記録した件数によらずメモリ使用量は一定 (約60KB) になる
+// 無効な場合は計測を行わず、計測のオーバーヘッドもかからない
+#[derive(Debug, Clone)]
+pub struct Latencies {
+    enabled: bool,
+    buckets: Vec<u64>,
+    count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+impl Latencies {
+    pub fn new(enabled: bool) -> Self {
+        Latencies {
+            enabled,
+            buckets: if enabled {
+                vec![0; BUCKETS]
+            } else {
+                Vec::new()
+            },
+            count: 0,
+            sum: 0,
+            min: u64::max_value(),
+            max: 0,
+        }
+    }
+
+    // `f`を実行し、有効であればその所要時間を記録する
+    pub fn measure<T, F: FnOnce() -> T>(&mut self, f: F) -> T {
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        self.record(start.elapsed());
+        result
+    }
+
+    pub fn record(&mut self, elapsed: Duration) {
+        if !self.enabled {
+            return;
+        }
+        let nanos = elapsed.as_secs() as u128 * 1_000_000_000 + u128::from(elapsed.subsec_nanos());
+        let nanos = if nanos > u128::from(u64::max_value()) {
+            u64::max_value()
+        } else {
+            nanos as u64
+        };
+        self.buckets[bucket_index(nanos)] += 1;
+        self.count += 1;
+        self.sum += u128::from(nanos);
+        self.min = self.min.min(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // 記録がなければ None を返す
+    pub fn summary(&self) -> Option<LatencySummary> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(LatencySummary {
+            min: nanos_to_duration(self.min),
+            mean: nanos_to_duration((self.sum / u128::from(self.count)) as u64),
+            p50: self.percentile(500),
+            p90: self.percentile(900),
+            p99: self.percentile(990),
+            p999: self.percentile(999),
+            max: nanos_to_duration(self.max),
+        })
+    }
+
+    // nearest-rank法で、千分率pに当たる値が含まれるバケットの下限を求める
+    // バケットの幅による誤差があるため、記録された最小値と最大値の範囲に収める
+    fn percentile(&self, p: u64) -> Duration {
+        let rank = ((p * self.count + 999) / 1000).max(1);
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                let nanos = bucket_lower_bound(i).max(self.min).min(self.max);
+                return nanos_to_duration(nanos);
+            }
+        }
+        nanos_to_duration(self.max)
+    }
+}
+
+// 値が SUB_BUCKETS 未満であればそのまま、それ以上であれば上位 SUB_BUCKET_BITS+1 ビットでバケットを決める
+fn bucket_index(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS {
+        return nanos as usize;
+    }
+    let shift = 63 - nanos.leading_zeros() - SUB_BUCKET_BITS;
+    let sub = (nanos >> shift) - SUB_BUCKETS;
+    (((u64::from(shift) + 1) << SUB_BUCKET_BITS) + sub) as usize
+}
+
+fn bucket_lower_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = (index >> SUB_BUCKET_BITS) - 1;
+    (SUB_BUCKETS + (index & (SUB_BUCKETS - 1))) << shift
+}
+
+fn nanos_to_duration(nanos: u64) -> Duration {
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+// `ops`件の操作に`elapsed`かかった場合の1秒あたりの操作数
+pub fn ops_per_sec(ops: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+    ops as f64 / secs
+}
+
+// cannyls のジャーナルレコードの大きさ
+// どのレコードも チェックサム(4) + 長さ(2) + タグ(1) + LumpId(16) から始まり、
+// Put はデータ領域上の位置 (開始アドレス5 + 長さ2) を、Embed は値の長さ(2)と値そのものを持つ
+const RECORD_HEADER_SIZE: u64 = 4 + 2 + 1 + 16;
+const PUT_RECORD_SIZE: u64 = RECORD_HEADER_SIZE + 5 + 2;
+const EMBED_RECORD_OVERHEAD: u64 = RECORD_HEADER_SIZE + 2;
+
+// ジャーナルのGCは生きているレコードを末尾に書き直すので、書き込むレコードの数倍の余裕を持たせる
+const JOURNAL_HEADROOM: u64 = 4;
+
+// データ領域では値の末尾に2バイトの情報が付き、ブロック単位で割り当てられる
+pub const DATA_TRAILER_SIZE: u64 = 2;
+
+// データ領域上のアドレスは40ビット (ブロック単位) で表される
+const MAX_DATA_BLOCKS: u64 = 1 << 40;
+
+// ベンチマーク用に生成するストレージの大きさ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StorageLayout {
+    // ストレージ全体の大きさ (バイト)
+    pub capacity: u64,
+    pub journal_ratio: f64,
+    // 書き込む値の大きさの合計 (バイト)
+    pub total: u64,
+}
+
+// `size`バイトの値を`count`個書き込むベンチマークに必要なストレージの大きさを求める
+// 桁あふれする場合や、cannylsで扱えない大きさになる場合はエラーを返す
+pub fn storage_layout(
+    count: u64,
+    size: u64,
+    embedded: bool,
+    block_size: u64,
+) -> Result<StorageLayout, String> {
+    if count == 0 {
+        return Err("count must be greater than 0".to_owned());
+    }
+    if size == 0 {
+        return Err("size must be greater than 0".to_owned());
+    }
+    let overflow = || {
+        format!(
+            "parameters overflow u64 (count = {}, size = {})",
+            count, size
+        )
+    };
+    let total = count.checked_mul(size).ok_or_else(overflow)?;
+
+    let record_size = if embedded {
+        EMBED_RECORD_OVERHEAD.checked_add(size)
+    } else {
+        Some(PUT_RECORD_SIZE)
+    };
+    let journal_bytes = record_size
+        .and_then(|n| n.checked_mul(count))
+        .and_then(|n| n.checked_mul(JOURNAL_HEADROOM))
+        .and_then(|n| n.checked_add(block_size))
+        .ok_or_else(overflow)?;
+
+    // 埋め込みの場合も書き込みはデータ領域を使わないが、ストレージの生成にはデータ領域が必要になる
+    let data_blocks = size
+        .checked_add(DATA_TRAILER_SIZE + block_size - 1)
+        .map(|n| n / block_size)
+        .and_then(|n| n.checked_mul(count))
+        .and_then(|n| n.checked_mul(2))
+        .ok_or_else(overflow)?;
+    if data_blocks > MAX_DATA_BLOCKS {
+        return Err(format!(
+            "the data region for {} values of {} bytes exceeds the maximum size of cannyls",
+            count, size
+        ));
+    }
+    let capacity = data_blocks
+        .checked_mul(block_size)
+        .and_then(|n| n.checked_add(journal_bytes))
+        .ok_or_else(overflow)?;
+
+    let journal_ratio = (journal_bytes as f64 / capacity as f64).max(0.01);
+    Ok(StorageLayout {
+        capacity,
+        journal_ratio,
+        total,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub min: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+impl fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "p50 = {:?}, p90 = {:?}, p99 = {:?}, p999 = {:?}, max = {:?}",
+            self.p50, self.p90, self.p99, self.p999, self.max
+        )
+    }
+}
+
+// ベンチマークの途中経過を、指定した件数ごとに標準エラー出力に表示する
+// `in_place`の場合 (端末に出力する場合) は `\r` で同じ行を書き換え、そうでなければ1行ずつ出力する
+#[derive(Debug)]
+pub struct Progress {
+    interval: u64,
+    total: u64,
+    start: Instant,
+    in_place: bool,
+    shown: bool,
+}
+impl Progress {
+    pub fn new(interval: u64, total: u64, in_place: bool) -> Self {
+        Progress {
+            interval,
+            total,
+            start: Instant::now(),
+            in_place,
+            shown: false,
+        }
+    }
+
+    // `done`件を終えたことを伝える (`interval`件ごとにのみ表示する)
+    pub fn update(&mut self, done: u64) {
+        if done % self.interval != 0 {
+            return;
+        }
+        let line = progress_line(done, self.total, self.start.elapsed());
+        if self.in_place {
+            eprint!("\r{}", line);
+            self.shown = true;
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+
+    // 同じ行を書き換えていた場合は改行し、後続の出力と混ざらないようにする
+    pub fn finish(&mut self) {
+        if self.shown {
+            eprintln!();
+            self.shown = false;
+        }
+    }
+}
+
+// 書き込んだ件数、全体に対する割合、それまでのスループット
+pub fn progress_line(done: u64, total: u64, elapsed: Duration) -> String {
+    let percent = if total == 0 {
+        100.0
+    } else {
+        done as f64 * 100.0 / total as f64
+    };
+    format!(
+        "written {}/{} ({:.1}%), {:.2}ops/s",
+        done,
+        total,
+        percent,
+        ops_per_sec(done, elapsed)
+    )
+}
+
+fn duration_to_nanos(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000_000 + u64::from(d.subsec_nanos())
+}
+
+// ベンチマークで書き込む値の内容
+// Zero: 全て0, Random: 書き込むたびに乱数で埋める, Pattern: 0, 1, 2, ... と1ずつ増えるバイト列
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueFill {
+    Zero,
+    Random,
+    Pattern,
+}
+impl ValueFill {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ValueFill::Zero => "zero",
+            ValueFill::Random => "random",
+            ValueFill::Pattern => "pattern",
+        }
+    }
+
+    pub fn fill(self, buf: &mut [u8], rng: &mut Rng) {
+        match self {
+            ValueFill::Zero => {
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+            }
+            ValueFill::Random => rng.fill_bytes(buf),
+            ValueFill::Pattern => {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = i as u8;
+                }
+            }
+        }
+    }
+}
+impl FromStr for ValueFill {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(ValueFill::Zero),
+            "random" => Ok(ValueFill::Random),
+            "pattern" => Ok(ValueFill::Pattern),
+            _ => Err(format!(
+                "`{}` is an invalid fill (zero, random or pattern)",
+                s
+            )),
+        }
+    }
+}
+
+// ベンチマークで書き込む値を`fill`に従って用意する
+// Random の場合のみ、値を取り出すたびに新たな乱数で埋め直す
+#[derive(Debug)]
+pub struct Values {
+    fill: ValueFill,
+    rng: Rng,
+    buf: Vec<u8>,
+}
+impl Values {
+    pub fn new(fill: ValueFill, size: usize, seed: u64) -> Self {
+        let mut values = Values {
+            fill,
+            rng: Rng::new(seed),
+            buf: vec![0; size],
+        };
+        fill.fill(&mut values.buf, &mut values.rng);
+        values
+    }
+
+    pub fn next_value(&mut self) -> &[u8] {
+        if self.fill == ValueFill::Random {
+            self.fill.fill(&mut self.buf, &mut self.rng);
+        }
+        &self.buf
+    }
+}
+
+// ベンチマーク結果の機械可読な出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultFormat {
+    Json,
+    Csv,
+}
+impl FromStr for ResultFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ResultFormat::Json),
+            "csv" => Ok(ResultFormat::Csv),
+            _ => Err(format!("`{}` is an invalid result format (json or csv)", s)),
+        }
+    }
+}
+
+// ベンチマーク1回分の条件と結果
+// JSONでは1つのオブジェクト、CSVでは1行として出力する (所要時間はナノ秒)
+#[derive(Debug, Clone)]
+pub struct BenchRecord {
+    pub bench: &'static str,
+    pub count: u64,
+    pub size: Option<usize>,
+    pub pattern: String,
+    pub seed: u64,
+    // 値を書き込まないベンチマークでは None
+    pub fill: Option<ValueFill>,
+    // 同期しない場合や同期を行わないベンチマークでは None
+    pub sync_interval: Option<u64>,
+    pub block_size: u16,
+    pub ops: u64,
+    pub elapsed: Duration,
+    pub put_latency: Option<LatencySummary>,
+    pub get_latency: Option<LatencySummary>,
+    pub delete_latency: Option<LatencySummary>,
+}
+impl BenchRecord {
+    // 出力する列の名前と値 (JSONとCSVで共通)
+    fn columns(&self) -> Vec<(String, Value)> {
+        let mut columns = vec![
+            ("bench", json!(self.bench)),
+            ("count", json!(self.count)),
+            ("size", json!(self.size)),
+            ("pattern", json!(self.pattern)),
+            ("seed", json!(self.seed)),
+            ("fill", json!(self.fill.map(ValueFill::as_str))),
+            ("sync_interval", json!(self.sync_interval)),
+            ("block_size", json!(self.block_size)),
+            ("ops", json!(self.ops)),
+            ("elapsed_ns", json!(duration_to_nanos(self.elapsed))),
+            ("ops_per_sec", json!(ops_per_sec(self.ops, self.elapsed))),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect::<Vec<_>>();
+        let latencies = [
+            ("put", self.put_latency),
+            ("get", self.get_latency),
+            ("delete", self.delete_latency),
+        ];
+        for &(op, summary) in &latencies {
+            let stats = summary.map(|s| [s.p50, s.p90, s.p99, s.p999, s.max]);
+            for (i, stat) in ["p50", "p90", "p99", "p999", "max"].iter().enumerate() {
+                let nanos = stats.map(|s| duration_to_nanos(s[i]));
+                columns.push((format!("{}_{}_ns", op, stat), json!(nanos)));
+            }
+        }
+        columns
+    }
+
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let object = self.columns().into_iter().collect::<Map<_, _>>();
+        serde_json::to_writer(&mut out, &object)?;
+        writeln!(out)
+    }
+
+    // `header`が真であれば、列名の行を先に出力する
+    pub fn write_csv<W: Write>(&self, header: bool, mut out: W) -> io::Result<()> {
+        let columns = self.columns();
+        if header {
+            let names = columns.iter().map(|c| c.0.as_str()).collect::<Vec<_>>();
+            csv::write_record(&mut out, &names)?;
+        }
+        let values = columns
+            .iter()
+            .map(|c| match c.1 {
+                Value::Null => String::new(),
+                Value::String(ref s) => s.clone(),
+                ref v => v.to_string(),
+            })
+            .collect::<Vec<_>>();
+        let values = values.iter().map(|v| v.as_str()).collect::<Vec<_>>();
+        csv::write_record(&mut out, &values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_works() {
+        let mut latencies = Latencies::new(true);
+        assert_eq!(latencies.summary(), None);
+
+        // 1000, 999, ..., 1 ナノ秒
+        // 128ナノ秒未満はバケットの幅が1なので、値がそのまま得られる
+        for ns in (1..1001).rev() {
+            latencies.record(Duration::from_nanos(ns));
+        }
+        assert_eq!(latencies.len(), 1000);
+        let summary = latencies.summary().unwrap();
+        assert_eq!(summary.min, Duration::from_nanos(1));
+        assert_eq!(summary.max, Duration::from_nanos(1000));
+        assert_eq!(summary.mean, Duration::from_nanos(500));
+        assert_eq!(summary.p50, Duration::from_nanos(500));
+        assert_eq!(summary.p90, Duration::from_nanos(900));
+        assert_eq!(summary.p99, Duration::from_nanos(988));
+        assert_eq!(summary.p999, Duration::from_nanos(996));
+
+        let mut latencies = Latencies::new(true);
+        latencies.record(Duration::from_millis(7));
+        let summary = latencies.summary().unwrap();
+        assert_eq!(summary.p50, Duration::from_millis(7));
+        assert_eq!(summary.p999, Duration::from_millis(7));
+        assert_eq!(summary.max, Duration::from_millis(7));
+        assert_eq!(summary.mean, Duration::from_millis(7));
+    }
+
+    #[test]
+    fn percentiles_are_within_bucket_resolution() {
+        // 100, 99, ..., 1 ミリ秒
+        let mut latencies = Latencies::new(true);
+        for ms in (1..101).rev() {
+            latencies.record(Duration::from_millis(ms));
+        }
+        let summary = latencies.summary().unwrap();
+        for &(actual, expected) in &[
+            (summary.p50, 50),
+            (summary.p90, 90),
+            (summary.p99, 99),
+            (summary.p999, 100),
+        ] {
+            let expected = Duration::from_millis(expected);
+            assert!(actual <= expected);
+            assert!(expected - actual < expected / 128);
+        }
+        assert_eq!(summary.max, Duration::from_millis(100));
+
+        for &nanos in &[0, 1, 127, 128, 129, 1000, 123_456_789, u64::max_value()] {
+            let lower = bucket_lower_bound(bucket_index(nanos));
+            assert!(lower <= nanos);
+            assert!(nanos - lower <= nanos / 128);
+        }
+        assert_eq!(bucket_index(u64::max_value()), BUCKETS - 1);
+    }
+
+    #[test]
+    fn disabled_latencies_record_nothing() {
+        let mut latencies = Latencies::new(false);
+        assert_eq!(latencies.measure(|| 1 + 1), 2);
+        latencies.record(Duration::from_millis(1));
+        assert!(latencies.is_empty());
+        assert_eq!(latencies.summary(), None);
+    }
+
+    #[test]
+    fn progress_line_works() {
+        assert_eq!(
+            progress_line(250, 1000, Duration::from_secs(2)),
+            "written 250/1000 (25.0%), 125.00ops/s"
+        );
+        assert_eq!(
+            progress_line(1000, 1000, Duration::from_millis(500)),
+            "written 1000/1000 (100.0%), 2000.00ops/s"
+        );
+    }
+
+    #[test]
+    fn storage_layout_works() {
+        let layout = storage_layout(1000, 4096, false, 512).unwrap();
+        assert_eq!(layout.total, 4096 * 1000);
+        // 4096 + 2 バイトは 9 ブロックを使う
+        assert!(layout.capacity >= 9 * 512 * 1000);
+        assert!(layout.journal_ratio >= 0.01 && layout.journal_ratio < 1.0);
+        let journal = (layout.capacity as f64 * layout.journal_ratio) as u64;
+        assert!(journal >= 1000 * PUT_RECORD_SIZE);
+
+        // 小さな値を埋め込む場合は、ジャーナルが大半を占める
+        let layout = storage_layout(1000, 10, true, 512).unwrap();
+        let journal = (layout.capacity as f64 * layout.journal_ratio) as u64;
+        assert!(journal >= 1000 * (EMBED_RECORD_OVERHEAD + 10));
+        assert!(layout.journal_ratio < 1.0);
+    }
+
+    #[test]
+    fn storage_layout_rejects_pathological_inputs() {
+        assert!(storage_layout(0, 4096, false, 512).is_err());
+        assert!(storage_layout(1000, 0, false, 512).is_err());
+
+        let e = storage_layout(u64::max_value(), 1, false, 512).unwrap_err();
+        assert!(e.contains("overflow u64"));
+        let e = storage_layout(u64::max_value(), 1 << 40, false, 512).unwrap_err();
+        assert!(e.contains("overflow u64"));
+        let e = storage_layout(2, u64::max_value(), true, 512).unwrap_err();
+        assert!(e.contains("overflow u64"));
+
+        // 桁あふれはしないが、cannylsのデータ領域に収まらない
+        let e = storage_layout(1 << 30, 1 << 20, false, 512).unwrap_err();
+        assert!(e.contains("maximum size"));
+    }
+
+    #[test]
+    fn value_fill_works() {
+        let mut rng = Rng::new(0);
+        let mut buf = vec![1; 300];
+        ValueFill::Zero.fill(&mut buf, &mut rng);
+        assert!(buf.iter().all(|&b| b == 0));
+
+        ValueFill::Pattern.fill(&mut buf, &mut rng);
+        assert_eq!(&buf[..3], &[0, 1, 2]);
+        assert_eq!(buf[256], 0);
+        assert_eq!(buf[299], 43);
+
+        let mut values = Values::new(ValueFill::Random, 16, 0);
+        let first = values.next_value().to_vec();
+        assert_ne!(first, values.next_value());
+        assert_eq!(
+            Values::new(ValueFill::Random, 16, 0).next_value(),
+            &first[..]
+        );
+        assert_eq!(
+            Values::new(ValueFill::Pattern, 4, 0).next_value(),
+            &[0, 1, 2, 3]
+        );
+        assert!("ones".parse::<ValueFill>().is_err());
+    }
+
+    #[test]
+    fn bench_record_works() {
+        let mut latencies = Latencies::new(true);
+        latencies.record(Duration::from_nanos(100));
+        let record = BenchRecord {
+            bench: "WBench",
+            count: 2,
+            size: Some(512),
+            pattern: "sequential".to_owned(),
+            seed: 0,
+            fill: Some(ValueFill::Zero),
+            sync_interval: None,
+            block_size: 512,
+            ops: 2,
+            elapsed: Duration::from_millis(500),
+            put_latency: latencies.summary(),
+            get_latency: None,
+            delete_latency: None,
+        };
+
+        let mut buf = Vec::new();
+        record.write_csv(true, &mut buf).unwrap();
+        let csv = String::from_utf8(buf).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0].starts_with("bench,count,size,pattern,seed,fill,sync_interval,block_size,")
+        );
+        assert!(lines[0].ends_with(",delete_p999_ns,delete_max_ns"));
+        assert!(lines[1].starts_with("WBench,2,512,sequential,0,zero,,512,2,500000000,4.0,100,"));
+        assert!(lines[1].ends_with(",,,,,"));
+
+        let mut buf = Vec::new();
+        record.write_json(&mut buf).unwrap();
+        let json: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(json["bench"], "WBench");
+        assert_eq!(json["ops_per_sec"], 4.0);
+        assert_eq!(json["put_p999_ns"], 100);
+        assert_eq!(json["get_p50_ns"], Value::Null);
+        assert_eq!(json["sync_interval"], Value::Null);
+
+        assert_eq!("csv".parse(), Ok(ResultFormat::Csv));
+        assert!("yaml".parse::<ResultFormat>().is_err());
+    }
+}
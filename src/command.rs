@@ -0,0 +1,1086 @@
+// 対話モード(Open)やScriptで使われるコマンドの解釈と実行
+// 各コマンドの実行結果は`CommandResult`として返し、出力は`write_text`/`write_json`で行う
+use cannyls::lump::LumpId;
+use cannyls::nvm::NonVolatileMemory;
+use cannyls::storage::{JournalSnapshot, StorageHeader};
+use regex::Regex;
+use serde_json::Value as JsonValue;
+
+use handle::{
+    write_header_info, write_journal_info, write_journal_stats, write_lump_stat, write_lump_stats,
+    write_lumpids, write_storage_usage, HeaderInfo, JournalStats, JsonLump, ListFormat,
+    LumpPlacement, LumpStat, LumpStats, StorageHandle, StorageUsage,
+};
+use key::{format_key, parse_key};
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::{self, FromStr};
+
+pub const READ_ONLY_MESSAGE: &str = "storage opened read-only";
+pub const NON_INTERACTIVE_MESSAGE: &str = "refusing to prompt in non-interactive mode, pass --yes";
+
+// `help`で表示する、対話モードで使用できるコマンドとその引数の書式
+// keyは10進数、`0x`付きの16進数、32桁の16進数、UUID形式のいずれでも指定できる
+pub(crate) const COMMANDS: &[(&str, &str)] = &[
+    ("put key value", "put a value (`\"\"` is an empty value)"),
+    (
+        "put_embedded key value",
+        "put a value embedded in the journal region",
+    ),
+    ("get key", "get the value of the key"),
+    ("exists key", "check whether the key exists"),
+    ("stat key", "show the size and placement of the value"),
+    ("put_file key path", "put the contents of the file"),
+    (
+        "get_file key path",
+        "write the value of the key to the file",
+    ),
+    ("delete key", "delete the key"),
+    ("list [text|plain|hex|json]", "list all lumpids"),
+    ("list_range start end", "list lumpids in [start, end)"),
+    ("count", "show the number and total size of lumps"),
+    ("usage", "show the usage of the data and journal regions"),
+    ("dump", "dump all key-value pairs"),
+    ("clear", "delete all lumps"),
+    ("header", "show the storage header"),
+    (
+        "journal [key]",
+        "show the journal region (only the entries of the key)",
+    ),
+    (
+        "journal_stat",
+        "show the usage and entry counts of the journal",
+    ),
+    ("journal_gc", "run a full GC of the journal region"),
+    ("sync", "sync the journal to the disk"),
+    ("help", "show this help"),
+];
+
+pub fn help_text() -> String {
+    let width = COMMANDS.iter().map(|c| c.0.len()).max().unwrap_or(0);
+    let mut text = String::from("available commands:");
+    for &(syntax, description) in COMMANDS {
+        text.push_str(&format!(
+            "\n  {:width$}  {}",
+            syntax,
+            description,
+            width = width
+        ));
+    }
+    text
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    NotFound,
+    Error,
+}
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Status::Ok => "ok",
+            Status::NotFound => "not_found",
+            Status::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Payload {
+    Value(Vec<u8>),
+    // keyが[min, max]の範囲のlumpid。出力の際に`iter_keys_between`で少しずつ取得する
+    // 出力時の形式も合わせて保持する
+    Keys(u128, u128, ListFormat),
+    // 値は出力の際に`iter_entries`で1件ずつ読み込む (全ての値を一度にメモリに載せない)
+    Entries,
+    Stats(LumpStats),
+    // keyと、`--key-format`に従って整形したkey
+    Stat(u128, String, LumpStat),
+    Usage(StorageUsage),
+    Header(StorageHeader),
+    Journal(JournalSnapshot),
+    JournalStats(JournalStats),
+}
+
+#[derive(Debug)]
+pub struct CommandResult {
+    pub status: Status,
+    pub payload: Option<Payload>,
+    pub message: String,
+}
+impl CommandResult {
+    fn ok(payload: Payload) -> Self {
+        CommandResult {
+            status: Status::Ok,
+            payload: Some(payload),
+            message: String::new(),
+        }
+    }
+    fn message<T: Into<String>>(status: Status, message: T) -> Self {
+        CommandResult {
+            status,
+            payload: None,
+            message: message.into(),
+        }
+    }
+    fn error<T: Into<String>>(message: T) -> Self {
+        Self::message(Status::Error, message)
+    }
+}
+
+fn is_valid_characters(data: &str) -> bool {
+    str::from_utf8(data.as_bytes()).is_ok()
+}
+
+// 実行前に確認を求める、lumpを削除するコマンドかどうか
+pub fn is_destructive(input: &str) -> bool {
+    match input.split_whitespace().next() {
+        Some("clear") | Some("delete") => true,
+        _ => false,
+    }
+}
+
+// `delete key` の形式の入力であれば、削除しようとしているkeyを入力された文字列のまま返す
+pub fn delete_target(input: &str) -> Option<&str> {
+    let delete_regex = Regex::new(r"^delete\s+(\S+)$").unwrap();
+    delete_regex
+        .captures(input)
+        .map(|captured| captured.get(1).unwrap().as_str())
+}
+
+// `"..."` のようにダブルクォートで囲まれた値は、囲みを外して扱う
+// これにより `put 5 ""` で空の値を書き込むことができる
+pub fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+macro_rules! try_key {
+    ($s:expr) => {
+        match parse_key($s) {
+            Ok(key) => key,
+            Err(e) => return CommandResult::error(e),
+        }
+    };
+}
+
+// lumpを変更するコマンドの後には、設定されていればジャーナルのGCを自動的に行い、その旨を出力に加える
+pub fn execute<N: NonVolatileMemory>(handle: &mut StorageHandle<N>, input: &str) -> CommandResult {
+    let mut result = execute_command(handle, input);
+    if !changes_lumps(input) {
+        return result;
+    }
+    let note = match handle.auto_journal_gc() {
+        Ok(None) => return result,
+        Ok(Some((ratio, report))) => format!(
+            "auto journal GC (journal usage {:.2}%): reclaimed {} bytes",
+            ratio * 100.0,
+            report.reclaimed_bytes()
+        ),
+        Err(e) => {
+            result.status = Status::Error;
+            format!("auto journal GC failed with the error {}", e)
+        }
+    };
+    if !result.message.is_empty() {
+        result.message.push('\n');
+    }
+    result.message.push_str(&note);
+    result
+}
+
+// lumpを書き込んだり削除したりするコマンドかどうか
+pub fn changes_lumps(input: &str) -> bool {
+    match input.split_whitespace().next() {
+        Some("put") | Some("put_embedded") | Some("put_file") | Some("delete") | Some("clear") => {
+            true
+        }
+        _ => false,
+    }
+}
+
+// ストレージに書き込むコマンドかどうか (読み込み専用で開いている場合は拒否する)
+// lumpは変更しないが、ジャーナルに書き込む`journal_gc`と`sync`も含む
+fn is_mutating(input: &str) -> bool {
+    if changes_lumps(input) {
+        return true;
+    }
+    match input.split_whitespace().next() {
+        Some("journal_gc") | Some("sync") => true,
+        _ => false,
+    }
+}
+
+fn execute_command<N: NonVolatileMemory>(
+    handle: &mut StorageHandle<N>,
+    input: &str,
+) -> CommandResult {
+    let put_regex = Regex::new(r"^put(_embedded)?\s+(\S+)\s+([^\x00]+)$").unwrap();
+    let get_regex = Regex::new(r"^get\s+(\S+)$").unwrap();
+    let exists_regex = Regex::new(r"^exists\s+(\S+)$").unwrap();
+    let stat_regex = Regex::new(r"^stat\s+(\S+)$").unwrap();
+    let delete_regex = Regex::new(r"^delete\s+(\S+)$").unwrap();
+    let list_regex = Regex::new(r"^list(?:\s+(\S+))?$").unwrap();
+    let list_range_regex = Regex::new(r"^list_range\s+(\S+)\s+(\S+)$").unwrap();
+    let put_file_regex = Regex::new(r"^put_file\s+(\S+)\s+(.+)$").unwrap();
+    let get_file_regex = Regex::new(r"^get_file\s+(\S+)\s+(.+)$").unwrap();
+    let journal_regex = Regex::new(r"^journal(?:\s+(\S+))?$").unwrap();
+
+    if handle.is_read_only() && is_mutating(input) {
+        return CommandResult::error(READ_ONLY_MESSAGE);
+    }
+
+    if let Some(captured) = put_regex.captures(input) {
+        let embedded = captured.get(1).is_some();
+        let key = try_key!(captured.get(2).unwrap().as_str());
+        let shown = handle.format_key(key);
+        let value = unquote(captured.get(3).unwrap().as_str());
+
+        if !is_valid_characters(value) {
+            return CommandResult::error(format!(
+                "your input value {} is invalid wrt UTF-8",
+                input
+            ));
+        }
+        let (result, suffix) = if embedded {
+            (handle.put_embedded(key, value.as_bytes()), " (embedded)")
+        } else {
+            (handle.put_str(key, value), "")
+        };
+        match result {
+            Ok(true) => CommandResult::message(
+                Status::Ok,
+                format!("put key={}, value={}{}", shown, value, suffix),
+            ),
+            Ok(false) => CommandResult::message(
+                Status::Ok,
+                format!("[overwrite] put key={}, value={}{}", shown, value, suffix),
+            ),
+            Err(e) => CommandResult::error(format!("failed to put key {}: {}", shown, e)),
+        }
+    } else if let Some(captured) = get_regex.captures(input) {
+        let key = try_key!(captured.get(1).unwrap().as_str());
+        let shown = handle.format_key(key);
+        match handle.get_bytes(key) {
+            Ok(Some(value)) => CommandResult::ok(Payload::Value(value)),
+            Ok(None) => {
+                CommandResult::message(Status::NotFound, format!("no entry for key {}", shown))
+            }
+            Err(e) => CommandResult::error(format!("failed to get key {}: {}", shown, e)),
+        }
+    } else if let Some(captured) = exists_regex.captures(input) {
+        let key = try_key!(captured.get(1).unwrap().as_str());
+        if handle.exists(key) {
+            CommandResult::message(Status::Ok, "true")
+        } else {
+            CommandResult::message(Status::NotFound, "false")
+        }
+    } else if let Some(captured) = stat_regex.captures(input) {
+        let key = try_key!(captured.get(1).unwrap().as_str());
+        let shown = handle.format_key(key);
+        match handle.stat(key) {
+            Ok(Some(stat)) => CommandResult::ok(Payload::Stat(key, shown, stat)),
+            Ok(None) => {
+                CommandResult::message(Status::NotFound, format!("no entry for key {}", shown))
+            }
+            Err(e) => CommandResult::error(format!("failed to stat key {}: {}", shown, e)),
+        }
+    } else if let Some(captured) = put_file_regex.captures(input) {
+        let key = try_key!(captured.get(1).unwrap().as_str());
+        let shown = handle.format_key(key);
+        let path = unquote(captured.get(2).unwrap().as_str());
+        match handle.put_file(key, path) {
+            Ok(true) => {
+                CommandResult::message(Status::Ok, format!("put key={}, file={}", shown, path))
+            }
+            Ok(false) => CommandResult::message(
+                Status::Ok,
+                format!("[overwrite] put key={}, file={}", shown, path),
+            ),
+            Err(e) => {
+                CommandResult::error(format!("failed to put {} to key {}: {}", path, shown, e))
+            }
+        }
+    } else if let Some(captured) = get_file_regex.captures(input) {
+        let key = try_key!(captured.get(1).unwrap().as_str());
+        let shown = handle.format_key(key);
+        let path = unquote(captured.get(2).unwrap().as_str());
+        match handle.get_to_file(key, path) {
+            Ok(Some(size)) => CommandResult::message(
+                Status::Ok,
+                format!("wrote {} bytes of key {} to {}", size, shown, path),
+            ),
+            Ok(None) => {
+                CommandResult::message(Status::NotFound, format!("no entry for key {}", shown))
+            }
+            Err(e) => {
+                CommandResult::error(format!("failed to write key {} to {}: {}", shown, path, e))
+            }
+        }
+    } else if let Some(captured) = delete_regex.captures(input) {
+        let key = try_key!(captured.get(1).unwrap().as_str());
+        let shown = handle.format_key(key);
+        match handle.delete_key(key) {
+            Ok(result) => {
+                CommandResult::message(Status::Ok, format!("delete result => {:?}", result))
+            }
+            Err(e) => CommandResult::error(format!("failed to delete key {}: {}", shown, e)),
+        }
+    } else if let Some(captured) = list_range_regex.captures(input) {
+        let start = try_key!(captured.get(1).unwrap().as_str());
+        let end = try_key!(captured.get(2).unwrap().as_str());
+        // [start, end) を [min, max] に直す (空の範囲は min > max とする)
+        let (min, max) = if start < end {
+            (start, end - 1)
+        } else {
+            (1, 0)
+        };
+        CommandResult::ok(Payload::Keys(min, max, ListFormat::Text))
+    } else if let Some(captured) = list_regex.captures(input) {
+        match captured
+            .get(1)
+            .map_or(Ok(ListFormat::Text), |m| m.as_str().parse())
+        {
+            Ok(format) => CommandResult::ok(Payload::Keys(0, u128::max_value(), format)),
+            Err(e) => CommandResult::error(e),
+        }
+    } else if input == "dump" {
+        CommandResult::ok(Payload::Entries)
+    } else if input == "count" {
+        match handle.lump_stats() {
+            Ok(stats) => CommandResult::ok(Payload::Stats(stats)),
+            Err(e) => CommandResult::error(format!("failed to count lumps: {}", e)),
+        }
+    } else if input == "usage" {
+        match handle.usage() {
+            Ok(usage) => CommandResult::ok(Payload::Usage(usage)),
+            Err(e) => CommandResult::error(format!("failed to compute the usage: {}", e)),
+        }
+    } else if input == "journal_stat" {
+        match handle.journal_stats() {
+            Ok(stats) => CommandResult::ok(Payload::JournalStats(stats)),
+            Err(e) => CommandResult::error(format!("failed to read the journal: {}", e)),
+        }
+    } else if input == "header" {
+        CommandResult::ok(Payload::Header(handle.header().clone()))
+    } else if let Some(captured) = journal_regex.captures(input) {
+        let key = match captured.get(1) {
+            Some(key) => Some(try_key!(key.as_str())),
+            None => None,
+        };
+        match handle.journal_info(key) {
+            Ok(snapshot) => CommandResult::ok(Payload::Journal(snapshot)),
+            Err(e) => CommandResult::error(format!("failed to read the journal: {}", e)),
+        }
+    } else if input == "clear" {
+        match handle.clear() {
+            Ok(deleted) => CommandResult::message(Status::Ok, format!("cleared {} lumps", deleted)),
+            Err(e) => CommandResult::error(format!("failed to clear: {}", e)),
+        }
+    } else if input == "help" {
+        CommandResult::message(Status::Ok, help_text())
+    } else if input == "journal_gc" {
+        match handle.journal_gc_with_stats() {
+            Ok(report) => CommandResult::message(
+                Status::Ok,
+                format!("journal full GC succeeded!\n{}", report),
+            ),
+            Err(e) => CommandResult::error(format!("journal_gc failed with the error {}", e)),
+        }
+    } else if input == "sync" {
+        match handle.try_journal_sync() {
+            Ok(()) => CommandResult::message(Status::Ok, "journal sync succeeded!"),
+            Err(e) => CommandResult::error(format!("sync failed with the error {}", e)),
+        }
+    } else {
+        CommandResult::error(format!(
+            "`{}` is an invalid command (type `help` to list the commands)",
+            input
+        ))
+    }
+}
+
+// 対話モードで表示する形式で出力する
+// `dump`の結果は`handle`から値を1件ずつ読み込みながら出力する
+pub fn write_text<N, W>(
+    handle: &mut StorageHandle<N>,
+    result: &CommandResult,
+    mut out: W,
+) -> io::Result<()>
+where
+    N: NonVolatileMemory,
+    W: Write,
+{
+    if !result.message.is_empty() {
+        writeln!(out, "{}", result.message)?;
+    }
+    match result.payload {
+        None => Ok(()),
+        Some(Payload::Value(ref value)) => {
+            writeln!(out, "get => {:?}", String::from_utf8_lossy(value))
+        }
+        Some(Payload::Keys(min, max, format)) => {
+            write_lumpids(handle.iter_keys_between(min, max), format, out)
+        }
+        Some(Payload::Entries) => {
+            let mut entries = handle.iter_entries().peekable();
+            if entries.peek().is_none() {
+                return writeln!(out, "there are no lumps");
+            }
+            writeln!(out, "<lump list>")?;
+            for entry in entries {
+                match entry {
+                    Ok((key, data)) => {
+                        writeln!(out, "{:?}", (key, String::from_utf8_lossy(data.as_bytes())))?
+                    }
+                    Err(e) => return writeln!(out, "failed to dump: {}", e),
+                }
+            }
+            writeln!(out, "</lump list>")
+        }
+        Some(Payload::Stats(ref stats)) => write_lump_stats(stats, ListFormat::Text, out),
+        Some(Payload::Stat(_, ref shown, ref stat)) => write_lump_stat(shown, stat, out),
+        Some(Payload::Usage(ref usage)) => write_storage_usage(usage, out),
+        Some(Payload::Header(ref header)) => write_header_info(header, out),
+        Some(Payload::Journal(ref snapshot)) => write_journal_info(snapshot, false, out),
+        Some(Payload::JournalStats(ref stats)) => write_journal_stats(stats, out),
+    }
+}
+
+// keyは`write_text`と同じく`handle`のkeyの表示形式で出力する
+fn payload_to_json<N: NonVolatileMemory>(
+    handle: &StorageHandle<N>,
+    payload: &Payload,
+) -> JsonValue {
+    match *payload {
+        Payload::Value(ref value) => {
+            let lump = JsonLump::new(&LumpId::new(0), value);
+            let mut json = json!({"type": "value", "value": lump.value, "size": lump.size});
+            if let Some(encoding) = lump.encoding {
+                json["encoding"] = json!(encoding);
+            }
+            json
+        }
+        Payload::Keys(..) | Payload::Entries => {
+            unreachable!("keys and entries are written by write_streamed_json")
+        }
+        Payload::Stats(ref stats) => json!({
+            "type": "stats",
+            "count": stats.count,
+            "total_size": stats.total_size,
+            "average_size": stats.average_size(),
+        }),
+        Payload::Stat(key, _, ref stat) => {
+            let mut json = json!({
+                "type": "stat",
+                "key": handle.format_key(key),
+                "size": stat.size,
+                "embedded": stat.placement == LumpPlacement::Embedded,
+            });
+            match stat.placement {
+                LumpPlacement::Data {
+                    start_block,
+                    blocks,
+                } => {
+                    json["start_block"] = json!(start_block);
+                    json["blocks"] = json!(blocks);
+                }
+                // 格納場所が分からない場合は埋め込まれているかどうかも示さない
+                LumpPlacement::Unknown => json["embedded"] = JsonValue::Null,
+                LumpPlacement::Embedded => {}
+            }
+            json
+        }
+        Payload::Usage(ref usage) => json!({
+            "type": "usage",
+            "data_region_size": usage.data_region_size,
+            "allocated_bytes": usage.allocated_bytes,
+            "free_bytes": usage.free_bytes(),
+            "largest_free_extent": usage.largest_free_extent,
+            "fragmentation": usage.fragmentation(),
+            "lumps": usage.lumps,
+            "embedded_lumps": usage.embedded_lumps,
+            "journal_region_size": usage.journal_region_size,
+            "journal_used_bytes": usage.journal_used_bytes,
+            "journal_free_bytes": usage.journal_free_bytes(),
+        }),
+        Payload::Header(ref header) => {
+            // `Header --format=json`と同じフィールドにする
+            let mut json = json!(HeaderInfo::new(header));
+            json["type"] = json!("header");
+            json
+        }
+        Payload::Journal(ref snapshot) => {
+            let entries = snapshot
+                .entries
+                .iter()
+                .map(|e| format!("{:?}", e))
+                .collect::<Vec<_>>();
+            json!({
+                "type": "journal",
+                "unreleased_head": snapshot.unreleased_head,
+                "head": snapshot.head,
+                "tail": snapshot.tail,
+                "entries": entries,
+            })
+        }
+        Payload::JournalStats(ref stats) => json!({
+            "type": "journal_stats",
+            "unreleased_head": stats.positions.unreleased_head,
+            "head": stats.positions.head,
+            "tail": stats.positions.tail,
+            "capacity": stats.capacity,
+            "occupied_bytes": stats.occupied_bytes,
+            "pending_release_bytes": stats.pending_release_bytes,
+            "utilization": stats.utilization(),
+            "puts": stats.puts,
+            "embeds": stats.embeds,
+            "deletes": stats.deletes,
+            "delete_ranges": stats.delete_ranges,
+            "others": stats.others,
+        }),
+    }
+}
+
+// 1つの実行結果を1行のJSONとして出力する
+pub fn write_json<N, W>(
+    handle: &mut StorageHandle<N>,
+    result: &CommandResult,
+    mut out: W,
+) -> io::Result<()>
+where
+    N: NonVolatileMemory,
+    W: Write,
+{
+    match result.payload {
+        Some(Payload::Keys(min, max, _)) => {
+            let key_format = handle.key_format();
+            let keys = handle
+                .iter_keys_between(min, max)
+                .map(|id| Ok(json!(format_key(id.as_u128(), key_format))));
+            return write_streamed_json(result, "keys", "keys", keys, out);
+        }
+        Some(Payload::Entries) => {
+            let entries = handle.iter_entries().map(|entry| {
+                entry
+                    .map(|(key, data)| json!(JsonLump::new(&key, data.as_bytes())))
+                    .map_err(|e| e.to_string())
+            });
+            return write_streamed_json(result, "entries", "entries", entries, out);
+        }
+        _ => {}
+    }
+    let json = json!({
+        "status": result.status.as_str(),
+        "message": result.message,
+        "payload": result.payload.as_ref().map(|p| payload_to_json(handle, p)),
+    });
+    serde_json::to_writer(&mut out, &json)?;
+    writeln!(out)
+}
+
+// `dump`や`list`の結果を、1件ずつ読み込みながら`write_json`と同じ形式で出力する
+// 要素はpayloadの`field`に配列として書き出す
+// 読み込みに失敗した場合は、そこまでの要素に続けてpayloadの`error`にその内容を出力する
+fn write_streamed_json<I, W>(
+    result: &CommandResult,
+    kind: &str,
+    field: &str,
+    items: I,
+    mut out: W,
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = Result<JsonValue, String>>,
+    W: Write,
+{
+    write!(
+        out,
+        "{{\"message\":{},\"payload\":{{\"type\":{},{}:[",
+        json!(result.message),
+        json!(kind),
+        json!(field)
+    )?;
+    let mut error = None;
+    for (i, item) in items.into_iter().enumerate() {
+        match item {
+            Ok(item) => {
+                if i > 0 {
+                    write!(out, ",")?;
+                }
+                serde_json::to_writer(&mut out, &item)?;
+            }
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+    }
+    write!(out, "]")?;
+    if let Some(error) = error {
+        write!(out, ",\"error\":{}", json!(error))?;
+    }
+    writeln!(out, "}},\"status\":{}}}", json!(result.status.as_str()))
+}
+
+// スクリプトの各行を順に実行し、実行結果を`on_result`に渡す
+// 空行と `#` で始まる行(コメント)は読み飛ばす
+// エラーになった行があればそこで中断し、`false`を返す
+// `continue_on_error`の場合は中断せずに最後まで実行し、エラーがあれば`false`を返す
+pub fn run_script<N, F>(
+    handle: &mut StorageHandle<N>,
+    script: &str,
+    continue_on_error: bool,
+    mut on_result: F,
+) -> bool
+where
+    N: NonVolatileMemory,
+    F: FnMut(&mut StorageHandle<N>, usize, &CommandResult),
+{
+    let mut succeeded = true;
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let result = execute(handle, line);
+        on_result(handle, i + 1, &result);
+        if result.status == Status::Error {
+            succeeded = false;
+            if !continue_on_error {
+                break;
+            }
+        }
+    }
+    succeeded
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expected {
+    Status(Status),
+    Value(Vec<u8>),
+    Keys(Vec<LumpId>),
+}
+
+// `get 5 == "foo"` のような、コマンドの実行結果に対する期待
+// 右辺には `"値"`, `[key, ...]`, `ok`, `not_found`, `error` のいずれかを書く
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expectation {
+    source: String,
+    command: String,
+    expected: Expected,
+}
+impl Expectation {
+    // 評価の際に実行するコマンド
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    // コマンドを実行し、結果が期待通りでなければその内容を`Err`で返す
+    pub fn evaluate<N: NonVolatileMemory>(
+        &self,
+        handle: &mut StorageHandle<N>,
+    ) -> Result<(), String> {
+        let result = execute(handle, &self.command);
+        let matched = match (&self.expected, &result.payload) {
+            (Expected::Status(status), _) => result.status == *status,
+            (Expected::Value(expected), Some(Payload::Value(value))) => expected == value,
+            (Expected::Keys(expected), Some(Payload::Keys(min, max, _))) => expected
+                .iter()
+                .cloned()
+                .eq(handle.iter_keys_between(*min, *max)),
+            _ => false,
+        };
+        if matched {
+            Ok(())
+        } else {
+            Err(format!(
+                "`{}` failed: got {}",
+                self,
+                describe(handle, &result)
+            ))
+        }
+    }
+}
+impl FromStr for Expectation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "`{}` is an invalid expectation (expected `COMMAND == VALUE`)",
+                s
+            )
+        };
+        let mut parts = s.splitn(2, "==");
+        let command = parts.next().unwrap().trim();
+        let expected = parts.next().ok_or_else(invalid)?.trim();
+        if command.is_empty() {
+            return Err(invalid());
+        }
+
+        let expected = match expected {
+            "ok" => Expected::Status(Status::Ok),
+            "not_found" => Expected::Status(Status::NotFound),
+            "error" => Expected::Status(Status::Error),
+            _ if expected.len() >= 2 && expected.starts_with('"') && expected.ends_with('"') => {
+                Expected::Value(unquote(expected).as_bytes().to_vec())
+            }
+            _ if expected.starts_with('[') && expected.ends_with(']') => {
+                let inner = expected[1..expected.len() - 1].trim();
+                let mut ids = Vec::new();
+                if !inner.is_empty() {
+                    for key in inner.split(',') {
+                        ids.push(LumpId::new(parse_key(key.trim())?));
+                    }
+                }
+                Expected::Keys(ids)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Expectation {
+            source: s.to_owned(),
+            command: command.to_owned(),
+            expected,
+        })
+    }
+}
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn describe<N: NonVolatileMemory>(handle: &mut StorageHandle<N>, result: &CommandResult) -> String {
+    match result.payload {
+        Some(Payload::Value(ref value)) => format!("{:?}", String::from_utf8_lossy(value)),
+        Some(Payload::Keys(min, max, _)) => format!(
+            "[{}]",
+            handle
+                .iter_keys_between(min, max)
+                .map(|id| id.as_u128().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        _ if result.message.is_empty() => result.status.as_str().to_owned(),
+        _ => format!("{} ({})", result.status.as_str(), result.message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cannyls::nvm::{FileNvm, MemoryNvm};
+    use cannyls::storage::Storage;
+    use handle::write_header_json;
+    use key::KeyFormat;
+    use std::fs;
+    use tempdir::TempDir;
+    use trackable::result::TestResult;
+
+    use super::*;
+
+    fn memory_handle() -> Result<StorageHandle<MemoryNvm>, cannyls::Error> {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        Ok(StorageHandle::new(storage))
+    }
+
+    #[test]
+    fn auto_journal_gc_works() -> TestResult {
+        let mut handle = memory_handle()?;
+        let result = execute(&mut handle, "put 1 foo");
+        assert_eq!(result.message, "put key=1, value=foo");
+
+        handle.set_auto_gc_threshold(Some(0.000_001));
+        let result = execute(&mut handle, "delete 1");
+        assert_eq!(result.status, Status::Ok);
+        assert!(result.message.contains("\nauto journal GC (journal usage "));
+        assert_eq!(handle.journal_info(None)?.entries.len(), 0);
+
+        // 変更を伴わないコマンドの後にはGCを行わない
+        execute(&mut handle, "put 2 bar");
+        let result = execute(&mut handle, "list");
+        assert!(result.message.is_empty());
+        // lumpを変更しない`sync`や`journal_gc`の後にも行わない
+        let result = execute(&mut handle, "sync");
+        assert_eq!(result.message, "journal sync succeeded!");
+        let result = execute(&mut handle, "journal_gc");
+        assert!(!result.message.contains("auto journal GC"));
+        Ok(())
+    }
+
+    #[test]
+    fn is_destructive_works() {
+        assert!(is_destructive("clear"));
+        assert!(is_destructive("delete 5"));
+        assert!(is_destructive("  delete 0x10"));
+        assert!(!is_destructive("get 5"));
+        assert!(!is_destructive("deleted"));
+        assert!(!is_destructive(""));
+    }
+
+    #[test]
+    fn changes_lumps_works() {
+        for input in &[
+            "put 1 a",
+            "put_embedded 1 a",
+            "put_file 1 x",
+            "delete 1",
+            "clear",
+        ] {
+            assert!(changes_lumps(input), "{}", input);
+            assert!(is_mutating(input), "{}", input);
+        }
+        for input in &["journal_gc", "sync"] {
+            assert!(!changes_lumps(input), "{}", input);
+            assert!(is_mutating(input), "{}", input);
+        }
+        assert!(!changes_lumps("get 1"));
+        assert!(!is_mutating("get 1"));
+    }
+
+    #[test]
+    fn delete_target_works() {
+        assert_eq!(delete_target("delete 5"), Some("5"));
+        assert_eq!(delete_target("delete   0x1f"), Some("0x1f"));
+        assert_eq!(delete_target("delete"), None);
+        assert_eq!(delete_target("delete 1 2"), None);
+        assert_eq!(delete_target("get 5"), None);
+    }
+
+    #[test]
+    fn unquote_works() {
+        assert_eq!(unquote(r#""""#), "");
+        assert_eq!(unquote(r#""a b""#), "a b");
+        assert_eq!(unquote("abc"), "abc");
+        assert_eq!(unquote(r#"""#), r#"""#);
+    }
+
+    #[test]
+    fn file_commands_work() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let input = dir.path().join("input.bin");
+        let output = dir.path().join("output.bin");
+        track_io!(fs::write(&input, [0, 159, 146, 150]))?;
+        let mut handle = memory_handle()?;
+
+        let result = execute(&mut handle, &format!("put_file 5 {}", input.display()));
+        assert_eq!(result.status, Status::Ok);
+        let result = execute(&mut handle, &format!("get_file 5 {}", output.display()));
+        assert_eq!(result.status, Status::Ok);
+        assert_eq!(track_io!(fs::read(&output))?, vec![0, 159, 146, 150]);
+
+        let result = execute(&mut handle, &format!("get_file 6 {}", output.display()));
+        assert_eq!(result.status, Status::NotFound);
+        let missing = dir.path().join("missing.bin");
+        let result = execute(&mut handle, &format!("put_file 6 {}", missing.display()));
+        assert_eq!(result.status, Status::Error);
+        assert_eq!(execute(&mut handle, "get 6").status, Status::NotFound);
+        Ok(())
+    }
+
+    #[test]
+    fn read_only_session_leaves_file_unchanged() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+
+        let nvm = track!(FileNvm::create(&path, 4_000_000))?;
+        let mut handle = StorageHandle::new(track!(Storage::create(nvm))?);
+        handle.put_str(0, "hoge")?;
+        drop(handle);
+        let before = track_io!(fs::read(&path))?;
+
+        let mut handle = StorageHandle::open_readonly(&path)?;
+        for input in &[
+            "get 0",
+            "list",
+            "list_range 0 10",
+            "dump",
+            "header",
+            "journal",
+        ] {
+            assert_eq!(execute(&mut handle, input).status, Status::Ok, "{}", input);
+        }
+        for input in &[
+            "put 1 bar",
+            "put_file 1 x",
+            "delete 0",
+            "clear",
+            "journal_gc",
+            "sync",
+        ] {
+            let result = execute(&mut handle, input);
+            assert_eq!(result.status, Status::Error, "{}", input);
+            assert_eq!(result.message, READ_ONLY_MESSAGE);
+        }
+        assert!(handle.journal_gc_with_stats().is_err());
+        assert!(handle.try_journal_sync().is_err());
+        assert!(handle.delete_range(0, 10).is_err());
+        drop(handle);
+
+        assert_eq!(track_io!(fs::read(&path))?, before);
+        Ok(())
+    }
+
+    #[test]
+    fn execute_works() -> TestResult {
+        let mut handle = memory_handle()?;
+
+        let result = execute(&mut handle, "put 5 foo");
+        assert_eq!(result.status, Status::Ok);
+        assert_eq!(result.message, "put key=5, value=foo");
+
+        let result = execute(&mut handle, "get 5");
+        let mut buf = Vec::new();
+        write_json(&mut handle, &result, &mut buf).unwrap();
+        let json: JsonValue = serde_json::from_slice(&buf).expect("valid JSON");
+        assert_eq!(
+            json,
+            json!({"status": "ok", "message": "", "payload": {"type": "value", "value": "foo", "size": 3}})
+        );
+
+        assert_eq!(execute(&mut handle, "get 6").status, Status::NotFound);
+        assert_eq!(execute(&mut handle, "get x").status, Status::Error);
+        assert_eq!(execute(&mut handle, "exists 5").message, "true");
+        assert_eq!(execute(&mut handle, "exists 6").status, Status::NotFound);
+        assert_eq!(execute(&mut handle, "stat 6").status, Status::NotFound);
+        let result = execute(&mut handle, "put_embedded 7 tiny");
+        assert_eq!(result.message, "put key=7, value=tiny (embedded)");
+        assert_eq!(handle.is_embedded(7)?, Some(true));
+        let mut buf = Vec::new();
+        let result = execute(&mut handle, "stat 5");
+        write_json(&mut handle, &result, &mut buf).unwrap();
+        let json: JsonValue = serde_json::from_slice(&buf).expect("valid JSON");
+        assert_eq!(json["payload"]["size"], json!(3));
+        assert_eq!(json["payload"]["embedded"], json!(false));
+        assert_eq!(execute(&mut handle, "frobnicate").status, Status::Error);
+        let mut buf = Vec::new();
+        let result = execute(&mut handle, "header");
+        write_json(&mut handle, &result, &mut buf).unwrap();
+        let mut json: JsonValue = serde_json::from_slice(&buf).expect("valid JSON");
+        let mut expected = Vec::new();
+        write_header_json(handle.header(), &mut expected).unwrap();
+        let expected: JsonValue = serde_json::from_slice(&expected).expect("valid JSON");
+        assert_eq!(json["payload"]["type"], json!("header"));
+        json["payload"].as_object_mut().unwrap().remove("type");
+        assert_eq!(json["payload"], expected);
+        let result = execute(&mut handle, "journal 7");
+        match result.payload {
+            Some(Payload::Journal(ref snapshot)) => assert_eq!(snapshot.entries.len(), 1),
+            _ => panic!("unexpected payload"),
+        }
+        assert_eq!(execute(&mut handle, "journal x").status, Status::Error);
+        let result = execute(&mut handle, "sync");
+        assert_eq!(result.status, Status::Ok);
+        assert_eq!(result.message, "journal sync succeeded!");
+
+        let result = execute(&mut handle, "help");
+        assert_eq!(result.status, Status::Ok);
+        for &(syntax, _) in COMMANDS {
+            assert!(result.message.contains(syntax));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn json_keys_follow_key_format() -> TestResult {
+        let mut handle = memory_handle()?;
+        handle.set_key_format(KeyFormat::Hex);
+        execute(&mut handle, "put 255 foo");
+
+        let mut buf = Vec::new();
+        let result = execute(&mut handle, "stat 255");
+        write_json(&mut handle, &result, &mut buf).unwrap();
+        let json: JsonValue = serde_json::from_slice(&buf).expect("valid JSON");
+        assert_eq!(json["payload"]["key"], json!("0xff"));
+
+        let mut buf = Vec::new();
+        let result = execute(&mut handle, "list");
+        write_json(&mut handle, &result, &mut buf).unwrap();
+        let json: JsonValue = serde_json::from_slice(&buf).expect("valid JSON");
+        assert_eq!(json["payload"]["keys"], json!(["0xff"]));
+        Ok(())
+    }
+
+    #[test]
+    fn dump_works() -> TestResult {
+        let mut handle = memory_handle()?;
+        let result = execute(&mut handle, "dump");
+        let mut buf = Vec::new();
+        write_text(&mut handle, &result, &mut buf).unwrap();
+        assert_eq!(String::from_utf8_lossy(&buf), "there are no lumps\n");
+
+        handle.put_str(1, "foo")?;
+        handle.put_str(2, "")?;
+        let result = execute(&mut handle, "dump");
+        let mut buf = Vec::new();
+        write_text(&mut handle, &result, &mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf),
+            "<lump list>\n(LumpId(\"00000000000000000000000000000001\"), \"foo\")\n\
+             (LumpId(\"00000000000000000000000000000002\"), \"\")\n</lump list>\n"
+        );
+
+        let mut buf = Vec::new();
+        write_json(&mut handle, &result, &mut buf).unwrap();
+        let json: JsonValue = serde_json::from_slice(&buf).expect("valid JSON");
+        assert_eq!(
+            json,
+            json!({"status": "ok", "message": "", "payload": {"type": "entries", "entries": [
+                {"key": "1", "value": "foo", "size": 3},
+                {"key": "2", "value": "", "size": 0},
+            ]}})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn passing_script_works() -> TestResult {
+        let mut handle = memory_handle()?;
+        let script = "# setup\nput 5 foo\nput 6 bar\n\n  # teardown\ndelete 6\n";
+        let mut lines = Vec::new();
+        assert!(run_script(&mut handle, script, false, |_, line, _| lines.push(line)));
+        assert_eq!(lines, vec![2, 3, 6]);
+
+        for expectation in &[
+            r#"get 5 == "foo""#,
+            "get 6 == not_found",
+            "list == [5]",
+            "delete 7 == ok",
+        ] {
+            let expectation: Expectation = expectation.parse().unwrap();
+            assert_eq!(expectation.evaluate(&mut handle), Ok(()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn failing_script_works() -> TestResult {
+        let mut handle = memory_handle()?;
+        let script = "put 5 foo\nget nokey\nput 6 bar\n";
+        assert!(!run_script(&mut handle, script, false, |_, _, _| {}));
+
+        // エラーになった行以降は実行されない
+        let expectation: Expectation = "get 6 == not_found".parse().unwrap();
+        assert_eq!(expectation.evaluate(&mut handle), Ok(()));
+
+        // continue_on_error の場合は最後まで実行されるが、失敗として扱われる
+        let mut lines = Vec::new();
+        assert!(!run_script(&mut handle, script, true, |_, line, _| lines.push(line)));
+        assert_eq!(lines, vec![1, 2, 3]);
+        let expectation: Expectation = r#"get 6 == "bar""#.parse().unwrap();
+        assert_eq!(expectation.evaluate(&mut handle), Ok(()));
+
+        let expectation: Expectation = r#"get 5 == "bar""#.parse().unwrap();
+        assert_eq!(
+            expectation.evaluate(&mut handle),
+            Err(r#"`get 5 == "bar"` failed: got "foo""#.to_owned())
+        );
+        assert!("get 5".parse::<Expectation>().is_err());
+        assert!("get 5 == foo".parse::<Expectation>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+// Import/Export で使う最小限のCSVの読み書き (RFC 4180)
+// ダブルクォートで囲まれたフィールドには `,` や改行を含めることができ、`""` は `"` を表す
+// BatchPut 向けに、`key<TAB>value` 形式のTSVの読み込みも扱う
+use base64;
+use key::parse_key;
+
+use std::io::{self, BufRead, Write};
+use std::str;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    // レコードが始まる行の番号 (1始まり)
+    pub line: u64,
+    pub fields: Vec<String>,
+}
+
+pub struct Reader<R> {
+    input: R,
+    line: u64,
+}
+impl<R: BufRead> Reader<R> {
+    pub fn new(input: R) -> Self {
+        Reader { input, line: 0 }
+    }
+
+    fn read_line(&mut self, buf: &mut String) -> io::Result<bool> {
+        let n = self.input.read_line(buf)?;
+        if n > 0 {
+            self.line += 1;
+        }
+        Ok(n > 0)
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        // 空行は読み飛ばす
+        let mut buf = String::new();
+        loop {
+            buf.clear();
+            if !self.read_line(&mut buf)? {
+                return Ok(None);
+            }
+            if !buf.trim_end_matches(&['\r', '\n'][..]).is_empty() {
+                break;
+            }
+        }
+        let line = self.line;
+
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = buf.chars().collect::<Vec<_>>().into_iter().peekable();
+        loop {
+            let c = match chars.next() {
+                Some(c) => c,
+                None if quoted => {
+                    // 囲みの中の改行: 次の行に続く
+                    let mut next = String::new();
+                    if !self.read_line(&mut next)? {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("line {}: unterminated quoted field", line),
+                        ));
+                    }
+                    chars = next.chars().collect::<Vec<_>>().into_iter().peekable();
+                    continue;
+                }
+                None => break,
+            };
+            match c {
+                '"' if quoted => {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        quoted = false;
+                    }
+                }
+                '"' if field.is_empty() => quoted = true,
+                ',' if !quoted => {
+                    fields.push(field);
+                    field = String::new();
+                }
+                '\n' if !quoted => break,
+                '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+                _ => field.push(c),
+            }
+        }
+        fields.push(field);
+        Ok(Some(Record { line, fields }))
+    }
+}
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = io::Result<Record>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+// `key<TAB>value` 形式の行を、最初のタブで2つのフィールドに分けて読む
+// 値にはタブを含めることができるが、改行を含めることはできない
+pub struct TsvReader<R> {
+    input: R,
+    line: u64,
+}
+impl<R: BufRead> TsvReader<R> {
+    pub fn new(input: R) -> Self {
+        TsvReader { input, line: 0 }
+    }
+}
+impl<R: BufRead> Iterator for TsvReader<R> {
+    type Item = io::Result<Record>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = String::new();
+        loop {
+            buf.clear();
+            match self.input.read_line(&mut buf) {
+                Ok(0) => return None,
+                Ok(_) => self.line += 1,
+                Err(e) => return Some(Err(e)),
+            }
+            let line = buf.trim_end_matches(&['\r', '\n'][..]);
+            if !line.is_empty() {
+                return Some(Ok(Record {
+                    line: self.line,
+                    fields: line.splitn(2, '\t').map(|s| s.to_owned()).collect(),
+                }));
+            }
+        }
+    }
+}
+
+// UTF-8として解釈できない値は、この接頭辞を付けてbase64で表す
+// 接頭辞で始まるUTF-8の値も、区別のためにbase64で表す
+pub const BASE64_PREFIX: &str = "base64:";
+
+pub fn encode_value(value: &[u8]) -> String {
+    match str::from_utf8(value) {
+        Ok(s) if !s.starts_with(BASE64_PREFIX) => s.to_owned(),
+        _ => format!("{}{}", BASE64_PREFIX, base64::encode(value)),
+    }
+}
+
+pub fn decode_value(field: &str) -> Result<Vec<u8>, String> {
+    if field.starts_with(BASE64_PREFIX) {
+        base64::decode(&field[BASE64_PREFIX.len()..])
+            .map_err(|e| format!("invalid base64 value: {}", e))
+    } else {
+        Ok(field.as_bytes().to_vec())
+    }
+}
+
+// `key,value` の2つのフィールドからなるレコードを解釈する
+pub fn parse_key_value(record: &Record) -> Result<(u128, Vec<u8>), String> {
+    if record.fields.len() != 2 {
+        return Err(format!(
+            "expected 2 fields (key,value) but found {}",
+            record.fields.len()
+        ));
+    }
+    let key = parse_key(&record.fields[0])?;
+    let value = decode_value(&record.fields[1])?;
+    Ok((key, value))
+}
+
+// 先頭行が `key,value` (TSVでは `key<TAB>value`) であればヘッダ行とみなす
+pub fn is_header(record: &Record) -> bool {
+    record.line == 1 && record.fields == ["key", "value"]
+}
+
+fn write_field<W: Write>(out: &mut W, field: &str) -> io::Result<()> {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        write!(out, "\"{}\"", field.replace('"', "\"\""))
+    } else {
+        write!(out, "{}", field)
+    }
+}
+
+pub fn write_record<W: Write>(mut out: W, fields: &[&str]) -> io::Result<()> {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write_field(&mut out, field)?;
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(input: &str) -> io::Result<Vec<Record>> {
+        Reader::new(input.as_bytes()).collect()
+    }
+
+    #[test]
+    fn reader_works() {
+        let records =
+            read_all("1,foo\n2,\"a,b\"\r\n3,\"say \"\"hi\"\"\"\n4,\"x\ny\"\n5,\n\n").unwrap();
+        let fields = records
+            .iter()
+            .map(|r| (r.line, r.fields.clone()))
+            .collect::<Vec<_>>();
+        let row = |line, k: &str, v: &str| (line, vec![k.to_owned(), v.to_owned()]);
+        assert_eq!(
+            fields,
+            vec![
+                row(1, "1", "foo"),
+                row(2, "2", "a,b"),
+                row(3, "3", "say \"hi\""),
+                row(4, "4", "x\ny"),
+                row(6, "5", ""),
+            ]
+        );
+
+        assert!(read_all("1,\"foo\n").is_err());
+    }
+
+    #[test]
+    fn tsv_reader_works() {
+        let records = TsvReader::new("1\tfoo\r\n\n0x2\ta\tb\nbroken\n3\t\n".as_bytes())
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let fields = records
+            .iter()
+            .map(|r| (r.line, r.fields.clone()))
+            .collect::<Vec<_>>();
+        let row = |line, fields: &[&str]| (line, fields.iter().map(|s| s.to_string()).collect());
+        assert_eq!(
+            fields,
+            vec![
+                row(1, &["1", "foo"]),
+                row(3, &["0x2", "a\tb"]),
+                row(4, &["broken"]),
+                row(5, &["3", ""]),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_record_round_trips() {
+        let values = ["plain", "a,b", "say \"hi\"", "x\ny", ""];
+        let mut buf = Vec::new();
+        for v in &values {
+            write_record(&mut buf, &["1", v]).unwrap();
+        }
+        let records = Reader::new(&buf[..])
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let read = records
+            .iter()
+            .map(|r| r.fields[1].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(read, values);
+    }
+
+    #[test]
+    fn values_round_trip() {
+        for value in &[&b"foo"[..], b"", &[0, 255], b"base64:foo"] {
+            assert_eq!(
+                decode_value(&encode_value(value)).as_ref(),
+                Ok(&value.to_vec())
+            );
+        }
+        assert_eq!(encode_value(b"foo"), "foo");
+        assert_eq!(encode_value(&[0, 255]), "base64:AP8=");
+    }
+
+    #[test]
+    fn parse_key_value_works() {
+        let record = |fields: &[&str]| Record {
+            line: 1,
+            fields: fields.iter().map(|s| s.to_string()).collect(),
+        };
+        assert_eq!(
+            parse_key_value(&record(&["0x10", "foo"])),
+            Ok((16, b"foo".to_vec()))
+        );
+        assert_eq!(
+            parse_key_value(&record(&["1", "base64:AP8="])),
+            Ok((1, vec![0, 255]))
+        );
+        assert!(parse_key_value(&record(&["1", "base64:!"])).is_err());
+        assert!(parse_key_value(&record(&["1"])).is_err());
+        assert!(parse_key_value(&record(&["1", "a", "b"])).is_err());
+        assert!(parse_key_value(&record(&["x", "a"])).is_err());
+        assert!(is_header(&record(&["key", "value"])));
+    }
+}
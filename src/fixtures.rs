@@ -0,0 +1,223 @@
+use cannyls;
+use cannyls::nvm::NonVolatileMemory;
+use cannyls::storage::{Storage, StorageBuilder};
+use uuid::Uuid;
+
+use handle::StorageHandle;
+use rng::Rng;
+
+// フィクスチャを構成する操作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Put(u128, Vec<u8>),
+    Delete(u128),
+}
+
+// バグ報告などで再現可能なストレージを生成するためのレシピ
+// 同じレシピと同じシードからは、バイト単位で同一のストレージが生成される
+pub struct Recipe {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub capacity: u64,
+    pub journal_ratio: f64,
+    ops: fn(&mut Rng) -> Vec<Op>,
+}
+
+impl Recipe {
+    pub fn ops(&self, seed: u64) -> Vec<Op> {
+        (self.ops)(&mut Rng::new(seed))
+    }
+}
+
+// レシピを追加する場合はこの表に追記する
+pub const RECIPES: &[Recipe] = &[
+    Recipe {
+        name: "small-mixed",
+        description: "1000 lumps of varied sizes including binary and empty values",
+        capacity: 16 * 1024 * 1024,
+        journal_ratio: 0.05,
+        ops: small_mixed,
+    },
+    Recipe {
+        name: "fragmented",
+        description: "interleaved put/delete pattern leaving holes in the data region",
+        capacity: 16 * 1024 * 1024,
+        journal_ratio: 0.05,
+        ops: fragmented,
+    },
+    Recipe {
+        name: "journal-heavy",
+        description: "many puts and deletes without journal GC",
+        capacity: 4 * 1024 * 1024,
+        journal_ratio: 0.3,
+        ops: journal_heavy,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Recipe> {
+    RECIPES.iter().find(|r| r.name == name)
+}
+
+pub fn generate<N: NonVolatileMemory>(
+    recipe: &Recipe,
+    seed: u64,
+    nvm: N,
+) -> Result<StorageHandle<N>, cannyls::Error> {
+    // ヘッダに書き込まれるUUIDもシードから決める
+    let uuid = Uuid::parse_str(&format!("{:032x}", Rng::new(seed).next_u128()))
+        .expect("32 hex digits are a valid UUID");
+    let storage: Storage<N> = track!(StorageBuilder::new()
+        .journal_region_ratio(recipe.journal_ratio)
+        .instance_uuid(uuid)
+        .create(nvm))?;
+    let mut handle = StorageHandle::new(storage);
+    for op in recipe.ops(seed) {
+        match op {
+            Op::Put(key, value) => {
+                track!(handle.put_bytes(key, &value))?;
+            }
+            Op::Delete(key) => {
+                track!(handle.delete_key(key))?;
+            }
+        }
+    }
+    track!(handle.journal_sync())?;
+    Ok(handle)
+}
+
+fn random_value(rng: &mut Rng) -> Vec<u8> {
+    let size = match rng.gen_range(0, 10) {
+        0 => 0,
+        1..=5 => rng.gen_range(1, 64),
+        6..=8 => rng.gen_range(64, 1024),
+        _ => rng.gen_range(1024, 8 * 1024),
+    } as usize;
+    let mut value = vec![0; size];
+    if rng.gen_range(0, 3) == 0 {
+        rng.fill_bytes(&mut value);
+    } else {
+        for b in &mut value {
+            *b = b'a' + rng.gen_range(0, 26) as u8;
+        }
+    }
+    value
+}
+
+fn small_mixed(rng: &mut Rng) -> Vec<Op> {
+    (0..1000)
+        .map(|key| Op::Put(key, random_value(rng)))
+        .collect()
+}
+
+fn fragmented(rng: &mut Rng) -> Vec<Op> {
+    let mut ops = Vec::new();
+    for key in 0..1000 {
+        ops.push(Op::Put(key, random_value(rng)));
+        if key % 2 == 1 {
+            ops.push(Op::Delete(key - 1));
+        }
+    }
+    ops
+}
+
+fn journal_heavy(rng: &mut Rng) -> Vec<Op> {
+    let mut ops = Vec::new();
+    for round in 0..4 {
+        let base = round * 500;
+        for key in base..base + 500 {
+            let mut value = vec![0; rng.gen_range(1, 32) as usize];
+            rng.fill_bytes(&mut value);
+            ops.push(Op::Put(key, value));
+        }
+        for key in base..base + 500 {
+            if rng.gen_range(0, 10) != 0 {
+                ops.push(Op::Delete(key));
+            }
+        }
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use cannyls::nvm::FileNvm;
+    use std::fs;
+    use tempdir::TempDir;
+    use trackable::result::TestResult;
+
+    use super::*;
+
+    // FNV-1a
+    fn digest(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+            (h ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    // 操作列をバイト列にしてからダイジェストを取る
+    fn ops_digest(ops: &[Op]) -> u64 {
+        let mut bytes = Vec::new();
+        for op in ops {
+            match *op {
+                Op::Put(key, ref value) => {
+                    bytes.push(0);
+                    bytes.extend_from_slice(&key.to_string().into_bytes());
+                    bytes.push(b':');
+                    bytes.extend_from_slice(value);
+                }
+                Op::Delete(key) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&key.to_string().into_bytes());
+                }
+            }
+        }
+        digest(&bytes)
+    }
+
+    fn generate_file(
+        dir: &TempDir,
+        recipe: &Recipe,
+        seed: u64,
+        name: &str,
+    ) -> Result<u64, cannyls::Error> {
+        let path = dir.path().join(name);
+        let nvm = track!(FileNvm::create(&path, recipe.capacity))?;
+        drop(track!(generate(recipe, seed, nvm))?);
+        let bytes = track!(fs::read(&path).map_err(cannyls::Error::from))?;
+        Ok(digest(&bytes))
+    }
+
+    #[test]
+    fn fixtures_are_reproducible() -> TestResult {
+        let dir = track!(TempDir::new("kanils_test").map_err(cannyls::Error::from))?;
+        // シード7で生成される操作列のダイジェスト
+        // レシピや乱数生成器を変更して既存のフィクスチャが変わる場合は、ここも更新する
+        let expected = [
+            ("small-mixed", 0xcba0_6781_28f2_ce53),
+            ("fragmented", 0xda36_be02_17ba_ac5b),
+            ("journal-heavy", 0x143e_93a9_0dfa_5eb0),
+        ];
+        assert_eq!(expected.len(), RECIPES.len());
+        for &(name, digest) in &expected {
+            let recipe = find(name).expect("recipe exists");
+            assert_eq!(ops_digest(&recipe.ops(7)), digest, "recipe {}", name);
+        }
+
+        for recipe in RECIPES {
+            let a = generate_file(&dir, recipe, 7, &format!("{}-a.lusf", recipe.name))?;
+            let b = generate_file(&dir, recipe, 7, &format!("{}-b.lusf", recipe.name))?;
+            let c = generate_file(&dir, recipe, 8, &format!("{}-c.lusf", recipe.name))?;
+            assert_eq!(a, b, "recipe {}", recipe.name);
+            assert_ne!(a, c, "recipe {}", recipe.name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn recipes_can_be_found_by_name() {
+        for recipe in RECIPES {
+            assert!(find(recipe.name).is_some());
+        }
+        assert!(find("no-such-recipe").is_none());
+    }
+}
@@ -1,207 +1,3442 @@
 extern crate cannyls;
-use cannyls::lump::{LumpData, LumpId};
-use cannyls::nvm::FileNvm;
-use cannyls::storage::{JournalSnapshot, Storage, StorageBuilder};
+use cannyls::block::BlockSize;
+use cannyls::lump::{LumpData, LumpHeader, LumpId};
+use cannyls::nvm::{FileNvm, NonVolatileMemory};
+use cannyls::storage::{
+    JournalEntry, JournalRecord, JournalSnapshot, Storage, StorageBuilder, StorageHeader,
+};
+use cannyls::ErrorKind;
+use trackable::error::ErrorKindExt;
 
+use bench::DATA_TRAILER_SIZE;
+use csv;
+use key::{format_key, KeyFormat};
+use nvm::ReadOnlyNvm;
+use schemes::LumpIdScheme;
+use units::{format_bytes, format_size};
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
 use std::path::Path;
-use std::str;
+use std::str::{self, FromStr};
+use std::vec;
 
 fn lumpdata_to_string(data: &LumpData) -> String {
-    String::from_utf8(data.as_bytes().to_vec()).expect("should succeed")
+    String::from_utf8_lossy(data.as_bytes()).into_owned()
 }
 
-pub struct StorageHandle {
-    storage: Storage<FileNvm>,
+#[derive(Serialize)]
+pub(crate) struct JsonLump {
+    pub(crate) key: String,
+    pub(crate) value: String,
+    pub(crate) size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) encoding: Option<&'static str>,
+}
+impl JsonLump {
+    pub(crate) fn new(key: &LumpId, bytes: &[u8]) -> Self {
+        // u128はJSONの数値として精度を保てないため10進数の文字列にする
+        let key = key.as_u128().to_string();
+        match str::from_utf8(bytes) {
+            Ok(value) => JsonLump {
+                key,
+                value: value.to_owned(),
+                size: bytes.len(),
+                encoding: None,
+            },
+            Err(_) => JsonLump {
+                key,
+                value: base64::encode(bytes),
+                size: bytes.len(),
+                encoding: Some("base64"),
+            },
+        }
+    }
 }
 
-impl StorageHandle {
-    pub fn new(storage: Storage<FileNvm>) -> Self {
-        StorageHandle { storage }
+// lumpid一覧の出力形式
+// Text: 従来通りの`LumpId("...")`形式, Plain: 10進数, Hex: 32桁の16進数, Json: 文字列の配列
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    Text,
+    Plain,
+    Hex,
+    Json,
+}
+impl FromStr for ListFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ListFormat::Text),
+            "plain" => Ok(ListFormat::Plain),
+            "hex" => Ok(ListFormat::Hex),
+            "json" => Ok(ListFormat::Json),
+            _ => Err(format!(
+                "`{}` is an invalid list format (text, plain, hex or json)",
+                s
+            )),
+        }
     }
+}
 
-    pub fn create<T: AsRef<Path>>(path: T) -> Self {
-        let nvm = track_try_unwrap!(FileNvm::open(path));
-        let storage = track_try_unwrap!(StorageBuilder::new().open(nvm));
-        StorageHandle { storage }
+// List, Dumpでのlumpidの出力順
+// Asc: keyの昇順, Desc: keyの降順
+// cannyls はkeyの昇順にlumpidを返すため、並べ替えを行わない順序は昇順と変わらない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOrder {
+    Asc,
+    Desc,
+}
+impl FromStr for KeyOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(KeyOrder::Asc),
+            "desc" => Ok(KeyOrder::Desc),
+            _ => Err(format!("`{}` is an invalid sort order (asc or desc)", s)),
+        }
     }
+}
 
-    pub fn put_str(&mut self, key: u128, value: &str) -> Result<bool, cannyls::Error> {
-        let lump_id = LumpId::new(key);
-        let lump_data =
-            track_try_unwrap!(self.storage.allocate_lump_data_with_bytes(value.as_bytes()));
-        self.storage.put(&lump_id, &lump_data)
+// `ids`は1件ずつ書き出すため、`iter_keys`のイテレータをそのまま渡せる
+pub fn write_lumpids<I, W>(ids: I, format: ListFormat, out: W) -> io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<LumpId>,
+    W: Write,
+{
+    write_annotated_lumpids(ids, None, format, false, out)
+}
+
+// text形式の場合のみ、スキームに従った注釈を付けて出力する
+// `quiet`の場合は `<lumpid list>` などの囲みや、lumpがない旨の表示を省く
+fn write_annotated_lumpids<I, W>(
+    ids: I,
+    scheme: Option<&'static dyn LumpIdScheme>,
+    format: ListFormat,
+    quiet: bool,
+    mut out: W,
+) -> io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<LumpId>,
+    W: Write,
+{
+    let mut ids = ids.into_iter().peekable();
+    match format {
+        ListFormat::Text if quiet => {
+            for lumpid in ids {
+                let lumpid = lumpid.borrow();
+                writeln!(out, "{:?}{}", lumpid, annotation(scheme, lumpid))?;
+            }
+        }
+        ListFormat::Text => {
+            if ids.peek().is_none() {
+                writeln!(out, "there are no lumps")?;
+            } else {
+                writeln!(out, "<lumpid list>")?;
+                for lumpid in ids {
+                    let lumpid = lumpid.borrow();
+                    writeln!(out, "{:?}{}", lumpid, annotation(scheme, lumpid))?;
+                }
+                writeln!(out, "</lumpid list>")?;
+            }
+        }
+        ListFormat::Plain => {
+            for lumpid in ids {
+                writeln!(out, "{}", lumpid.borrow().as_u128())?;
+            }
+        }
+        ListFormat::Hex => {
+            for lumpid in ids {
+                writeln!(out, "{:032x}", lumpid.borrow().as_u128())?;
+            }
+        }
+        ListFormat::Json => {
+            write!(out, "[")?;
+            for (i, lumpid) in ids.enumerate() {
+                if i > 0 {
+                    write!(out, ",")?;
+                }
+                serde_json::to_writer(&mut out, &lumpid.borrow().as_u128().to_string())?;
+            }
+            writeln!(out, "]")?;
+        }
+    }
+    Ok(())
+}
+
+// スキームが設定されていれば ` [type=1, ...]` のような注釈を返す
+fn annotation(scheme: Option<&'static dyn LumpIdScheme>, key: &LumpId) -> String {
+    scheme.map_or_else(String::new, |s| format!(" [{}]", s.decode(key.as_u128())))
+}
+
+// Key-Valueペアを1件ずつ標準出力に書き出す (全件をメモリに載せない)
+// `quiet`の場合は `<lump list>` などの囲みや、lumpがない旨の表示を省く
+fn write_entries<I>(
+    entries: I,
+    scheme: Option<&'static dyn LumpIdScheme>,
+    quiet: bool,
+) -> Result<(), cannyls::Error>
+where
+    I: IntoIterator<Item = Result<(LumpId, LumpData), cannyls::Error>>,
+{
+    let mut entries = entries.into_iter().peekable();
+    if entries.peek().is_none() {
+        if !quiet {
+            println!("there are no lumps");
+        }
+        return Ok(());
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if !quiet && writeln!(out, "<lump list>").is_err() {
+        return Ok(());
+    }
+    for entry in entries {
+        let (key, data) = track!(entry)?;
+        let lump = (key, lumpdata_to_string(&data));
+        // `head` などで出力先が閉じられた場合はそこで打ち切る
+        if writeln!(out, "{:?}{}", lump, annotation(scheme, &key))
+            .and_then(|_| out.flush())
+            .is_err()
+        {
+            return Ok(());
+        }
+    }
+    if !quiet {
+        let _ = writeln!(out, "</lump list>");
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonLumpSize {
+    key: String,
+    size: usize,
+}
+
+// lumpidと値のバイト長の組を`write_lumpids`と同じ形式で出力する
+// `quiet`の場合は `<lumpid list>` などの囲みや、lumpがない旨の表示を省く
+// `write_lumpids`と同じく1件ずつ書き出すため、イテレータをそのまま渡せる
+pub fn write_lumpids_with_sizes<I, W>(
+    entries: I,
+    format: ListFormat,
+    quiet: bool,
+    mut out: W,
+) -> io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<(LumpId, usize)>,
+    W: Write,
+{
+    let mut entries = entries.into_iter().peekable();
+    match format {
+        ListFormat::Text if quiet => {
+            for entry in entries {
+                let (lumpid, size) = *entry.borrow();
+                writeln!(out, "{:?} {}", lumpid, size)?;
+            }
+        }
+        ListFormat::Text => {
+            if entries.peek().is_none() {
+                writeln!(out, "there are no lumps")?;
+            } else {
+                writeln!(out, "<lumpid list>")?;
+                for entry in entries {
+                    let (lumpid, size) = *entry.borrow();
+                    writeln!(out, "{:?} {}", lumpid, size)?;
+                }
+                writeln!(out, "</lumpid list>")?;
+            }
+        }
+        ListFormat::Plain => {
+            for entry in entries {
+                let (lumpid, size) = *entry.borrow();
+                writeln!(out, "{} {}", lumpid.as_u128(), size)?;
+            }
+        }
+        ListFormat::Hex => {
+            for entry in entries {
+                let (lumpid, size) = *entry.borrow();
+                writeln!(out, "{:032x} {}", lumpid.as_u128(), size)?;
+            }
+        }
+        ListFormat::Json => {
+            write!(out, "[")?;
+            for (i, entry) in entries.enumerate() {
+                let (lumpid, size) = *entry.borrow();
+                if i > 0 {
+                    write!(out, ",")?;
+                }
+                let entry = JsonLumpSize {
+                    key: lumpid.as_u128().to_string(),
+                    size,
+                };
+                serde_json::to_writer(&mut out, &entry)?;
+            }
+            writeln!(out, "]")?;
+        }
     }
-    pub fn put(&mut self, key: u128, value: &str) {
-        let result = track_try_unwrap!(self.put_str(key, value));
+    Ok(())
+}
 
-        if result {
-            println!("put key={}, value={}", key, value);
+// lumpの数と値のバイト長の合計
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LumpStats {
+    pub count: u64,
+    pub total_size: u64,
+}
+impl LumpStats {
+    pub fn average_size(&self) -> f64 {
+        if self.count == 0 {
+            0.0
         } else {
-            println!("[overwrite] put key={}, value={}", key, value);
+            self.total_size as f64 / self.count as f64
         }
     }
+}
 
-    pub fn get_string(&mut self, key: u128) -> Result<Option<String>, cannyls::Error> {
-        let lump_id = LumpId::new(key);
-        self.storage
-            .get(&lump_id)
-            .map(|s| s.map(|s| lumpdata_to_string(&s)))
+// Plain(とHex)は`count total_size average_size`を空白区切りで1行に出力する
+pub fn write_lump_stats<W: Write>(
+    stats: &LumpStats,
+    format: ListFormat,
+    mut out: W,
+) -> io::Result<()> {
+    match format {
+        ListFormat::Text => {
+            writeln!(out, "lumps = {}", stats.count)?;
+            writeln!(out, "total size = {}", stats.total_size)?;
+            writeln!(out, "average size = {:.2}", stats.average_size())
+        }
+        // スクリプトから使えるよう、plainでは件数のみを出力する
+        ListFormat::Plain | ListFormat::Hex => writeln!(out, "{}", stats.count),
+        ListFormat::Json => {
+            let json = json!({
+                "count": stats.count,
+                "total_size": stats.total_size,
+                "average_size": stats.average_size(),
+            });
+            serde_json::to_writer(&mut out, &json)?;
+            writeln!(out)
+        }
     }
-    pub fn get(&mut self, key: u128) {
-        let result = track_try_unwrap!(self.get_string(key));
-        if let Some(string) = result {
-            println!("get => {:?}", string);
+}
+
+// lumpの値がどこに格納されているか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LumpPlacement {
+    // ジャーナル領域に埋め込まれている
+    Embedded,
+    // データ領域の`start_block`番目のブロックから`blocks`ブロック
+    Data { start_block: u64, blocks: u64 },
+    // ジャーナルに格納場所の記録が見つからない
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LumpStat {
+    pub size: usize,
+    pub placement: LumpPlacement,
+}
+
+// `key`は`format_key`で整形済みのものを渡す
+pub fn write_lump_stat<W: Write>(key: &str, stat: &LumpStat, mut out: W) -> io::Result<()> {
+    writeln!(out, "key = {}", key)?;
+    writeln!(
+        out,
+        "size = {} ({})",
+        stat.size,
+        format_size(stat.size as u64)
+    )?;
+    match stat.placement {
+        LumpPlacement::Embedded => writeln!(out, "placement = embedded in the journal region"),
+        LumpPlacement::Data {
+            start_block,
+            blocks,
+        } => writeln!(
+            out,
+            "placement = data region (start block = {}, blocks = {})",
+            start_block, blocks
+        ),
+        LumpPlacement::Unknown => writeln!(out, "placement = unknown (not found in the journal)"),
+    }
+}
+
+// データ領域とジャーナル領域の使用状況
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StorageUsage {
+    pub data_region_size: u64,
+    pub allocated_bytes: u64,
+    pub lumps: u64,
+    // ジャーナル領域に埋め込まれていてデータ領域を使わないlumpの数
+    pub embedded_lumps: u64,
+    pub journal_region_size: u64,
+    pub journal_used_bytes: u64,
+    pub largest_free_extent: u64,
+}
+impl StorageUsage {
+    pub fn free_bytes(&self) -> u64 {
+        self.data_region_size.saturating_sub(self.allocated_bytes)
+    }
+
+    pub fn journal_free_bytes(&self) -> u64 {
+        self.journal_region_size
+            .saturating_sub(self.journal_used_bytes)
+    }
+
+    // 空き領域のうち、最大の連続した空き領域に含まれない割合 (0なら断片化していない)
+    pub fn fragmentation(&self) -> f64 {
+        let free = self.free_bytes();
+        if free == 0 {
+            0.0
         } else {
-            println!("no entry for the key {:?}", key);
+            1.0 - self.largest_free_extent as f64 / free as f64
         }
     }
+}
 
-    pub fn delete_key(&mut self, key: u128) -> Result<bool, cannyls::Error> {
-        let lump_id = LumpId::new(key);
-        self.storage.delete(&lump_id)
+// データ領域上の使用中の区間 (ブロック単位)
+// 隣接したり重なったりする区間はまとめて保持するため、lumpの数ではなく連続した使用中の区間の数に比例した大きさになる
+#[derive(Debug, Default)]
+struct AllocatedExtents {
+    // 開始ブロック => 終了ブロック (終了は含まない)
+    runs: BTreeMap<u64, u64>,
+}
+impl AllocatedExtents {
+    fn insert(&mut self, mut start: u64, mut end: u64) {
+        if let Some((&s, &e)) = self.runs.range(..=start).next_back() {
+            if e >= start {
+                start = s;
+            }
+        }
+        let absorbed = self
+            .runs
+            .range(start..=end)
+            .map(|(&s, &e)| (s, e))
+            .collect::<Vec<_>>();
+        for (s, e) in absorbed {
+            self.runs.remove(&s);
+            end = end.max(e);
+        }
+        self.runs.insert(start, end);
     }
-    pub fn delete(&mut self, key: u128) {
-        let result = track_try_unwrap!(self.delete_key(key));
-        println!("delete result => {:?}", result);
+
+    // `total_blocks`ブロックのデータ領域のうち、最大の連続した空き区間のブロック数
+    fn largest_free(&self, total_blocks: u64) -> u64 {
+        let mut largest = 0;
+        let mut position = 0;
+        for (&start, &end) in self.runs.iter().chain(Some((&total_blocks, &total_blocks))) {
+            largest = largest.max(start.saturating_sub(position));
+            position = position.max(end);
+        }
+        largest
     }
+}
 
-    pub fn journal_info(&mut self) -> Result<JournalSnapshot, cannyls::Error> {
-        self.storage.journal_snapshot()
+// ジャーナル領域のリングバッファ上の各位置
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct JournalPositions {
+    pub unreleased_head: u64,
+    pub head: u64,
+    pub tail: u64,
+}
+impl JournalPositions {
+    pub fn new(snapshot: &JournalSnapshot) -> Self {
+        JournalPositions {
+            unreleased_head: snapshot.unreleased_head,
+            head: snapshot.head,
+            tail: snapshot.tail,
+        }
     }
 
-    pub fn print_journal_info(&mut self) {
-        let snapshot = track_try_unwrap!(self.journal_info());
+    // unreleased_headからtailまでの使用中のバイト数
+    pub fn used_bytes(&self, header: &StorageHeader) -> u64 {
+        ring_distance(journal_capacity(header), self.unreleased_head, self.tail)
+    }
+}
 
-        println!(
-            "journal [unreleased head] position = {}",
-            snapshot.unreleased_head
-        );
-        println!("journal [head] position = {}", snapshot.head);
-        println!("journal [tail] position = {}", snapshot.tail);
+// ジャーナル領域の先頭1ブロックはヘッダ、残りがリングバッファとして使われる
+fn journal_capacity(header: &StorageHeader) -> u64 {
+    header.journal_region_size - u64::from(header.block_size.as_u16())
+}
 
-        if snapshot.entries.is_empty() {
-            println!("there are no journal entries");
-        } else {
-            println!("<journal entries>");
-            for e in snapshot.entries {
-                println!("{:?}", e);
+// リングバッファ上で`from`から`to`まで進むバイト数
+fn ring_distance(capacity: u64, from: u64, to: u64) -> u64 {
+    if to >= from {
+        to - from
+    } else {
+        capacity - from + to
+    }
+}
+
+// ジャーナル領域のリングバッファの使用状況と、レコードの種類ごとのエントリ数
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct JournalStats {
+    pub positions: JournalPositions,
+    pub capacity: u64,
+    // headからtailまで
+    pub occupied_bytes: u64,
+    // unreleased_headからheadまで (GCで不要になったが、まだ解放されていない部分)
+    pub pending_release_bytes: u64,
+    pub puts: u64,
+    pub embeds: u64,
+    pub deletes: u64,
+    pub delete_ranges: u64,
+    // EndOfRecords, GoToFront
+    pub others: u64,
+}
+impl JournalStats {
+    pub fn from_journal(header: &StorageHeader, snapshot: &JournalSnapshot) -> Self {
+        let capacity = journal_capacity(header);
+        let mut stats = JournalStats {
+            positions: JournalPositions::new(snapshot),
+            capacity,
+            occupied_bytes: ring_distance(capacity, snapshot.head, snapshot.tail),
+            pending_release_bytes: ring_distance(capacity, snapshot.unreleased_head, snapshot.head),
+            ..JournalStats::default()
+        };
+        for entry in &snapshot.entries {
+            match entry.record {
+                JournalRecord::Put(..) => stats.puts += 1,
+                JournalRecord::Embed(..) => stats.embeds += 1,
+                JournalRecord::Delete(_) => stats.deletes += 1,
+                JournalRecord::DeleteRange(_) => stats.delete_ranges += 1,
+                JournalRecord::EndOfRecords | JournalRecord::GoToFront => stats.others += 1,
             }
-            println!("</journal entries>");
         }
+        stats
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.puts + self.embeds + self.deletes + self.delete_ranges + self.others
     }
 
-    pub fn journal_gc(&mut self) {
-        println!("run journal full GC ...");
-        track_try_unwrap!(self.storage.journal_sync());
-        let result = self.storage.journal_gc();
-        if let Err(error) = result {
-            panic!("journal_gc failed with the error {:?}", error);
+    // リングバッファのうち、unreleased_headからtailまでが占める割合
+    pub fn utilization(&self) -> f64 {
+        if self.capacity == 0 {
+            0.0
         } else {
-            println!("journal full GC succeeded!");
+            (self.occupied_bytes + self.pending_release_bytes) as f64 / self.capacity as f64
         }
     }
+}
+
+// ジャーナルGCの前後の位置、使用量とエントリ数
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct JournalGcReport {
+    pub before: JournalPositions,
+    pub after: JournalPositions,
+    pub used_before: u64,
+    pub used_after: u64,
+    pub entries_before: u64,
+    pub entries_after: u64,
+    // リングバッファの容量
+    pub capacity: u64,
+}
+impl JournalGcReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.used_before.saturating_sub(self.used_after)
+    }
 
-    pub fn all_keys(&mut self) -> Vec<LumpId> {
-        self.storage.list()
+    pub fn released_entries(&self) -> u64 {
+        self.entries_before.saturating_sub(self.entries_after)
     }
 
-    pub fn print_list_of_lumpids(&mut self) {
-        let ids = self.storage.list();
-        if ids.is_empty() {
-            println!("there are no lumps");
+    // GCによってheadが進んだバイト数
+    pub fn head_advanced(&self) -> u64 {
+        ring_distance(self.capacity, self.before.head, self.after.head)
+    }
+
+    // GC後のリングバッファの使用率
+    pub fn utilization(&self) -> f64 {
+        if self.capacity == 0 {
+            0.0
         } else {
-            println!("<lumpid list>");
-            for lumpid in ids {
-                println!("{:?}", lumpid);
+            self.used_after as f64 / self.capacity as f64
+        }
+    }
+}
+impl fmt::Display for JournalGcReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(label, p, used) in &[
+            ("before", self.before, self.used_before),
+            ("after", self.after, self.used_after),
+        ] {
+            writeln!(
+                f,
+                "{}: unreleased head = {}, head = {}, tail = {} (used {} bytes)",
+                label, p.unreleased_head, p.head, p.tail, used
+            )?;
+        }
+        writeln!(
+            f,
+            "released {} entries, head advanced {} bytes",
+            self.released_entries(),
+            self.head_advanced()
+        )?;
+        write!(
+            f,
+            "reclaimed {} bytes, utilization = {:.2}%",
+            self.reclaimed_bytes(),
+            self.utilization() * 100.0
+        )
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+// `1536 (1.5 KiB, 50.00%)` のように、バイト数に読みやすい形式と割合を添える
+fn format_bytes_with_percent(part: u64, whole: u64) -> String {
+    if part < 1024 {
+        format!("{} ({:.2}%)", part, percent(part, whole))
+    } else {
+        format!(
+            "{} ({}, {:.2}%)",
+            part,
+            format_size(part),
+            percent(part, whole)
+        )
+    }
+}
+
+pub fn write_storage_usage<W: Write>(usage: &StorageUsage, mut out: W) -> io::Result<()> {
+    let data = usage.data_region_size;
+    writeln!(out, "data region size    = {}", format_bytes(data))?;
+    writeln!(
+        out,
+        "allocated bytes     = {}",
+        format_bytes_with_percent(usage.allocated_bytes, data)
+    )?;
+    writeln!(
+        out,
+        "free bytes          = {}",
+        format_bytes_with_percent(usage.free_bytes(), data)
+    )?;
+    writeln!(
+        out,
+        "largest free extent = {}",
+        format_bytes(usage.largest_free_extent)
+    )?;
+    writeln!(
+        out,
+        "fragmentation       = {:.2}%",
+        usage.fragmentation() * 100.0
+    )?;
+    writeln!(
+        out,
+        "lumps               = {} (embedded: {})",
+        usage.lumps, usage.embedded_lumps
+    )?;
+    let journal = usage.journal_region_size;
+    writeln!(out, "journal region size = {}", format_bytes(journal))?;
+    writeln!(
+        out,
+        "journal used bytes  = {}",
+        format_bytes_with_percent(usage.journal_used_bytes, journal)
+    )?;
+    writeln!(
+        out,
+        "journal free bytes  = {}",
+        format_bytes_with_percent(usage.journal_free_bytes(), journal)
+    )
+}
+
+pub fn write_journal_stats<W: Write>(stats: &JournalStats, mut out: W) -> io::Result<()> {
+    let p = stats.positions;
+    writeln!(out, "unreleased head       = {}", p.unreleased_head)?;
+    writeln!(out, "head                  = {}", p.head)?;
+    writeln!(out, "tail                  = {}", p.tail)?;
+    writeln!(
+        out,
+        "journal capacity      = {}",
+        format_bytes(stats.capacity)
+    )?;
+    writeln!(
+        out,
+        "occupied bytes        = {}",
+        format_bytes(stats.occupied_bytes)
+    )?;
+    writeln!(
+        out,
+        "pending release bytes = {}",
+        format_bytes(stats.pending_release_bytes)
+    )?;
+    writeln!(
+        out,
+        "utilization           = {:.2}%",
+        stats.utilization() * 100.0
+    )?;
+    writeln!(out, "entries               = {}", stats.entries())?;
+    writeln!(out, "  put                 = {}", stats.puts)?;
+    writeln!(out, "  embed               = {}", stats.embeds)?;
+    writeln!(out, "  delete              = {}", stats.deletes)?;
+    writeln!(out, "  delete range        = {}", stats.delete_ranges)?;
+    writeln!(out, "  other               = {}", stats.others)
+}
+
+// Header で出力するヘッダ情報 (テキストとJSONで共通)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HeaderInfo {
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub block_size: u16,
+    pub uuid: String,
+    pub journal_region_size: u64,
+    pub journal_header_size: u64,
+    pub journal_record_size: u64,
+    pub data_region_size: u64,
+    // ストレージヘッダ(ヘッダ領域)の大きさと、ストレージ全体の大きさ
+    // cannylsの`StorageHeader::region_size`, `StorageHeader::storage_size`に合わせた名前にする
+    pub region_size: u64,
+    pub storage_size: u64,
+}
+impl HeaderInfo {
+    pub fn new(header: &StorageHeader) -> Self {
+        let block_size = u64::from(header.block_size.as_u16());
+        HeaderInfo {
+            major_version: header.major_version,
+            minor_version: header.minor_version,
+            block_size: header.block_size.as_u16(),
+            uuid: header.instance_uuid.to_string(),
+            journal_region_size: header.journal_region_size,
+            journal_header_size: block_size,
+            journal_record_size: header.journal_region_size - block_size,
+            data_region_size: header.data_region_size,
+            region_size: header.region_size(),
+            storage_size: header.storage_size(),
+        }
+    }
+}
+
+pub fn write_header_info<W: Write>(header: &StorageHeader, mut out: W) -> io::Result<()> {
+    let info = HeaderInfo::new(header);
+    writeln!(out, "header =>")?;
+    writeln!(out, "  major version = {}", info.major_version)?;
+    writeln!(out, "  minor version = {}", info.minor_version)?;
+    writeln!(
+        out,
+        "  block size = {}",
+        format_bytes(u64::from(info.block_size))
+    )?;
+    writeln!(out, "  uuid = {}", info.uuid)?;
+    writeln!(
+        out,
+        "  journal region size = {}",
+        format_bytes(info.journal_region_size)
+    )?;
+    writeln!(
+        out,
+        "    journal header size = {}",
+        format_bytes(info.journal_header_size)
+    )?;
+    writeln!(
+        out,
+        "    journal record size = {}",
+        format_bytes(info.journal_record_size)
+    )?;
+    writeln!(
+        out,
+        "  data region size = {}",
+        format_bytes(info.data_region_size)
+    )?;
+    writeln!(
+        out,
+        "  storage header size => {}",
+        format_bytes(info.region_size)
+    )?;
+    writeln!(
+        out,
+        "  storage total size = {}",
+        format_bytes(info.storage_size)
+    )
+}
+
+pub fn write_header_json<W: Write>(header: &StorageHeader, mut out: W) -> io::Result<()> {
+    serde_json::to_writer(&mut out, &HeaderInfo::new(header))?;
+    writeln!(out)
+}
+
+// Info で出力する、ヘッダとlumpの数、データ領域とジャーナル領域の使用状況をまとめたもの
+pub fn write_storage_info<W: Write>(
+    header: &StorageHeader,
+    usage: &StorageUsage,
+    journal: &JournalStats,
+    json: bool,
+    mut out: W,
+) -> io::Result<()> {
+    if json {
+        let json = json!({
+            "header": HeaderInfo::new(header),
+            "lumps": usage.lumps,
+            "embedded_lumps": usage.embedded_lumps,
+            "data_region_size": usage.data_region_size,
+            "allocated_bytes": usage.allocated_bytes,
+            "journal_capacity": journal.capacity,
+            "journal_used_bytes": usage.journal_used_bytes,
+            "journal_utilization": journal.utilization(),
+        });
+        serde_json::to_writer(&mut out, &json)?;
+        return writeln!(out);
+    }
+    write_header_info(header, &mut out)?;
+    writeln!(
+        out,
+        "lumps = {} (embedded: {})",
+        usage.lumps, usage.embedded_lumps
+    )?;
+    writeln!(
+        out,
+        "data allocated bytes = {} / {} ({:.2}%)",
+        format_bytes(usage.allocated_bytes),
+        format_bytes(usage.data_region_size),
+        percent(usage.allocated_bytes, usage.data_region_size)
+    )?;
+    writeln!(
+        out,
+        "journal used bytes = {} / {} ({:.2}%)",
+        format_bytes(usage.journal_used_bytes),
+        format_bytes(journal.capacity),
+        journal.utilization() * 100.0
+    )
+}
+
+// `quiet`の場合はエントリのみを出力する (各位置や `<journal entries>` などの囲みを省く)
+pub fn write_journal_info<W: Write>(
+    snapshot: &JournalSnapshot,
+    quiet: bool,
+    mut out: W,
+) -> io::Result<()> {
+    if quiet {
+        for e in &snapshot.entries {
+            writeln!(out, "{:?}", e)?;
+        }
+        return Ok(());
+    }
+
+    writeln!(
+        out,
+        "journal [unreleased head] position = {}",
+        snapshot.unreleased_head
+    )?;
+    writeln!(out, "journal [head] position = {}", snapshot.head)?;
+    writeln!(out, "journal [tail] position = {}", snapshot.tail)?;
+
+    if snapshot.entries.is_empty() {
+        writeln!(out, "there are no journal entries")?;
+    } else {
+        writeln!(out, "<journal entries>")?;
+        for e in &snapshot.entries {
+            writeln!(out, "{:?}", e)?;
+        }
+        writeln!(out, "</journal entries>")?;
+    }
+    Ok(())
+}
+
+// ジャーナルのエントリが`key`のlumpを対象とするかどうか (DeleteRangeの場合は範囲に含むかどうか)
+fn journal_entry_refers_to(entry: &JournalEntry, key: u128) -> bool {
+    let id = LumpId::new(key);
+    match entry.record {
+        JournalRecord::Put(i, _) | JournalRecord::Embed(i, _) | JournalRecord::Delete(i) => i == id,
+        JournalRecord::DeleteRange(ref range) => range.start <= id && id < range.end,
+        JournalRecord::EndOfRecords | JournalRecord::GoToFront => false,
+    }
+}
+
+// ジャーナルのエントリの種類 (Journal の --entry-type)
+// Delete は DeleteRange も含む
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalEntryType {
+    Put,
+    Embed,
+    Delete,
+}
+impl JournalEntryType {
+    fn matches(self, entry: &JournalEntry) -> bool {
+        match (self, &entry.record) {
+            (JournalEntryType::Put, &JournalRecord::Put(..))
+            | (JournalEntryType::Embed, &JournalRecord::Embed(..))
+            | (JournalEntryType::Delete, &JournalRecord::Delete(_))
+            | (JournalEntryType::Delete, &JournalRecord::DeleteRange(_)) => true,
+            _ => false,
+        }
+    }
+}
+impl FromStr for JournalEntryType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "put" => Ok(JournalEntryType::Put),
+            "embed" => Ok(JournalEntryType::Embed),
+            "delete" => Ok(JournalEntryType::Delete),
+            _ => Err(format!(
+                "`{}` is an invalid entry type (put, embed or delete)",
+                s
+            )),
+        }
+    }
+}
+
+// 出力するジャーナルのエントリの条件
+// 全ての条件を満たすエントリを先頭から最大`limit`件残す
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct JournalFilter {
+    pub key: Option<u128>,
+    pub entry_type: Option<JournalEntryType>,
+    pub limit: Option<usize>,
+}
+impl JournalFilter {
+    pub fn apply(&self, snapshot: &mut JournalSnapshot) {
+        if let Some(key) = self.key {
+            snapshot.entries.retain(|e| journal_entry_refers_to(e, key));
+        }
+        if let Some(entry_type) = self.entry_type {
+            snapshot.entries.retain(|e| entry_type.matches(e));
+        }
+        if let Some(limit) = self.limit {
+            snapshot.entries.truncate(limit);
+        }
+    }
+}
+
+// positionはジャーナル領域上の位置、Putのdata_start, data_blocksはデータ領域上の位置とブロック数
+fn journal_entry_to_json(entry: &JournalEntry) -> serde_json::Value {
+    let position = entry.start.as_u64();
+    let lump_id = |id: LumpId| id.as_u128().to_string();
+    match entry.record {
+        JournalRecord::Put(id, ref portion) => json!({
+            "record": "put",
+            "position": position,
+            "lump_id": lump_id(id),
+            "data_start": portion.start.as_u64(),
+            "data_blocks": portion.len,
+        }),
+        JournalRecord::Embed(id, ref data) => json!({
+            "record": "embed",
+            "position": position,
+            "lump_id": lump_id(id),
+            "length": data.len(),
+        }),
+        JournalRecord::Delete(id) => json!({
+            "record": "delete",
+            "position": position,
+            "lump_id": lump_id(id),
+        }),
+        JournalRecord::DeleteRange(ref range) => json!({
+            "record": "delete_range",
+            "position": position,
+            "start": lump_id(range.start),
+            "end": lump_id(range.end),
+        }),
+        JournalRecord::EndOfRecords => json!({"record": "end_of_records", "position": position}),
+        JournalRecord::GoToFront => json!({"record": "go_to_front", "position": position}),
+    }
+}
+
+// 1行目に各位置を、続けて1エントリを1行ずつJSONで出力する
+pub fn write_journal_json<W: Write>(snapshot: &JournalSnapshot, mut out: W) -> io::Result<()> {
+    let header = json!({
+        "unreleased_head": snapshot.unreleased_head,
+        "head": snapshot.head,
+        "tail": snapshot.tail,
+    });
+    serde_json::to_writer(&mut out, &header)?;
+    writeln!(out)?;
+    for e in &snapshot.entries {
+        serde_json::to_writer(&mut out, &journal_entry_to_json(e))?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+// 2つのストレージの差分
+// 両方に存在するが値が異なるkeyは`differing`に入る
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StorageDiff {
+    pub only_in_self: Vec<LumpId>,
+    pub only_in_other: Vec<LumpId>,
+    pub differing: Vec<LumpId>,
+}
+impl StorageDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.differing.is_empty()
+    }
+}
+
+// 正しいストレージ(source)に合わせて壊れたストレージを修復するための計画
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RepairPlan {
+    pub copy: Vec<LumpId>,
+    pub overwrite: Vec<LumpId>,
+    pub delete: Vec<LumpId>,
+}
+impl RepairPlan {
+    pub fn is_empty(&self) -> bool {
+        self.copy.is_empty() && self.overwrite.is_empty() && self.delete.is_empty()
+    }
+}
+
+// Mergeで両方のストレージに同じkeyが存在した場合の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    // 書き込み先の値を残す
+    Skip,
+    // 書き込み元の値で上書きする
+    Overwrite,
+    // 何も書き込まずにエラーにする
+    Fail,
+}
+impl FromStr for ConflictPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "fail" | "error" => Ok(ConflictPolicy::Fail),
+            _ => Err(format!(
+                "`{}` is an invalid conflict policy (skip, overwrite, fail or error)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MergeSummary {
+    pub merged: u64,
+    pub skipped: u64,
+    pub overwritten: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VerifySummary {
+    pub ok: u64,
+    pub failed: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: u64,
+    // importedのうち、既に存在していたkeyを上書きした数
+    pub overwritten: u64,
+    pub skipped: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeleteSummary {
+    pub deleted: u64,
+    // 指定されたが存在しなかったkeyの数
+    pub absent: u64,
+}
+
+// `copy_to`でジャーナルを同期する間隔 (lump数)
+const COPY_SYNC_INTERVAL: u64 = 1024;
+
+// `Keys`が1回の`list_range`で取得するlumpid数の目安
+const ITER_CHUNK_SIZE: usize = 1024;
+
+// keyが[min, max]の範囲(両端を含む)に含まれるlumpidを昇順に返す
+fn list_between<N: NonVolatileMemory>(
+    storage: &mut Storage<N>,
+    min: u128,
+    max: u128,
+) -> Vec<LumpId> {
+    if min > max {
+        return Vec::new();
+    }
+    // [min, max + 1) として問い合わせる (max が u128::MAX の場合は別途確認する)
+    match max.checked_add(1) {
+        Some(end) => storage.list_range(LumpId::new(min)..LumpId::new(end)),
+        None => {
+            let mut ids = storage.list_range(LumpId::new(min)..LumpId::new(max));
+            if storage.head(&LumpId::new(max)).is_some() {
+                ids.push(LumpId::new(max));
             }
-            println!("</lumpid list>");
+            ids
         }
     }
+}
 
-    pub fn print_all_key_value_pairs(&mut self) {
-        let ids = self.storage.list();
-        if ids.is_empty() {
-            println!("there are no lumps");
-        } else {
-            let result = ids
-                .iter()
-                .map(|key| {
-                    (
-                        key,
-                        lumpdata_to_string(&self.storage.get(key).unwrap().unwrap()),
-                    )
-                }).collect::<Vec<_>>();
-            println!("<lump list>");
-            for lump in result {
-                println!("{:?}", lump);
+// keyの昇順(`KeyOrder::Desc`の場合は降順)にlumpidを返すイテレータ
+// `list_range`でkeyの範囲を区切って少しずつ取得し、全てのlumpidを一度にメモリに載せない
+// 取得した件数が多すぎれば次の範囲を狭め、少なければ広げる
+pub struct Keys<'a, N: 'a + NonVolatileMemory> {
+    storage: &'a mut Storage<N>,
+    // まだ問い合わせていない範囲[min, max] (Noneなら全て問い合わせ済み)
+    rest: Option<(u128, u128)>,
+    descending: bool,
+    width: u128,
+    buffer: vec::IntoIter<LumpId>,
+}
+impl<'a, N: NonVolatileMemory> Keys<'a, N> {
+    // 列挙中のストレージから値を読み込む
+    fn get(&mut self, id: &LumpId) -> Result<Option<LumpData>, cannyls::Error> {
+        track!(self.storage.get(id))
+    }
+
+    // 列挙中のストレージの索引を参照する
+    fn head(&mut self, id: &LumpId) -> Option<LumpHeader> {
+        self.storage.head(id)
+    }
+
+    fn new(storage: &'a mut Storage<N>, min: u128, max: u128, order: KeyOrder) -> Self {
+        Keys {
+            storage,
+            rest: if min <= max { Some((min, max)) } else { None },
+            descending: order == KeyOrder::Desc,
+            width: ITER_CHUNK_SIZE as u128,
+            buffer: Vec::new().into_iter(),
+        }
+    }
+}
+impl<'a, N: NonVolatileMemory> Iterator for Keys<'a, N> {
+    type Item = LumpId;
+    fn next(&mut self) -> Option<LumpId> {
+        loop {
+            if let Some(id) = self.buffer.next() {
+                return Some(id);
+            }
+            let (min, max) = self.rest?;
+            let ids = if self.descending {
+                let start = max.saturating_sub(self.width - 1).max(min);
+                self.rest = if start == min {
+                    None
+                } else {
+                    Some((min, start - 1))
+                };
+                let mut ids = list_between(self.storage, start, max);
+                ids.reverse();
+                ids
+            } else {
+                let end = min.saturating_add(self.width - 1).min(max);
+                self.rest = if end == max {
+                    None
+                } else {
+                    Some((end + 1, max))
+                };
+                list_between(self.storage, min, end)
+            };
+            if ids.len() > ITER_CHUNK_SIZE {
+                self.width = (self.width / 2).max(1);
+            } else if ids.len() < ITER_CHUNK_SIZE / 2 {
+                self.width = self.width.saturating_mul(2);
             }
-            println!("</lump list>");
+            self.buffer = ids.into_iter();
         }
     }
+}
 
-    pub fn print_header_info(&mut self) {
-        let header = self.storage.header();
-        println!("header =>");
-        println!("  major version = {}", header.major_version);
-        println!("  minor version = {}", header.minor_version);
-        let block_size_u64 = u64::from(header.block_size.as_u16());
-        println!("  block size = {}", block_size_u64);
-        println!("  uuid = {}", header.instance_uuid);
-        println!("  journal region size = {}", header.journal_region_size);
-        println!("    journal header size = {}", block_size_u64);
-        println!(
-            "    journal record size = {}",
-            header.journal_region_size - block_size_u64
-        );
-        println!("  data region size = {}", header.data_region_size);
-        println!("  storage header size => {}", header.region_size());
-        println!("  storage total size = {}", header.storage_size());
+// keyの昇順(`KeyOrder::Desc`の場合は降順)に(lumpid, 値)を返すイテレータ
+// 値は1件ずつ読み込む。一覧の取得後に削除されたlumpは返さない
+pub struct Entries<'a, N: 'a + NonVolatileMemory> {
+    keys: Keys<'a, N>,
+}
+impl<'a, N: NonVolatileMemory> Iterator for Entries<'a, N> {
+    type Item = Result<(LumpId, LumpData), cannyls::Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(id) = self.keys.next() {
+            match self.keys.get(&id) {
+                Ok(Some(data)) => return Some(Ok((id, data))),
+                Ok(None) => {}
+                Err(e) => return Some(Err(track!(e))),
+            }
+        }
+        None
+    }
+}
+impl<'a, N: NonVolatileMemory> Entries<'a, N> {
+    // 値を読み込まずに先頭からn件を読み飛ばす
+    pub fn skip_keys(mut self, n: usize) -> Self {
+        if n > 0 {
+            self.keys.nth(n - 1);
+        }
+        self
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use tempdir::TempDir;
-    use trackable::result::TestResult;
+pub struct StorageHandle<N: NonVolatileMemory = FileNvm> {
+    storage: Storage<N>,
+    read_only: bool,
+    scheme: Option<&'static dyn LumpIdScheme>,
+    key_format: KeyFormat,
+    quiet: bool,
+    // ジャーナル領域の使用率がこれを超えた場合に、auto_journal_gc でGCを行う
+    auto_gc_threshold: Option<f64>,
+}
 
-    use super::*;
-    use handle::StorageHandle;
+impl StorageHandle<FileNvm> {
+    // 既存のストレージを開く
+    // ファイルが存在しない場合やヘッダが壊れている場合はpanicせずにエラーを返す
+    pub fn create<T: AsRef<Path>>(path: T) -> Result<Self, cannyls::Error> {
+        let nvm = track!(FileNvm::open(path))?;
+        let storage = track!(StorageBuilder::new().open(nvm))?;
+        Ok(StorageHandle::new(storage))
+    }
+}
 
-    macro_rules! track_io {
-        ($expr:expr) => {
-            $expr.map_err(|e: ::std::io::Error| track!(cannyls::Error::from(e)))
-        };
+impl StorageHandle<ReadOnlyNvm> {
+    // 書き込み権限なしでストレージを開く
+    // put, delete, journal_gc などの変更操作はエラーになる
+    pub fn open_readonly<T: AsRef<Path>>(path: T) -> Result<Self, cannyls::Error> {
+        let nvm = track!(ReadOnlyNvm::open(path))?;
+        let storage = track!(StorageBuilder::new().open(nvm))?;
+        Ok(StorageHandle {
+            storage,
+            read_only: true,
+            scheme: None,
+            key_format: KeyFormat::Dec,
+            quiet: false,
+            auto_gc_threshold: None,
+        })
     }
+}
 
-    #[test]
-    fn overwrite_works() -> TestResult {
-        let dir = track_io!(TempDir::new("cannyls_test"))?;
-        let path = dir.path().join("test.lusf");
+impl<N: NonVolatileMemory> StorageHandle<N> {
+    pub fn new(storage: Storage<N>) -> Self {
+        StorageHandle {
+            storage,
+            read_only: false,
+            scheme: None,
+            key_format: KeyFormat::Dec,
+            quiet: false,
+            auto_gc_threshold: None,
+        }
+    }
 
-        let nvm = track_try_unwrap!(FileNvm::create(path, 4_000_000));
-        let storage = track_try_unwrap!(Storage::create(nvm));
-        let mut handle = StorageHandle::new(storage);
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 
-        assert!(handle.put_str(0, "hoge").is_ok());
-        assert!(handle.put_str(0, "bar").is_ok());
-        assert_eq!(handle.get_string(0)?.unwrap(), "bar".to_owned());
+    // 設定した場合、lumpidを出力する際にスキームに従って分解した構成要素も出力する
+    pub fn set_lumpid_scheme(&mut self, scheme: Option<&'static dyn LumpIdScheme>) {
+        self.scheme = scheme;
+    }
 
-        Ok(())
+    // keyを数値として出力する際の形式 (LumpId("...")形式の出力には影響しない)
+    pub fn set_key_format(&mut self, format: KeyFormat) {
+        self.key_format = format;
     }
 
-    #[test]
-    fn delete_works() -> TestResult {
-        let dir = track_io!(TempDir::new("cannyls_test"))?;
-        let path = dir.path().join("test.lusf");
+    // 設定した場合、一覧などを出力する際に `<lumpid list>` のような囲みや説明の表示を省き、データのみを出力する
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
 
-        let nvm = track_try_unwrap!(FileNvm::create(path, 4_000_000));
-        let storage = track_try_unwrap!(Storage::create(nvm));
-        let mut handle = StorageHandle::new(storage);
+    // 対話モードで、変更を伴うコマンドの後にジャーナルのGCを自動的に行う使用率 (None の場合は行わない)
+    pub fn set_auto_gc_threshold(&mut self, threshold: Option<f64>) {
+        self.auto_gc_threshold = threshold;
+    }
 
-        assert!(handle.put_str(0, "hoge").is_ok());
-        assert!(handle.delete_key(0)?, true);
+    pub fn key_format(&self) -> KeyFormat {
+        self.key_format
+    }
+
+    pub fn format_key(&self, key: u128) -> String {
+        format_key(key, self.key_format)
+    }
+
+    fn annotation(&self, key: &LumpId) -> String {
+        annotation(self.scheme, key)
+    }
+
+    fn ensure_writable(&self) -> Result<(), cannyls::Error> {
+        if self.read_only {
+            Err(track!(cannyls::Error::from(
+                ErrorKind::InvalidInput.cause("storage opened read-only")
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn put_str(&mut self, key: u128, value: &str) -> Result<bool, cannyls::Error> {
+        self.put_bytes(key, value.as_bytes())
+    }
+    // 値が LumpData::MAX_SIZE より大きい場合はエラーを返す
+    pub fn put_bytes(&mut self, key: u128, value: &[u8]) -> Result<bool, cannyls::Error> {
+        track!(self.ensure_writable())?;
+        if value.len() > LumpData::MAX_SIZE {
+            return Err(track!(cannyls::Error::from(ErrorKind::InvalidInput.cause(
+                format!(
+                    "{} bytes exceeds the lump size limit ({} bytes)",
+                    value.len(),
+                    LumpData::MAX_SIZE
+                )
+            ))));
+        }
+        let lump_id = LumpId::new(key);
+        let lump_data = track!(self.storage.allocate_lump_data_with_bytes(value))?;
+        track!(self.storage.put(&lump_id, &lump_data))
+    }
+    // keyの値の末尾にvalueを連結して書き戻し、連結後の値のバイト長を返す
+    // keyが存在しない場合はvalueを値とする新たなlumpを作成する
+    // 連結後の値はデータ領域に書き込む (元の値が埋め込まれていた場合も)
+    pub fn append(&mut self, key: u128, value: &[u8]) -> Result<usize, cannyls::Error> {
+        track!(self.ensure_writable())?;
+        let mut data = track!(self.get_bytes(key))?.unwrap_or_default();
+        data.extend_from_slice(value);
+        track!(self.put_bytes(key, &data))?;
+        Ok(data.len())
+    }
+
+    // 値をデータ領域ではなくジャーナル領域に埋め込んでputする
+    // 値が LumpData::MAX_EMBEDDED_SIZE より大きい場合はエラーを返す
+    pub fn put_embedded(&mut self, key: u128, value: &[u8]) -> Result<bool, cannyls::Error> {
+        track!(self.ensure_writable())?;
+        if value.len() > LumpData::MAX_EMBEDDED_SIZE {
+            return Err(track!(cannyls::Error::from(ErrorKind::InvalidInput.cause(
+                format!(
+                    "{} bytes exceeds the embedded size limit ({} bytes)",
+                    value.len(),
+                    LumpData::MAX_EMBEDDED_SIZE
+                )
+            ))));
+        }
+        let lump_data = track!(LumpData::new_embedded(value.to_vec()))?;
+        track!(self.storage.put(&LumpId::new(key), &lump_data))
+    }
+    pub fn get_string(&mut self, key: u128) -> Result<Option<String>, cannyls::Error> {
+        let lump_id = LumpId::new(key);
+        self.storage
+            .get(&lump_id)
+            .map(|s| s.map(|s| lumpdata_to_string(&s)))
+    }
+    pub fn get_bytes(&mut self, key: u128) -> Result<Option<Vec<u8>>, cannyls::Error> {
+        let lump_id = LumpId::new(key);
+        let data = track!(self.storage.get(&lump_id))?;
+        Ok(data.map(|data| data.as_bytes().to_vec()))
+    }
+    // 値が存在すれば出力して`Ok(true)`、存在しなければ`Ok(false)`を返す
+    // 読み出し時のエラーはpanicせずに呼び出し元へ返す
+    pub fn get(&mut self, key: u128) -> Result<bool, cannyls::Error> {
+        if let Some(string) = track!(self.get_string(key))? {
+            println!("get => {:?}{}", string, self.annotation(&LumpId::new(key)));
+            Ok(true)
+        } else {
+            println!("no entry for key {}", key);
+            Ok(false)
+        }
+    }
+
+    // ファイルの内容をそのまま値としてputする (UTF-8である必要はない)
+    pub fn put_file<P: AsRef<Path>>(&mut self, key: u128, path: P) -> Result<bool, cannyls::Error> {
+        track!(self.ensure_writable())?;
+        let value = track_io!(fs::read(path))?;
+        track!(self.put_bytes(key, &value))
+    }
+
+    // 値をそのままファイルに書き出し、書き出したバイト数を返す
+    // keyが存在しない場合はファイルを作らずに None を返す
+    pub fn get_to_file<P: AsRef<Path>>(
+        &mut self,
+        key: u128,
+        path: P,
+    ) -> Result<Option<usize>, cannyls::Error> {
+        match track!(self.get_bytes(key))? {
+            Some(value) => {
+                track_io!(fs::write(path, &value))?;
+                Ok(Some(value.len()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // 値を加工せずにそのまま`out`に書き出し、書き出したバイト数を返す
+    // keyが存在しない場合は何も書き出さずに None を返す
+    pub fn write_raw<W: Write>(
+        &mut self,
+        key: u128,
+        mut out: W,
+    ) -> Result<Option<usize>, cannyls::Error> {
+        match track!(self.get_bytes(key))? {
+            Some(value) => {
+                track_io!(out.write_all(&value))?;
+                track_io!(out.flush())?;
+                Ok(Some(value.len()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // 複数のkeyの値を、指定された順にまとめて取得する
+    // 存在しないkeyも結果に含め、None で表す
+    pub fn get_many(
+        &mut self,
+        keys: &[u128],
+    ) -> Result<Vec<(u128, Option<String>)>, cannyls::Error> {
+        let mut values = Vec::with_capacity(keys.len());
+        for &key in keys {
+            values.push((key, track!(self.get_string(key))?));
+        }
+        Ok(values)
+    }
+
+    // `get_many`の結果をkeyごとに1行ずつ出力し、存在しなかったkeyの数を返す
+    pub fn print_many(&mut self, keys: &[u128]) -> Result<usize, cannyls::Error> {
+        let mut absent = 0;
+        for (key, value) in track!(self.get_many(keys))? {
+            match value {
+                Some(string) => println!(
+                    "{} => {:?}{}",
+                    self.format_key(key),
+                    string,
+                    self.annotation(&LumpId::new(key))
+                ),
+                None => {
+                    println!("{} => no entry", self.format_key(key));
+                    absent += 1;
+                }
+            }
+        }
+        Ok(absent)
+    }
+
+    // 値そのものは読み込まずに、インデックスのみを参照してkeyの存在を確認する
+    pub fn contains(&mut self, key: u128) -> Result<bool, cannyls::Error> {
+        Ok(self.exists(key))
+    }
+
+    // インデックスのみを参照するため、値は読み込まない
+    pub fn exists(&mut self, key: u128) -> bool {
+        self.storage.head(&LumpId::new(key)).is_some()
+    }
+
+    // keyのlumpがジャーナル領域に埋め込まれているかどうかを調べる (`placement`を参照)
+    // 存在しない場合は None を、格納場所が分からない場合は false を返す
+    pub fn is_embedded(&mut self, key: u128) -> Result<Option<bool>, cannyls::Error> {
+        let placement = track!(self.placement(key))?;
+        Ok(placement.map(|p| p == LumpPlacement::Embedded))
+    }
+
+    // keyのlumpの格納場所を調べる
+    // `head`のサイズがブロックサイズの倍数でなければ埋め込まれたlumpなので、ジャーナルは読まない (`lump_size`を参照)
+    // それ以外はジャーナルを末尾から探し、最後に書き込まれた記録から位置を求める
+    // 存在しない場合は None を、ジャーナルに記録が見つからない場合は`LumpPlacement::Unknown`を返す
+    pub fn placement(&mut self, key: u128) -> Result<Option<LumpPlacement>, cannyls::Error> {
+        let id = LumpId::new(key);
+        let approximate = match self.storage.head(&id) {
+            None => return Ok(None),
+            Some(header) => header.approximate_data_size,
+        };
+        if approximate % u32::from(self.storage.header().block_size.as_u16()) != 0 {
+            return Ok(Some(LumpPlacement::Embedded));
+        }
+        let snapshot = track!(self.storage.journal_snapshot())?;
+        let placement = snapshot
+            .entries
+            .iter()
+            .rev()
+            .filter_map(|entry| match entry.record {
+                JournalRecord::Put(i, ref portion) if i == id => Some(LumpPlacement::Data {
+                    start_block: portion.start.as_u64(),
+                    blocks: u64::from(portion.len),
+                }),
+                JournalRecord::Embed(i, _) if i == id => Some(LumpPlacement::Embedded),
+                _ => None,
+            })
+            .next();
+        Ok(Some(placement.unwrap_or(LumpPlacement::Unknown)))
+    }
+
+    // 値のバイト長と格納場所を返す
+    pub fn stat(&mut self, key: u128) -> Result<Option<LumpStat>, cannyls::Error> {
+        let size = match track!(self.lump_size(key))? {
+            None => return Ok(None),
+            Some(size) => size,
+        };
+        let placement = track!(self.placement(key))?;
+        Ok(placement.map(|placement| LumpStat { size, placement }))
+    }
+
+    // 値のバイト長を返す
+    // データ領域のlumpについて`head`で得られるサイズはブロック境界に切り上げられた近似値だが、
+    // 埋め込まれたlumpのサイズは正確な長さになる。ブロックサイズの倍数でなければ埋め込まれたlumpなのでそのまま返し、
+    // そうでなければ値を読み込んで求める
+    pub fn lump_size(&mut self, key: u128) -> Result<Option<usize>, cannyls::Error> {
+        let lump_id = LumpId::new(key);
+        let approximate = match self.storage.head(&lump_id) {
+            None => return Ok(None),
+            Some(header) => header.approximate_data_size,
+        };
+        if approximate % u32::from(self.storage.header().block_size.as_u16()) != 0 {
+            return Ok(Some(approximate as usize));
+        }
+        let data = track!(self.storage.get(&lump_id))?;
+        Ok(data.map(|data| data.as_bytes().len()))
+    }
+
+    pub fn delete_key(&mut self, key: u128) -> Result<bool, cannyls::Error> {
+        track!(self.ensure_writable())?;
+        let lump_id = LumpId::new(key);
+        self.storage.delete(&lump_id)
+    }
+    // [start, end) の範囲に含まれるlumpを全て削除し、実際に削除された件数を返す
+    // ジャーナルの同期は最後に一度だけ行う
+    pub fn delete_range(&mut self, start: u128, end: u128) -> Result<u64, cannyls::Error> {
+        track!(self.ensure_writable())?;
+        let deleted = track!(self.delete_range_without_sync(start, end))?;
+        track!(self.storage.journal_sync())?;
+        Ok(deleted)
+    }
+
+    fn delete_range_without_sync(&mut self, start: u128, end: u128) -> Result<u64, cannyls::Error> {
+        if start >= end {
+            return Ok(0);
+        }
+        let range = LumpId::new(start)..LumpId::new(end);
+        let deleted = track!(self.storage.delete_range(range))?;
+        Ok(deleted.len() as u64)
+    }
+
+    // keyがstart以上のlumpを(u128::MAX を含めて)全て削除し、削除された件数を返す
+    // [start, end) の形式ではendを u128::MAX + 1 にできないため、最大のkeyは個別に削除する
+    pub fn delete_range_from(&mut self, start: u128) -> Result<u64, cannyls::Error> {
+        track!(self.ensure_writable())?;
+        let mut deleted = track!(self.delete_range_without_sync(start, u128::max_value()))?;
+        if track!(self.storage.delete(&LumpId::new(u128::max_value())))? {
+            deleted += 1;
+        }
+        track!(self.storage.journal_sync())?;
+        Ok(deleted)
+    }
+
+    // 指定された全てのkeyを削除し、最後に一度だけジャーナルを同期する
+    pub fn delete_keys<I>(&mut self, keys: I) -> Result<DeleteSummary, cannyls::Error>
+    where
+        I: IntoIterator<Item = u128>,
+    {
+        track!(self.ensure_writable())?;
+        let mut summary = DeleteSummary::default();
+        for key in keys {
+            if track!(self.storage.delete(&LumpId::new(key)))? {
+                summary.deleted += 1;
+            } else {
+                summary.absent += 1;
+            }
+        }
+        track!(self.storage.journal_sync())?;
+        Ok(summary)
+    }
+
+    // keyが[min, max]の範囲(両端を含む)に含まれるlumpを削除し、削除された件数を返す
+    // ジャーナルの同期は最後に一度だけ行う
+    pub fn delete_between(&mut self, min: u128, max: u128) -> Result<u64, cannyls::Error> {
+        track!(self.ensure_writable())?;
+        if min > max {
+            return Ok(0);
+        }
+        match max.checked_add(1) {
+            Some(end) => track!(self.delete_range(min, end)),
+            None => track!(self.delete_range_from(min)),
+        }
+    }
+
+    // 全てのlumpを削除してからジャーナルのGCを行い、削除した件数を返す
+    // 容量やジャーナル領域の比率はそのまま残る
+    pub fn clear(&mut self) -> Result<u64, cannyls::Error> {
+        let deleted = track!(self.delete_range_from(0))?;
+        track!(self.journal_gc_with_stats())?;
+        Ok(deleted)
+    }
+
+    // `key,value` 形式のCSVを読み込んで全ての行をputし、最後に一度だけジャーナルを同期する
+    // 不正な行は`on_skip`に行番号と理由を渡して読み飛ばす
+    // `strict`の場合は不正な行があった時点でエラーを返す (それ以前の行は書き込まれている)
+    pub fn import_csv<R, F>(
+        &mut self,
+        input: R,
+        strict: bool,
+        on_skip: F,
+    ) -> Result<ImportSummary, cannyls::Error>
+    where
+        R: BufRead,
+        F: FnMut(u64, &str),
+    {
+        self.put_records(csv::Reader::new(input), None, strict, on_skip)
+    }
+
+    // `key,value` の2つのフィールドからなるレコードを全てputする
+    // `sync_interval`を指定した場合はその件数ごとに、いずれにしても最後にジャーナルを同期する
+    // 不正なレコードの扱いは`import_csv`と同じ
+    pub fn put_records<I, F>(
+        &mut self,
+        records: I,
+        sync_interval: Option<u64>,
+        strict: bool,
+        mut on_skip: F,
+    ) -> Result<ImportSummary, cannyls::Error>
+    where
+        I: Iterator<Item = io::Result<csv::Record>>,
+        F: FnMut(u64, &str),
+    {
+        track!(self.ensure_writable())?;
+        let mut summary = ImportSummary::default();
+        for record in records {
+            let record = track_io!(record)?;
+            if csv::is_header(&record) {
+                continue;
+            }
+            match csv::parse_key_value(&record) {
+                Ok((key, value)) => {
+                    if !track!(self.put_bytes(key, &value))? {
+                        summary.overwritten += 1;
+                    }
+                    summary.imported += 1;
+                    if let Some(n) = sync_interval {
+                        if n > 0 && summary.imported % n == 0 {
+                            track!(self.storage.journal_sync())?;
+                        }
+                    }
+                }
+                Err(e) => {
+                    if strict {
+                        track!(self.storage.journal_sync())?;
+                        return Err(track!(cannyls::Error::from(
+                            ErrorKind::InvalidInput.cause(format!("line {}: {}", record.line, e))
+                        )));
+                    }
+                    on_skip(record.line, &e);
+                    summary.skipped += 1;
+                }
+            }
+        }
+        track!(self.storage.journal_sync())?;
+        Ok(summary)
+    }
+
+    // 全てのlumpを読み込み、読み込めなかったlumpを`on_failure`にidと理由を渡して報告する
+    // cannyls はlumpごとのチェックサムを公開していないため、エラーなく読み込めるかどうかのみを検査する
+    pub fn verify<F: FnMut(LumpId, &str)>(&mut self, mut on_failure: F) -> VerifySummary {
+        let mut summary = VerifySummary::default();
+        let mut keys = self.iter_keys();
+        while let Some(id) = keys.next() {
+            match keys.get(&id) {
+                Ok(Some(_)) => summary.ok += 1,
+                Ok(None) => {
+                    on_failure(id, "listed but not found");
+                    summary.failed += 1;
+                }
+                Err(e) => {
+                    on_failure(id, &e.to_string());
+                    summary.failed += 1;
+                }
+            }
+        }
+        summary
+    }
+
+    // 全てのlumpを`import_csv`で読み込める形式で書き出し、書き出した件数を返す
+    // 値は1件ずつ読み出して書き出す (全件をメモリに載せない)
+    pub fn export_csv<W: Write>(&mut self, mut out: W) -> Result<u64, cannyls::Error> {
+        track_io!(csv::write_record(&mut out, &["key", "value"]))?;
+        let mut exported = 0;
+        for entry in self.iter_entries() {
+            let (key, data) = track!(entry)?;
+            let key = key.as_u128().to_string();
+            let value = csv::encode_value(data.as_bytes());
+            track_io!(csv::write_record(&mut out, &[&key, &value]))?;
+            exported += 1;
+        }
+        track_io!(out.flush())?;
+        Ok(exported)
+    }
+
+    pub fn journal_sync(&mut self) -> Result<(), cannyls::Error> {
+        track!(self.storage.journal_sync())
+    }
+
+    // `key`を指定した場合は、そのlumpを対象とするエントリのみを返す
+    pub fn journal_info(&mut self, key: Option<u128>) -> Result<JournalSnapshot, cannyls::Error> {
+        let filter = JournalFilter {
+            key,
+            ..JournalFilter::default()
+        };
+        track!(self.filtered_journal_info(&filter))
+    }
+
+    pub fn filtered_journal_info(
+        &mut self,
+        filter: &JournalFilter,
+    ) -> Result<JournalSnapshot, cannyls::Error> {
+        let mut snapshot = track!(self.storage.journal_snapshot())?;
+        filter.apply(&mut snapshot);
+        Ok(snapshot)
+    }
+
+    pub fn print_journal_info(
+        &mut self,
+        filter: &JournalFilter,
+        json: bool,
+    ) -> Result<(), cannyls::Error> {
+        let snapshot = track!(self.filtered_journal_info(filter))?;
+        let stdout = io::stdout();
+        let _ = if json {
+            write_journal_json(&snapshot, stdout.lock())
+        } else {
+            write_journal_info(&snapshot, self.quiet, stdout.lock())
+        };
+        Ok(())
+    }
+
+    // ジャーナルに書き込んだ内容をディスクに同期する
+    pub fn try_journal_sync(&mut self) -> Result<(), cannyls::Error> {
+        track!(self.ensure_writable())?;
+        track!(self.storage.journal_sync())
+    }
+
+    // GCの前後のジャーナルのスナップショットを比べ、解放されたバイト数やエントリ数を求める
+    pub fn journal_gc_with_stats(&mut self) -> Result<JournalGcReport, cannyls::Error> {
+        track!(self.ensure_writable())?;
+        track!(self.storage.journal_sync())?;
+        let before = track!(self.storage.journal_snapshot())?;
+        track!(self.storage.journal_gc())?;
+        let after = track!(self.storage.journal_snapshot())?;
+        let header = self.storage.header();
+        let (before_positions, after_positions) = (
+            JournalPositions::new(&before),
+            JournalPositions::new(&after),
+        );
+        Ok(JournalGcReport {
+            before: before_positions,
+            after: after_positions,
+            used_before: before_positions.used_bytes(header),
+            used_after: after_positions.used_bytes(header),
+            entries_before: before.entries.len() as u64,
+            entries_after: after.entries.len() as u64,
+            capacity: journal_capacity(header),
+        })
+    }
+
+    // ジャーナル領域の使用率が閾値を超えていればGCを行い、GC前の使用率と結果を返す
+    pub fn auto_journal_gc(&mut self) -> Result<Option<(f64, JournalGcReport)>, cannyls::Error> {
+        let threshold = match self.auto_gc_threshold {
+            Some(threshold) if !self.read_only => threshold,
+            _ => return Ok(None),
+        };
+        let positions = JournalPositions::new(&track!(self.storage.journal_snapshot())?);
+        let header = self.storage.header();
+        let ratio = positions.used_bytes(header) as f64 / header.journal_region_size as f64;
+        if ratio <= threshold {
+            return Ok(None);
+        }
+        let report = track!(self.journal_gc_with_stats())?;
+        Ok(Some((ratio, report)))
+    }
+
+    pub fn all_keys(&mut self) -> Vec<LumpId> {
+        self.storage.list()
+    }
+
+    pub fn iter_keys(&mut self) -> Keys<'_, N> {
+        self.iter_keys_between(0, u128::max_value())
+    }
+
+    // keyが[min, max]の範囲(両端を含む)に含まれるlumpidを昇順に返す
+    pub fn iter_keys_between(&mut self, min: u128, max: u128) -> Keys<'_, N> {
+        self.iter_keys_ordered(min, max, KeyOrder::Asc)
+    }
+
+    pub fn iter_keys_ordered(&mut self, min: u128, max: u128, order: KeyOrder) -> Keys<'_, N> {
+        Keys::new(&mut self.storage, min, max, order)
+    }
+
+    pub fn iter_entries(&mut self) -> Entries<'_, N> {
+        self.iter_entries_between(0, u128::max_value())
+    }
+
+    pub fn iter_entries_between(&mut self, min: u128, max: u128) -> Entries<'_, N> {
+        self.iter_entries_ordered(min, max, KeyOrder::Asc)
+    }
+
+    pub fn iter_entries_ordered(
+        &mut self,
+        min: u128,
+        max: u128,
+        order: KeyOrder,
+    ) -> Entries<'_, N> {
+        Entries {
+            keys: self.iter_keys_ordered(min, max, order),
+        }
+    }
+
+    pub fn count(&mut self) -> usize {
+        self.iter_keys().count()
+    }
+
+    // 値は1件ずつ読み込んで長さのみを集計する (全件をメモリに載せない)
+    pub fn lump_stats(&mut self) -> Result<LumpStats, cannyls::Error> {
+        let mut stats = LumpStats::default();
+        for entry in self.iter_entries() {
+            let (_, data) = track!(entry)?;
+            stats.count += 1;
+            stats.total_size += data.as_bytes().len() as u64;
+        }
+        Ok(stats)
+    }
+
+    // keyが[start, end)の範囲に含まれるlumpidを昇順に返す
+    pub fn list_range(&mut self, start: u128, end: u128) -> Vec<LumpId> {
+        if start >= end {
+            return Vec::new();
+        }
+        self.storage
+            .list_range(LumpId::new(start)..LumpId::new(end))
+    }
+
+    // keyが[min, max]の範囲(両端を含む)に含まれるlumpidを昇順に返す
+    pub fn list_between(&mut self, min: u128, max: u128) -> Vec<LumpId> {
+        list_between(&mut self.storage, min, max)
+    }
+
+    pub fn print_list_of_lumpids(&mut self, format: ListFormat) {
+        self.print_keys_between(0, u128::max_value(), KeyOrder::Asc, 0, None, format);
+    }
+
+    // keyが[min, max]の範囲のlumpidを、`order`の順に取得しながら
+    // 先頭の`offset`件を読み飛ばして最大`limit`件まで出力する
+    pub fn print_keys_between(
+        &mut self,
+        min: u128,
+        max: u128,
+        order: KeyOrder,
+        offset: usize,
+        limit: Option<usize>,
+        format: ListFormat,
+    ) {
+        let (scheme, quiet) = (self.scheme, self.quiet);
+        let ids = self
+            .iter_keys_ordered(min, max, order)
+            .skip(offset)
+            .take(limit.unwrap_or(usize::max_value()));
+        let stdout = io::stdout();
+        let _ = write_annotated_lumpids(ids, scheme, format, quiet, stdout.lock());
+    }
+
+    // text形式の場合のみ、スキームに従った注釈を付けて出力する
+    pub fn print_lumpids(&self, ids: &[LumpId], format: ListFormat) {
+        let stdout = io::stdout();
+        let _ = write_annotated_lumpids(ids, self.scheme, format, self.quiet, stdout.lock());
+    }
+
+    // lumpid一覧を値のバイト長とともに出力する
+    pub fn print_list_with_sizes(&mut self, format: ListFormat) -> Result<(), cannyls::Error> {
+        self.print_sizes_between(0, u128::max_value(), KeyOrder::Asc, 0, None, format)
+    }
+
+    // `print_keys_between`と同じ範囲のlumpidを、値を1件ずつ読み込みながらバイト長とともに出力する
+    pub fn print_sizes_between(
+        &mut self,
+        min: u128,
+        max: u128,
+        order: KeyOrder,
+        offset: usize,
+        limit: Option<usize>,
+        format: ListFormat,
+    ) -> Result<(), cannyls::Error> {
+        let quiet = self.quiet;
+        let mut error = None;
+        let sizes = self
+            .iter_entries_ordered(min, max, order)
+            .skip_keys(offset)
+            .take(limit.unwrap_or(usize::max_value()))
+            .scan(&mut error, |error, entry| match entry {
+                Ok((id, data)) => Some((id, data.as_bytes().len())),
+                Err(e) => {
+                    **error = Some(e);
+                    None
+                }
+            });
+        let stdout = io::stdout();
+        let _ = write_lumpids_with_sizes(sizes, format, quiet, stdout.lock());
+        match error {
+            Some(e) => Err(track!(e)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn print_range_of_lumpids(&mut self, start: u128, end: u128, format: ListFormat) {
+        let ids = self.list_range(start, end);
+        self.print_lumpids(&ids, format);
+    }
+
+    pub fn print_all_key_value_pairs(&mut self) -> Result<(), cannyls::Error> {
+        self.print_entries_between(0, u128::max_value(), KeyOrder::Asc, 0, None)
+    }
+
+    // keyが[min, max]の範囲のKey-Valueペアを、1件ずつ読み出しながら
+    // 先頭の`offset`件を読み飛ばして最大`limit`件まで出力する
+    pub fn print_entries_between(
+        &mut self,
+        min: u128,
+        max: u128,
+        order: KeyOrder,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<(), cannyls::Error> {
+        let (scheme, quiet) = (self.scheme, self.quiet);
+        let entries = self
+            .iter_entries_ordered(min, max, order)
+            .skip_keys(offset)
+            .take(limit.unwrap_or(usize::max_value()));
+        track!(write_entries(entries, scheme, quiet))
+    }
+
+    pub fn print_range_key_value_pairs(
+        &mut self,
+        start: u128,
+        end: u128,
+    ) -> Result<(), cannyls::Error> {
+        if start >= end {
+            return track!(write_entries(None, self.scheme, self.quiet));
+        }
+        self.print_entries_between(start, end - 1, KeyOrder::Asc, 0, None)
+    }
+
+    // keyの昇順に両ストレージのlumpidを1件ずつ突き合わせて差分を求める
+    pub fn diff<M: NonVolatileMemory>(
+        &mut self,
+        other: &mut StorageHandle<M>,
+    ) -> Result<StorageDiff, cannyls::Error> {
+        let mut left = self.iter_keys();
+        let mut right = other.iter_keys();
+        let (mut mine, mut theirs) = (left.next(), right.next());
+
+        let mut diff = StorageDiff::default();
+        loop {
+            // 一方のlumpidを列挙し終えた場合は、もう一方のlumpidを全てそちらにのみ存在するものとする
+            let order = match (mine, theirs) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(a), Some(b)) => a.cmp(&b),
+            };
+            match (order, mine, theirs) {
+                (Ordering::Less, Some(key), _) => {
+                    diff.only_in_self.push(key);
+                    mine = left.next();
+                }
+                (Ordering::Greater, _, Some(key)) => {
+                    diff.only_in_other.push(key);
+                    theirs = right.next();
+                }
+                (_, Some(key), _) => {
+                    let same = match (track!(left.get(&key))?, track!(right.get(&key))?) {
+                        (Some(a), Some(b)) => a.as_bytes() == b.as_bytes(),
+                        (None, None) => true,
+                        _ => false,
+                    };
+                    if !same {
+                        diff.differing.push(key);
+                    }
+                    mine = left.next();
+                    theirs = right.next();
+                }
+                _ => unreachable!(),
+            }
+        }
+        Ok(diff)
+    }
+
+    pub fn repair_plan<M: NonVolatileMemory>(
+        &mut self,
+        source: &mut StorageHandle<M>,
+        delete_extra: bool,
+    ) -> Result<RepairPlan, cannyls::Error> {
+        let diff = track!(self.diff(source))?;
+        Ok(RepairPlan {
+            copy: diff.only_in_other,
+            overwrite: diff.differing,
+            delete: if delete_extra {
+                diff.only_in_self
+            } else {
+                Vec::new()
+            },
+        })
+    }
+
+    // 計画に従ってsourceからlumpをコピー(上書き)し、余分なlumpを削除する
+    pub fn apply_repair<M: NonVolatileMemory>(
+        &mut self,
+        source: &mut StorageHandle<M>,
+        plan: &RepairPlan,
+    ) -> Result<(), cannyls::Error> {
+        track!(self.ensure_writable())?;
+        for key in plan.copy.iter().chain(plan.overwrite.iter()) {
+            if let Some(data) = track!(source.storage.get(key))? {
+                track!(self.put_bytes(key.as_u128(), data.as_bytes()))?;
+            }
+        }
+        for key in &plan.delete {
+            track!(self.storage.delete(key))?;
+        }
+        track!(self.storage.journal_sync())
+    }
+
+    // 全てのlumpをkeyを保ったままdestにコピーし、コピーした件数を返す
+    // 一定件数ごとにdestのジャーナルを同期する
+    pub fn copy_to<M: NonVolatileMemory>(
+        &mut self,
+        dest: &mut StorageHandle<M>,
+    ) -> Result<u64, cannyls::Error> {
+        self.copy_to_with_progress(dest, |_, _| {})
+    }
+
+    // `copy_to`と同じだが、ジャーナルを同期するたびに`on_progress`に(コピー済みの件数, 全件数)を渡す
+    pub fn copy_to_with_progress<M, F>(
+        &mut self,
+        dest: &mut StorageHandle<M>,
+        mut on_progress: F,
+    ) -> Result<u64, cannyls::Error>
+    where
+        M: NonVolatileMemory,
+        F: FnMut(u64, u64),
+    {
+        track!(dest.ensure_writable())?;
+        let total = self.count() as u64;
+        let mut copied = 0;
+        for entry in self.iter_entries() {
+            let (key, data) = track!(entry)?;
+            track!(dest.put_bytes(key.as_u128(), data.as_bytes()))?;
+            copied += 1;
+            if copied % COPY_SYNC_INTERVAL == 0 {
+                track!(dest.storage.journal_sync())?;
+                on_progress(copied, total);
+            }
+        }
+        track!(dest.storage.journal_sync())?;
+        on_progress(copied, total);
+        Ok(copied)
+    }
+
+    // 全てのlumpをブロックサイズが`block_size`のストレージのデータ領域に書き込む (`copy_to`) のに必要なバイト数
+    // ブロックサイズが同じならデータ領域のlumpは索引の大きさをそのまま使い、それ以外は値の長さから求める
+    pub fn required_data_bytes(&mut self, block_size: BlockSize) -> Result<u64, cannyls::Error> {
+        let source_block_size = self.storage.header().block_size;
+        let mut total = 0;
+        let mut keys = self.iter_keys();
+        while let Some(id) = keys.next() {
+            let approximate = match keys.head(&id) {
+                Some(header) => u64::from(header.approximate_data_size),
+                None => continue,
+            };
+            let aligned = approximate % u64::from(source_block_size.as_u16()) == 0;
+            if aligned && approximate > 0 && block_size == source_block_size {
+                total += approximate;
+                continue;
+            }
+            // 埋め込まれたlumpの大きさは正確な長さになる (`lump_size`を参照)
+            let size = if aligned {
+                match track!(keys.get(&id))? {
+                    Some(data) => data.as_bytes().len() as u64,
+                    None => continue,
+                }
+            } else {
+                approximate
+            };
+            total += block_size.ceil_align(size + DATA_TRAILER_SIZE);
+        }
+        Ok(total)
+    }
+
+    // otherの全てのlumpをこのストレージに書き込む
+    // 既に存在するkeyは`policy`に従って扱う。Failの場合は衝突するkeyがあれば何も書き込まない
+    pub fn merge_from<M: NonVolatileMemory>(
+        &mut self,
+        other: &mut StorageHandle<M>,
+        policy: ConflictPolicy,
+    ) -> Result<MergeSummary, cannyls::Error> {
+        track!(self.ensure_writable())?;
+        if policy == ConflictPolicy::Fail {
+            let storage = &mut self.storage;
+            if let Some(key) = other.iter_keys().find(|k| storage.head(k).is_some()) {
+                return Err(track!(cannyls::Error::from(
+                    ErrorKind::InvalidInput.cause(format!("{:?} exists in both storages", key))
+                )));
+            }
+        }
+
+        let mut summary = MergeSummary::default();
+        let mut keys = other.iter_keys();
+        while let Some(key) = keys.next() {
+            let exists = self.storage.head(&key).is_some();
+            if exists && policy == ConflictPolicy::Skip {
+                summary.skipped += 1;
+                continue;
+            }
+            if let Some(data) = track!(keys.get(&key))? {
+                track!(self.put_bytes(key.as_u128(), data.as_bytes()))?;
+                if exists {
+                    summary.overwritten += 1;
+                } else {
+                    summary.merged += 1;
+                }
+                if (summary.merged + summary.overwritten) % COPY_SYNC_INTERVAL == 0 {
+                    track!(self.storage.journal_sync())?;
+                }
+            }
+        }
+        track!(self.storage.journal_sync())?;
+        Ok(summary)
+    }
+
+    // `[{"key": "...", "value": "...", "size": n}, ...]` 形式で1件ずつ書き出す
+    // UTF-8として解釈できない値は `"encoding": "base64"` を付けてbase64で出力する
+    pub fn dump_json<W: Write>(&mut self, out: W) -> Result<(), cannyls::Error> {
+        self.dump_json_between(0, u128::max_value(), KeyOrder::Asc, 0, None, out)
+    }
+
+    // keyが[min, max]の範囲のlumpを、1件ずつ読み出しながら
+    // 先頭の`offset`件を読み飛ばして最大`limit`件まで書き出す
+    pub fn dump_json_between<W: Write>(
+        &mut self,
+        min: u128,
+        max: u128,
+        order: KeyOrder,
+        offset: usize,
+        limit: Option<usize>,
+        mut out: W,
+    ) -> Result<(), cannyls::Error> {
+        track_io!(write!(out, "["))?;
+        let entries = self
+            .iter_entries_ordered(min, max, order)
+            .skip_keys(offset)
+            .take(limit.unwrap_or(usize::max_value()));
+        for (i, entry) in entries.enumerate() {
+            let (key, data) = track!(entry)?;
+            let separator = if i == 0 { "\n" } else { ",\n" };
+            track_io!(write!(out, "{}", separator))?;
+            track_io!(
+                serde_json::to_writer(&mut out, &JsonLump::new(&key, data.as_bytes()))
+                    .map_err(io::Error::from)
+            )?;
+            track_io!(out.flush())?;
+        }
+        track_io!(writeln!(out, "\n]"))?;
+        Ok(())
+    }
+
+    pub fn journal_stats(&mut self) -> Result<JournalStats, cannyls::Error> {
+        let snapshot = track!(self.storage.journal_snapshot())?;
+        Ok(JournalStats::from_journal(self.storage.header(), &snapshot))
+    }
+
+    // 値は読み込まず、lumpごとの情報も保持せずに使用状況を求める
+    // lumpの数と使用量は索引(`head`)を順に調べて数え、最大の連続した空き領域はジャーナル上のPutレコードの位置から求める
+    pub fn usage(&mut self) -> Result<StorageUsage, cannyls::Error> {
+        let header = self.storage.header().clone();
+        let block_size = u64::from(header.block_size.as_u16());
+        let mut lumps = 0;
+        let mut embedded_lumps = 0;
+        let mut allocated_bytes = 0;
+        {
+            let mut keys = self.iter_keys();
+            while let Some(id) = keys.next() {
+                let size = keys
+                    .head(&id)
+                    .map_or(0, |h| u64::from(h.approximate_data_size));
+                lumps += 1;
+                // データ領域のlumpは1ブロック以上を使い、サイズはブロックサイズの倍数になる (`lump_size`を参照)
+                if size == 0 || size % block_size != 0 {
+                    embedded_lumps += 1;
+                } else {
+                    allocated_bytes += size;
+                }
+            }
+        }
+
+        // 索引のサイズと長さが一致するPutレコードを生存しているものとみなす
+        // 同じkeyを同じブロック数で上書きする前のレコードが残っている場合は、その区間も使用中とみなすため推定値になる
+        let snapshot = track!(self.storage.journal_snapshot())?;
+        let mut extents = AllocatedExtents::default();
+        for entry in &snapshot.entries {
+            if let JournalRecord::Put(id, ref portion) = entry.record {
+                let start = portion.start.as_u64();
+                let blocks = u64::from(portion.len);
+                if let Some(head) = self.storage.head(&id) {
+                    if u64::from(head.approximate_data_size) == blocks * block_size {
+                        extents.insert(start, start + blocks);
+                    }
+                }
+            }
+        }
+        let total_blocks = header.data_region_size / block_size;
+
+        Ok(StorageUsage {
+            data_region_size: header.data_region_size,
+            allocated_bytes,
+            lumps,
+            embedded_lumps,
+            journal_region_size: header.journal_region_size,
+            journal_used_bytes: JournalPositions::new(&snapshot).used_bytes(&header),
+            largest_free_extent: extents.largest_free(total_blocks) * block_size,
+        })
+    }
+
+    pub fn header(&self) -> &StorageHeader {
+        self.storage.header()
+    }
+
+    // ベンチマークなど、ストレージを直接操作する場合に使う
+    pub fn into_storage(self) -> Storage<N> {
+        self.storage
+    }
+
+    pub fn print_header_info(&mut self, json: bool) {
+        let stdout = io::stdout();
+        let _ = if json {
+            write_header_json(self.storage.header(), stdout.lock())
+        } else {
+            write_header_info(self.storage.header(), stdout.lock())
+        };
+    }
+
+    pub fn print_storage_info(&mut self, json: bool) -> Result<(), cannyls::Error> {
+        let usage = track!(self.usage())?;
+        let journal = track!(self.journal_stats())?;
+        let stdout = io::stdout();
+        let _ = write_storage_info(self.storage.header(), &usage, &journal, json, stdout.lock());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+    use trackable::result::TestResult;
+
+    use super::*;
+    use cannyls::nvm::MemoryNvm;
+    use handle::StorageHandle;
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // 読み込みエラーを任意のタイミングで発生させるためのNVM
+    struct FaultyNvm {
+        inner: MemoryNvm,
+        fail_reads: Arc<AtomicBool>,
+    }
+    impl FaultyNvm {
+        fn new(capacity: usize) -> (Self, Arc<AtomicBool>) {
+            let fail_reads = Arc::new(AtomicBool::new(false));
+            let nvm = FaultyNvm {
+                inner: MemoryNvm::new(vec![0; capacity]),
+                fail_reads: fail_reads.clone(),
+            };
+            (nvm, fail_reads)
+        }
+    }
+    impl Read for FaultyNvm {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.fail_reads.load(Ordering::SeqCst) {
+                return Err(io::Error::new(io::ErrorKind::Other, "injected read error"));
+            }
+            self.inner.read(buf)
+        }
+    }
+    impl Write for FaultyNvm {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.inner.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+    impl Seek for FaultyNvm {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+    impl NonVolatileMemory for FaultyNvm {
+        fn sync(&mut self) -> Result<(), cannyls::Error> {
+            self.inner.sync()
+        }
+        fn position(&self) -> u64 {
+            self.inner.position()
+        }
+        fn capacity(&self) -> u64 {
+            self.inner.capacity()
+        }
+        fn block_size(&self) -> cannyls::block::BlockSize {
+            self.inner.block_size()
+        }
+        fn split(self, position: u64) -> Result<(Self, Self), cannyls::Error> {
+            let (left, right) = track!(self.inner.split(position))?;
+            let left = FaultyNvm {
+                inner: left,
+                fail_reads: self.fail_reads.clone(),
+            };
+            let right = FaultyNvm {
+                inner: right,
+                fail_reads: self.fail_reads,
+            };
+            Ok((left, right))
+        }
+    }
+
+    #[test]
+    fn open_errors_are_returned() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let missing = dir.path().join("missing.lusf");
+        assert!(StorageHandle::create(&missing).is_err());
+        assert!(StorageHandle::open_readonly(&missing).is_err());
+
+        let corrupt = dir.path().join("corrupt.lusf");
+        track_io!(fs::write(&corrupt, vec![0xff; 4096]))?;
+        assert!(StorageHandle::create(&corrupt).is_err());
+        assert!(StorageHandle::open_readonly(&corrupt).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn overwrite_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+
+        let nvm = track_try_unwrap!(FileNvm::create(path, 4_000_000));
+        let storage = track_try_unwrap!(Storage::create(nvm));
+        let mut handle = StorageHandle::new(storage);
+
+        assert!(handle.put_str(0, "hoge").is_ok());
+        assert!(handle.put_str(0, "bar").is_ok());
+        assert_eq!(handle.get_string(0)?.unwrap(), "bar".to_owned());
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+
+        let nvm = track_try_unwrap!(FileNvm::create(path, 4_000_000));
+        let storage = track_try_unwrap!(Storage::create(nvm));
+        let mut handle = StorageHandle::new(storage);
+
+        assert!(handle.put_str(0, "hoge").is_ok());
+        assert!(handle.delete_key(0)?, true);
         assert!(handle.get_string(0)?.is_none());
 
         Ok(())
     }
+
+    #[test]
+    fn count_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        assert_eq!(handle.count(), 0);
+        for key in 0..3 {
+            handle.put_str(key, "hoge")?;
+        }
+        assert_eq!(handle.count(), 3);
+        // 上書きでは件数は変わらない
+        handle.put_str(0, "fuga")?;
+        assert_eq!(handle.count(), 3);
+        handle.delete_key(1)?;
+        assert_eq!(handle.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn contains_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        assert!(!handle.contains(0)?);
+        handle.put_str(0, "hoge")?;
+        assert!(handle.contains(0)?);
+        assert!(!handle.contains(1)?);
+        handle.delete_key(0)?;
+        assert!(!handle.contains(0)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_many_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        handle.put_str(1, "foo")?;
+        handle.put_str(3, "bar")?;
+
+        assert_eq!(
+            handle.get_many(&[3, 2, 1, 3])?,
+            vec![
+                (3, Some("bar".to_owned())),
+                (2, None),
+                (1, Some("foo".to_owned())),
+                (3, Some("bar".to_owned())),
+            ]
+        );
+        assert_eq!(handle.print_many(&[1, 2, 4])?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_raw_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        handle.put_bytes(1, &[0xff, 0x00, b'\n'])?;
+
+        let mut out = Vec::new();
+        assert_eq!(handle.write_raw(1, &mut out)?, Some(3));
+        assert_eq!(out, vec![0xff, 0x00, b'\n']);
+
+        let mut out = Vec::new();
+        assert_eq!(handle.write_raw(2, &mut out)?, None);
+        assert!(out.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn get_distinguishes_absent_keys_from_errors() -> TestResult {
+        let (nvm, fail_reads) = FaultyNvm::new(4_000_000);
+        let storage = track!(Storage::create(nvm))?;
+        let mut handle = StorageHandle::new(storage);
+
+        assert!(handle.put_str(0, "hoge").is_ok());
+        assert!(handle.get(0)?);
+        assert!(!handle.get(1)?);
+
+        fail_reads.store(true, Ordering::SeqCst);
+        assert!(handle.get(0).is_err());
+        assert!(handle.get_string(0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn verify_works() -> TestResult {
+        let (nvm, fail_reads) = FaultyNvm::new(4_000_000);
+        let storage = track!(Storage::create(nvm))?;
+        let mut handle = StorageHandle::new(storage);
+        handle.put_str(0, "foo")?;
+        handle.put_str(1, "bar")?;
+
+        let mut failures = Vec::new();
+        let summary = handle.verify(|id, _| failures.push(id));
+        assert_eq!(summary, VerifySummary { ok: 2, failed: 0 });
+        assert!(failures.is_empty());
+
+        fail_reads.store(true, Ordering::SeqCst);
+        let summary = handle.verify(|id, _| failures.push(id));
+        assert_eq!(summary, VerifySummary { ok: 0, failed: 2 });
+        assert_eq!(failures, vec![LumpId::new(0), LumpId::new(1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dump_json_works() -> TestResult {
+        let nvm = MemoryNvm::new(vec![0; 4_000_000]);
+        let storage = track!(Storage::create(nvm))?;
+        let mut handle = StorageHandle::new(storage);
+
+        assert!(handle.put_str(0, "hoge").is_ok());
+        assert!(handle.put_bytes(u128::max_value(), &[0xff, 0x00]).is_ok());
+
+        let mut buf = Vec::new();
+        handle.dump_json(&mut buf)?;
+        let json: serde_json::Value = serde_json::from_slice(&buf).expect("valid JSON");
+        assert_eq!(
+            json,
+            json!([
+                {"key": "0", "value": "hoge", "size": 4},
+                {"key": u128::max_value().to_string(), "value": "/wA=", "size": 2, "encoding": "base64"}
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn diff_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut a = StorageHandle::new(storage);
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut b = StorageHandle::new(storage);
+        assert!(a.diff(&mut b)?.is_empty());
+
+        a.put_str(0, "same")?;
+        a.put_str(1, "only in a")?;
+        a.put_str(2, "foo")?;
+        a.put_str(3, "foo")?;
+        b.put_str(0, "same")?;
+        b.put_str(2, "foobar")?;
+        b.put_str(3, "bar")?;
+        b.put_str(u128::max_value(), "only in b")?;
+
+        let diff = a.diff(&mut b)?;
+        assert_eq!(diff.only_in_self, vec![LumpId::new(1)]);
+        assert_eq!(diff.only_in_other, vec![LumpId::new(u128::max_value())]);
+        assert_eq!(diff.differing, vec![LumpId::new(2), LumpId::new(3)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_works() -> TestResult {
+        let setup = || -> Result<_, cannyls::Error> {
+            let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+            let mut dest = StorageHandle::new(storage);
+            dest.put_str(0, "dest")?;
+            dest.put_str(1, "dest only")?;
+            let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+            let mut src = StorageHandle::new(storage);
+            src.put_str(0, "src")?;
+            src.put_str(2, "src only")?;
+            Ok((dest, src))
+        };
+
+        let (mut dest, mut src) = setup()?;
+        let summary = dest.merge_from(&mut src, ConflictPolicy::Skip)?;
+        assert_eq!(
+            summary,
+            MergeSummary {
+                merged: 1,
+                skipped: 1,
+                overwritten: 0
+            }
+        );
+        assert_eq!(dest.get_string(0)?, Some("dest".to_owned()));
+        assert_eq!(dest.get_string(2)?, Some("src only".to_owned()));
+
+        let (mut dest, mut src) = setup()?;
+        let summary = dest.merge_from(&mut src, ConflictPolicy::Overwrite)?;
+        assert_eq!(
+            summary,
+            MergeSummary {
+                merged: 1,
+                skipped: 0,
+                overwritten: 1
+            }
+        );
+        assert_eq!(dest.get_string(0)?, Some("src".to_owned()));
+        assert_eq!(dest.count(), 3);
+
+        // 衝突するkeyがあれば何も書き込まない
+        let (mut dest, mut src) = setup()?;
+        assert!(dest.merge_from(&mut src, ConflictPolicy::Fail).is_err());
+        assert_eq!(dest.get_string(0)?, Some("dest".to_owned()));
+        assert_eq!(dest.get_string(2)?, None);
+
+        src.delete_key(0)?;
+        let summary = dest.merge_from(&mut src, ConflictPolicy::Fail)?;
+        assert_eq!(summary.merged, 1);
+        assert_eq!(dest.count(), 3);
+
+        assert_eq!("skip".parse(), Ok(ConflictPolicy::Skip));
+        assert_eq!("error".parse(), Ok(ConflictPolicy::Fail));
+        assert!("ignore".parse::<ConflictPolicy>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn repair_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut source = StorageHandle::new(storage);
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut suspect = StorageHandle::new(storage);
+
+        source.put_str(0, "same")?;
+        source.put_str(1, "missing")?;
+        source.put_str(2, "good")?;
+        suspect.put_str(0, "same")?;
+        suspect.put_str(2, "bad")?;
+        suspect.put_str(3, "extra")?;
+
+        let plan = suspect.repair_plan(&mut source, false)?;
+        assert_eq!(plan.copy, vec![LumpId::new(1)]);
+        assert_eq!(plan.overwrite, vec![LumpId::new(2)]);
+        assert!(plan.delete.is_empty());
+
+        let plan = suspect.repair_plan(&mut source, true)?;
+        assert_eq!(plan.delete, vec![LumpId::new(3)]);
+        suspect.apply_repair(&mut source, &plan)?;
+
+        assert!(suspect.diff(&mut source)?.is_empty());
+        assert_eq!(suspect.get_string(2)?, Some("good".to_owned()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_lumpids_works() {
+        let ids = vec![LumpId::new(1), LumpId::new(255)];
+        let render = |format| {
+            let mut buf = Vec::new();
+            write_lumpids(&ids, format, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert_eq!(render(ListFormat::Plain), "1\n255\n");
+        assert_eq!(
+            render(ListFormat::Hex),
+            "00000000000000000000000000000001\n000000000000000000000000000000ff\n"
+        );
+        assert_eq!(render(ListFormat::Json), "[\"1\",\"255\"]\n");
+        assert!("yaml".parse::<ListFormat>().is_err());
+    }
+
+    #[test]
+    fn write_lumpids_with_sizes_works() {
+        let entries = vec![(LumpId::new(5), 1_048_576), (LumpId::new(255), 0)];
+        let render = |format| {
+            let mut buf = Vec::new();
+            write_lumpids_with_sizes(&entries, format, false, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert_eq!(render(ListFormat::Plain), "5 1048576\n255 0\n");
+        assert_eq!(
+            render(ListFormat::Hex),
+            "00000000000000000000000000000005 1048576\n000000000000000000000000000000ff 0\n"
+        );
+        assert_eq!(
+            render(ListFormat::Json),
+            "[{\"key\":\"5\",\"size\":1048576},{\"key\":\"255\",\"size\":0}]\n"
+        );
+    }
+
+    #[test]
+    fn quiet_output_omits_banners() {
+        let ids = vec![LumpId::new(1)];
+        let mut buf = Vec::new();
+        write_annotated_lumpids(&ids, None, ListFormat::Text, true, &mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "LumpId(\"00000000000000000000000000000001\")\n"
+        );
+
+        let mut buf = Vec::new();
+        write_annotated_lumpids(&[] as &[LumpId], None, ListFormat::Text, true, &mut buf).unwrap();
+        assert!(buf.is_empty());
+
+        let entries = vec![(LumpId::new(1), 3)];
+        let mut buf = Vec::new();
+        write_lumpids_with_sizes(&entries, ListFormat::Text, true, &mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "LumpId(\"00000000000000000000000000000001\") 3\n"
+        );
+    }
+
+    #[test]
+    fn header_and_storage_info_work() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        handle.put_str(1, "foo")?;
+        let header = handle.header().clone();
+
+        let mut text = Vec::new();
+        write_header_info(&header, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let mut buf = Vec::new();
+        write_header_json(&header, &mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).expect("valid JSON");
+        assert_eq!(json["block_size"], json!(header.block_size.as_u16()));
+        assert_eq!(json["uuid"], json!(header.instance_uuid.to_string()));
+        assert_eq!(json["storage_size"], json!(header.storage_size()));
+        assert_eq!(json["region_size"], json!(header.region_size()));
+        assert_eq!(json["major_version"], json!(header.major_version));
+        assert_eq!(
+            json["journal_region_size"],
+            json!(header.journal_region_size)
+        );
+        assert!(text.contains(&format!("  uuid = {}\n", header.instance_uuid)));
+        // テキストではバイト数に読みやすい形式を添える
+        let record_size = json["journal_record_size"].as_u64().unwrap();
+        assert!(text.contains(&format!(
+            "    journal record size = {}\n",
+            format_bytes(record_size)
+        )));
+        assert!(text.contains(&format!(
+            "  storage total size = {} ({})\n",
+            header.storage_size(),
+            format_size(header.storage_size())
+        )));
+
+        let usage = handle.usage()?;
+        let journal = handle.journal_stats()?;
+        let mut buf = Vec::new();
+        write_storage_info(&header, &usage, &journal, true, &mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).expect("valid JSON");
+        assert_eq!(json["lumps"], json!(1));
+        assert_eq!(
+            json["header"]["data_region_size"],
+            json!(header.data_region_size)
+        );
+        assert_eq!(json["allocated_bytes"], json!(usage.allocated_bytes));
+
+        let mut buf = Vec::new();
+        write_storage_info(&header, &usage, &journal, false, &mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.starts_with("header =>\n"));
+        assert!(text.contains("lumps = 1 (embedded: 0)\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn journal_stats_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        let stats = handle.journal_stats()?;
+        assert_eq!(stats.entries(), 0);
+        assert_eq!(stats.occupied_bytes, 0);
+        assert_eq!(
+            stats.capacity,
+            handle.header().journal_region_size - u64::from(handle.header().block_size.as_u16())
+        );
+
+        handle.put_str(1, "foo")?;
+        handle.put_str(2, "bar")?;
+        handle.put_embedded(3, b"baz")?;
+        handle.delete_key(1)?;
+        let stats = handle.journal_stats()?;
+        assert_eq!(
+            (stats.puts, stats.embeds, stats.deletes, stats.delete_ranges),
+            (2, 1, 1, 0)
+        );
+        assert_eq!(stats.entries(), 4);
+        let snapshot = handle.journal_info(None)?;
+        assert_eq!(stats.occupied_bytes, snapshot.tail - snapshot.head);
+        assert_eq!(stats.pending_release_bytes, 0);
+        let utilization = stats.occupied_bytes as f64 / stats.capacity as f64;
+        assert!((stats.utilization() - utilization).abs() < 1e-9);
+
+        let mut buf = Vec::new();
+        write_journal_stats(&stats, &mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.contains("entries               = 4\n"));
+        assert!(text.contains(&format!("tail                  = {}\n", snapshot.tail)));
+        assert!(text.contains("  embed               = 1\n"));
+
+        // リングバッファを折り返した位置も扱える
+        assert_eq!(ring_distance(100, 90, 10), 20);
+        assert_eq!(ring_distance(100, 10, 90), 80);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_gc_reports_reclaimed_bytes() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        handle.put_str(1, "foo")?;
+        handle.put_str(2, "bar")?;
+        handle.delete_key(1)?;
+
+        let report = handle.journal_gc_with_stats()?;
+        assert_eq!(report.before.unreleased_head, 0);
+        assert_eq!(report.used_before, report.before.tail);
+        assert!(report.used_after < report.used_before);
+        assert_eq!(
+            report.reclaimed_bytes(),
+            report.used_before - report.used_after
+        );
+        assert_eq!(report.used_after, handle.usage()?.journal_used_bytes);
+        assert_eq!(report.entries_before, 3);
+        assert_eq!(report.released_entries(), 3 - report.entries_after);
+        assert_eq!(
+            report.head_advanced(),
+            report.after.head - report.before.head
+        );
+        let utilization = report.used_after as f64 / report.capacity as f64;
+        assert!((report.utilization() - utilization).abs() < 1e-9);
+        assert!(report
+            .to_string()
+            .contains(&format!("reclaimed {} bytes, ", report.reclaimed_bytes())));
+        Ok(())
+    }
+
+    #[test]
+    fn journal_filter_and_json_work() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        handle.put_str(1, "foo")?;
+        handle.put_embedded(2, b"bar")?;
+        handle.delete_key(1)?;
+
+        assert_eq!(handle.journal_info(None)?.entries.len(), 3);
+        let snapshot = handle.journal_info(Some(1))?;
+        assert_eq!(snapshot.entries.len(), 2);
+        assert_eq!(handle.journal_info(Some(3))?.entries.len(), 0);
+
+        let filter = |entry_type: &str, limit| JournalFilter {
+            key: None,
+            entry_type: Some(entry_type.parse().unwrap()),
+            limit,
+        };
+        assert_eq!(
+            handle
+                .filtered_journal_info(&filter("put", None))?
+                .entries
+                .len(),
+            1
+        );
+        assert_eq!(
+            handle
+                .filtered_journal_info(&filter("embed", None))?
+                .entries
+                .len(),
+            1
+        );
+        assert_eq!(
+            handle
+                .filtered_journal_info(&filter("delete", None))?
+                .entries
+                .len(),
+            1
+        );
+        assert_eq!(
+            handle
+                .filtered_journal_info(&filter("put", Some(0)))?
+                .entries
+                .len(),
+            0
+        );
+        assert!("delete_range".parse::<JournalEntryType>().is_err());
+        let limited = JournalFilter {
+            limit: Some(2),
+            ..JournalFilter::default()
+        };
+        let entries = handle.filtered_journal_info(&limited)?.entries;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].start.as_u64(), 0);
+
+        let mut buf = Vec::new();
+        write_journal_json(&snapshot, &mut buf).unwrap();
+        let lines = String::from_utf8(buf).unwrap();
+        let lines = lines
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).expect("valid JSON"))
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["tail"], json!(snapshot.tail));
+        assert_eq!(lines[1]["record"], json!("put"));
+        assert_eq!(lines[1]["lump_id"], json!("1"));
+        assert_eq!(lines[1]["position"], json!(0));
+        assert_eq!(lines[2]["record"], json!("delete"));
+
+        let snapshot = handle.journal_info(Some(2))?;
+        let mut buf = Vec::new();
+        write_journal_json(&snapshot, &mut buf).unwrap();
+        let entry = String::from_utf8(buf)
+            .unwrap()
+            .lines()
+            .nth(1)
+            .unwrap()
+            .to_owned();
+        let entry: serde_json::Value = serde_json::from_str(&entry).unwrap();
+        assert_eq!(entry["record"], json!("embed"));
+        assert_eq!(entry["length"], json!(3));
+        Ok(())
+    }
+
+    #[test]
+    fn read_only_handle_rejects_mutations() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+        let path = dir.path().join("test.lusf");
+
+        let nvm = track!(FileNvm::create(&path, 4_000_000))?;
+        let storage = track!(Storage::create(nvm))?;
+        let mut handle = StorageHandle::new(storage);
+        handle.put_str(0, "hoge")?;
+        drop(handle);
+        let before = track_io!(fs::read(&path))?;
+
+        let mut handle = StorageHandle::open_readonly(&path)?;
+        assert!(handle.is_read_only());
+        assert_eq!(handle.get_string(0)?, Some("hoge".to_owned()));
+        assert!(handle.put_str(1, "bar").is_err());
+        assert!(handle.delete_key(0).is_err());
+        drop(handle);
+
+        assert_eq!(track_io!(fs::read(&path))?, before);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_values_work() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        assert!(handle.put_str(5, "")?);
+        assert!(handle.contains(5)?);
+        assert_eq!(handle.lump_size(5)?, Some(0));
+        assert_eq!(handle.get_string(5)?, Some("".to_owned()));
+        assert!(handle.get(5)?);
+
+        let mut buf = Vec::new();
+        handle.dump_json(&mut buf)?;
+        let json: serde_json::Value = serde_json::from_slice(&buf).expect("valid JSON");
+        assert_eq!(json, json!([{"key": "5", "value": "", "size": 0}]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn lump_size_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        handle.put_str(0, "foo")?;
+        handle.put_bytes(1, &vec![0; 1000])?;
+        handle.put_embedded(2, b"tiny")?;
+        assert_eq!(handle.lump_size(0)?, Some(3));
+        assert_eq!(handle.lump_size(1)?, Some(1000));
+        assert_eq!(handle.lump_size(2)?, Some(4));
+        assert_eq!(handle.lump_size(3)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn put_embedded_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        handle.put_str(0, "foo")?;
+        assert_eq!(handle.is_embedded(0)?, Some(false));
+        assert_eq!(handle.is_embedded(2)?, None);
+
+        assert!(handle.put_embedded(2, b"tiny")?);
+        assert_eq!(handle.get_string(2)?, Some("tiny".to_owned()));
+        assert_eq!(handle.is_embedded(2)?, Some(true));
+        // 既存のkeyを埋め込みで上書きし、通常の書き込みで元に戻す
+        assert!(!handle.put_embedded(0, b"bar")?);
+        assert_eq!(handle.is_embedded(0)?, Some(true));
+        handle.put_str(0, "foo")?;
+        assert_eq!(handle.is_embedded(0)?, Some(false));
+
+        let too_large = vec![0; LumpData::MAX_EMBEDDED_SIZE + 1];
+        assert!(handle.put_embedded(3, &too_large).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn append_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        // 存在しないkeyには新たなlumpを作り、存在するkeyには末尾に連結する
+        assert_eq!(handle.append(5, b"foo")?, 3);
+        assert_eq!(handle.append(5, b"bar\n")?, 7);
+        assert_eq!(handle.get_bytes(5)?, Some(b"foobar\n".to_vec()));
+
+        // 埋め込まれた値に連結した結果はデータ領域に書き込む
+        handle.put_embedded(2, b"tiny")?;
+        assert_eq!(handle.append(2, b"!")?, 5);
+        assert_eq!(handle.get_string(2)?, Some("tiny!".to_owned()));
+        assert_eq!(handle.is_embedded(2)?, Some(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn put_bytes_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        let too_large = vec![0; LumpData::MAX_SIZE + 1];
+        let e = handle.put_bytes(3, &too_large).unwrap_err();
+        assert!(e.to_string().contains(&LumpData::MAX_SIZE.to_string()));
+        assert!(!handle.contains(3)?);
+
+        // 末尾の改行やUTF-8でないバイト列もそのまま書き込む
+        handle.put_bytes(4, b"line\n\x00\xff\n")?;
+        assert_eq!(handle.get_bytes(4)?, Some(b"line\n\x00\xff\n".to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stat_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        handle.put_bytes(1, &vec![0; 1000])?;
+        handle.put_embedded(2, b"tiny")?;
+
+        let stat = handle.stat(2)?.unwrap();
+        assert_eq!(stat.size, 4);
+        assert_eq!(stat.placement, LumpPlacement::Embedded);
+        let stat = handle.stat(1)?.unwrap();
+        assert_eq!(stat.size, 1000);
+        match stat.placement {
+            LumpPlacement::Data { blocks, .. } => assert_eq!(blocks, 2),
+            _ => panic!("{:?}", stat),
+        }
+        assert_eq!(handle.stat(3)?, None);
+
+        let mut buf = Vec::new();
+        write_lump_stat("2", &handle.stat(2)?.unwrap(), &mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "key = 2\nsize = 4 (4 B)\nplacement = embedded in the journal region\n"
+        );
+        let unknown = LumpStat {
+            size: 1000,
+            placement: LumpPlacement::Unknown,
+        };
+        let mut buf = Vec::new();
+        write_lump_stat("1", &unknown, &mut buf).unwrap();
+        assert!(String::from_utf8(buf)
+            .unwrap()
+            .ends_with("placement = unknown (not found in the journal)\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn lump_stats_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        handle.put_str(0, "foo")?;
+        handle.put_bytes(1, &vec![0; 1000])?;
+
+        let stats = handle.lump_stats()?;
+        assert_eq!(
+            stats,
+            LumpStats {
+                count: 2,
+                total_size: 1003
+            }
+        );
+        assert_eq!(stats.average_size(), 501.5);
+        let mut buf = Vec::new();
+        write_lump_stats(&stats, ListFormat::Plain, &mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "2\n");
+        let mut buf = Vec::new();
+        write_lump_stats(&stats, ListFormat::Text, &mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "lumps = 2\ntotal size = 1003\naverage size = 501.50\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_range_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        for key in &[1, 2, 3, 5, 8] {
+            handle.put_str(*key, "value")?;
+        }
+
+        // startは含み、endは含まない
+        let ids = |keys: &[u128]| keys.iter().map(|k| LumpId::new(*k)).collect::<Vec<_>>();
+        assert_eq!(handle.list_range(2, 5), ids(&[2, 3]));
+        assert_eq!(handle.list_range(2, 6), ids(&[2, 3, 5]));
+        assert_eq!(
+            handle.list_range(0, u128::max_value()),
+            ids(&[1, 2, 3, 5, 8])
+        );
+        assert_eq!(handle.list_range(3, 3), ids(&[]));
+        assert_eq!(handle.list_range(8, 1), ids(&[]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn iter_keys_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        let max = u128::max_value();
+        // 1回の問い合わせ幅より多く連続したkeyと、離れたkeyを混ぜる
+        let mut keys = (0..3000).collect::<Vec<u128>>();
+        keys.extend(&[1 << 64, max - 1, max]);
+        for key in &keys {
+            handle.put_str(*key, "v")?;
+        }
+
+        let iterated = handle
+            .iter_keys()
+            .map(|id| id.as_u128())
+            .collect::<Vec<_>>();
+        assert_eq!(iterated, keys);
+        assert_eq!(handle.count(), keys.len());
+        assert_eq!(
+            handle
+                .iter_keys_between(2999, max - 1)
+                .map(|id| id.as_u128())
+                .collect::<Vec<_>>(),
+            vec![2999, 1 << 64, max - 1]
+        );
+        assert_eq!(handle.iter_keys_between(5, 4).count(), 0);
+        assert_eq!(handle.iter_keys().take(3).count(), 3);
+
+        // 降順でも範囲の両端を含み、途中で打ち切れる
+        let descending = handle
+            .iter_keys_ordered(0, max, KeyOrder::Desc)
+            .map(|id| id.as_u128())
+            .collect::<Vec<_>>();
+        assert_eq!(descending, keys.iter().rev().cloned().collect::<Vec<_>>());
+        assert_eq!(
+            handle
+                .iter_keys_ordered(2998, max - 1, KeyOrder::Desc)
+                .map(|id| id.as_u128())
+                .collect::<Vec<_>>(),
+            vec![max - 1, 1 << 64, 2999, 2998]
+        );
+        assert_eq!(handle.iter_keys_ordered(5, 4, KeyOrder::Desc).count(), 0);
+        assert_eq!("desc".parse::<KeyOrder>(), Ok(KeyOrder::Desc));
+        assert!("reverse".parse::<KeyOrder>().is_err());
+        assert!("none".parse::<KeyOrder>().is_err());
+
+        let entries = handle
+            .iter_entries_between(max - 1, max)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].0, LumpId::new(max));
+        assert_eq!(entries[1].1.as_bytes(), b"v");
+
+        // 並べ替えた後に読み飛ばす
+        let skipped = handle
+            .iter_entries_ordered(0, max, KeyOrder::Desc)
+            .skip_keys(2)
+            .take(2)
+            .collect::<Result<Vec<_>, _>>()?;
+        let skipped = skipped.iter().map(|e| e.0.as_u128()).collect::<Vec<_>>();
+        assert_eq!(skipped, vec![1 << 64, 2999]);
+        assert_eq!(handle.iter_entries().skip_keys(keys.len()).count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_between_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        let max = u128::max_value();
+        for key in &[1, 2, 3, 5, max - 1, max] {
+            handle.put_str(*key, "value")?;
+        }
+
+        // 両端を含む
+        let ids = |keys: &[u128]| keys.iter().map(|k| LumpId::new(*k)).collect::<Vec<_>>();
+        assert_eq!(handle.list_between(2, 5), ids(&[2, 3, 5]));
+        assert_eq!(handle.list_between(3, 3), ids(&[3]));
+        assert_eq!(handle.list_between(4, 4), ids(&[]));
+        assert_eq!(handle.list_between(5, 2), ids(&[]));
+        assert_eq!(handle.list_between(5, max), ids(&[5, max - 1, max]));
+        assert_eq!(handle.list_between(max, max), ids(&[max]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn usage_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        let usage = handle.usage()?;
+        assert_eq!(usage.allocated_bytes, 0);
+        assert_eq!(usage.largest_free_extent, usage.data_region_size);
+        assert_eq!(usage.fragmentation(), 0.0);
+
+        // 512バイトのブロックを1つずつ使うlumpを3つ書き込み、真ん中を削除して穴をあける
+        for key in 0..3 {
+            handle.put_bytes(key, &[0; 100])?;
+        }
+        handle.delete_key(1)?;
+        let usage = handle.usage()?;
+        assert_eq!(usage.lumps, 2);
+        assert_eq!(usage.allocated_bytes, 2 * 512);
+        assert_eq!(usage.free_bytes(), usage.data_region_size - 2 * 512);
+        assert_eq!(usage.largest_free_extent, usage.data_region_size - 3 * 512);
+        assert!(usage.fragmentation() > 0.0);
+        assert!(usage.journal_used_bytes > 0);
+        assert_eq!(
+            usage.journal_used_bytes + usage.journal_free_bytes(),
+            usage.journal_region_size
+        );
+
+        // 上書きで移動した元の区間や、埋め込まれたlumpは使用量に含めない
+        handle.put_bytes(0, &[0; 1000])?;
+        handle.put_embedded(5, b"tiny")?;
+        let usage = handle.usage()?;
+        assert_eq!(usage.lumps, 3);
+        assert_eq!(usage.embedded_lumps, 1);
+        assert_eq!(usage.allocated_bytes, 3 * 512);
+
+        Ok(())
+    }
+
+    #[test]
+    fn required_data_bytes_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        handle.put_bytes(0, &[0; 100])?;
+        handle.put_bytes(1, &[0; 1000])?;
+        handle.put_embedded(2, b"tiny")?;
+
+        // 埋め込まれたlumpもコピー先ではデータ領域に書き込まれる
+        assert_eq!(
+            handle.required_data_bytes(BlockSize::min())?,
+            512 + 1024 + 512
+        );
+        let block_size = BlockSize::new(1024).unwrap();
+        assert_eq!(handle.required_data_bytes(block_size)?, 3 * 1024);
+        Ok(())
+    }
+
+    #[test]
+    fn allocated_extents_works() {
+        let mut extents = AllocatedExtents::default();
+        assert_eq!(extents.largest_free(10), 10);
+
+        extents.insert(2, 3);
+        extents.insert(5, 6);
+        assert_eq!(extents.largest_free(10), 4);
+        // 隣接する区間や重なる区間はまとめる
+        extents.insert(3, 5);
+        extents.insert(1, 4);
+        assert_eq!(extents.runs.len(), 1);
+        assert_eq!(extents.runs.get(&1), Some(&6));
+        assert_eq!(extents.largest_free(10), 4);
+        extents.insert(8, 10);
+        assert_eq!(extents.largest_free(10), 2);
+    }
+
+    #[test]
+    fn import_csv_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        let input = "key,value\n1,foo\nbroken\n0x2,\"a,b\"\nx,bar\n";
+        let mut skipped = Vec::new();
+        let summary = handle.import_csv(input.as_bytes(), false, |line, _| skipped.push(line))?;
+        assert_eq!(
+            summary,
+            ImportSummary {
+                imported: 2,
+                overwritten: 0,
+                skipped: 2
+            }
+        );
+        assert_eq!(skipped, vec![3, 5]);
+        assert_eq!(handle.get_string(2)?, Some("a,b".to_owned()));
+
+        let error = handle.import_csv("3,baz\nbroken\n".as_bytes(), true, |_, _| {});
+        assert!(error.is_err());
+        assert_eq!(handle.count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_keys_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        handle.put_str(1, "foo")?;
+        handle.put_str(2, "bar")?;
+        handle.put_str(3, "baz")?;
+
+        let summary = handle.delete_keys(vec![1, 3, 4, 3])?;
+        assert_eq!(
+            summary,
+            DeleteSummary {
+                deleted: 2,
+                absent: 2
+            }
+        );
+        assert_eq!(handle.count(), 1);
+        assert_eq!(handle.get_string(2)?, Some("bar".to_owned()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn put_records_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        handle.put_str(1, "old")?;
+
+        let input = "key\tvalue\n1\tfoo\n2\tbar\tbaz\nx\tqux\n3\n4\t\n";
+        let mut skipped = Vec::new();
+        let summary = handle.put_records(
+            csv::TsvReader::new(input.as_bytes()),
+            Some(2),
+            false,
+            |line, _| skipped.push(line),
+        )?;
+        assert_eq!(
+            summary,
+            ImportSummary {
+                imported: 3,
+                overwritten: 1,
+                skipped: 2
+            }
+        );
+        assert_eq!(skipped, vec![4, 5]);
+        assert_eq!(handle.get_string(1)?, Some("foo".to_owned()));
+        assert_eq!(handle.get_string(2)?, Some("bar\tbaz".to_owned()));
+        assert_eq!(handle.get_string(4)?, Some("".to_owned()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn export_csv_round_trips() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut source = StorageHandle::new(storage);
+        source.put_str(1, "plain")?;
+        source.put_str(2, "a,\"b\"\nc")?;
+        source.put_bytes(3, &[0, 159, 146, 150])?;
+        source.put_str(4, "")?;
+        source.put_str(u128::max_value(), "base64:not encoded")?;
+
+        let mut csv = Vec::new();
+        assert_eq!(source.export_csv(&mut csv)?, 5);
+
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut target = StorageHandle::new(storage);
+        let summary = target.import_csv(&csv[..], true, |_, _| {})?;
+        assert_eq!(summary.imported, 5);
+        assert!(target.diff(&mut source)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn copy_to_works() -> TestResult {
+        let dir = track_io!(TempDir::new("cannyls_test"))?;
+
+        let nvm = track!(FileNvm::create(dir.path().join("src.lusf"), 4_000_000))?;
+        let mut source = StorageHandle::new(track!(Storage::create(nvm))?);
+        for key in 0..2000 {
+            source.put_str(key, &key.to_string())?;
+        }
+        source.put_bytes(u128::max_value(), &[0, 255])?;
+
+        // コピー先は容量が異なっていてもよい
+        let dest_path = dir.path().join("dest.lusf");
+        let nvm = track!(FileNvm::create(&dest_path, 8_000_000))?;
+        let mut dest = StorageHandle::new(track!(Storage::create(nvm))?);
+        let mut progress = Vec::new();
+        let copied =
+            source.copy_to_with_progress(&mut dest, |n, total| progress.push((n, total)))?;
+        assert_eq!(copied, 2001);
+        assert_eq!(progress, vec![(1024, 2001), (2001, 2001)]);
+        assert!(source.diff(&mut dest)?.is_empty());
+
+        // 開き直しても全てのlumpが残っている
+        drop(dest);
+        let mut dest = StorageHandle::create(&dest_path)?;
+        assert_eq!(dest.count(), 2001);
+        assert_eq!(dest.get_bytes(u128::max_value())?, Some(vec![0, 255]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn clear_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        assert_eq!(handle.clear()?, 0);
+
+        for key in 0..10 {
+            handle.put_str(key, "foo")?;
+        }
+        handle.put_str(u128::max_value(), "bar")?;
+        let capacity = handle.header().data_region_size;
+
+        assert_eq!(handle.clear()?, 11);
+        assert_eq!(handle.count(), 0);
+        assert_eq!(handle.usage()?.allocated_bytes, 0);
+        assert_eq!(handle.header().data_region_size, capacity);
+
+        // 消去後も書き込める
+        handle.put_str(1, "baz")?;
+        assert_eq!(handle.get_string(1)?, Some("baz".to_owned()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_range_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+        for key in &[1, 2, 3, 5, 8] {
+            handle.put_str(*key, "value")?;
+        }
+
+        assert_eq!(handle.delete_range(3, 3)?, 0); // 空の範囲
+        assert_eq!(handle.delete_range(5, 1)?, 0);
+        assert_eq!(handle.delete_range(9, 100)?, 0); // 存在するkeyを含まない範囲
+        assert_eq!(handle.delete_range(2, 8)?, 3);
+        assert_eq!(handle.all_keys(), vec![LumpId::new(1), LumpId::new(8)]);
+
+        // 両端を含む範囲
+        assert_eq!(handle.delete_between(8, 1)?, 0);
+        assert_eq!(handle.delete_between(2, 7)?, 0);
+        assert_eq!(handle.delete_between(1, 8)?, 2);
+        assert!(handle.all_keys().is_empty());
+
+        Ok(())
+    }
+
+    const BOUNDARY_KEYS: &[u128] = &[
+        0,
+        1,
+        0xffff_ffff_ffff_ffff,
+        0x1_0000_0000_0000_0000,
+        0xffff_ffff_ffff_fffe_ffff_ffff_ffff_ffff,
+        0xffff_ffff_ffff_ffff_ffff_ffff_ffff_fffe,
+        0xffff_ffff_ffff_ffff_ffff_ffff_ffff_ffff,
+    ];
+
+    #[test]
+    fn boundary_keys_work() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        for key in BOUNDARY_KEYS {
+            assert!(handle.put_str(*key, &key.to_string())?);
+        }
+        for key in BOUNDARY_KEYS {
+            assert!(handle.contains(*key)?);
+            assert_eq!(handle.get_string(*key)?, Some(key.to_string()));
+        }
+        let ids = handle.all_keys();
+        assert_eq!(ids.len(), BOUNDARY_KEYS.len());
+        let mut hex = Vec::new();
+        write_lumpids(&ids, ListFormat::Hex, &mut hex).unwrap();
+        assert!(String::from_utf8(hex)
+            .unwrap()
+            .ends_with("ffffffffffffffffffffffffffffffff\n"));
+
+        // u64の境界をまたぐ範囲
+        assert_eq!(
+            handle.delete_range(0xffff_ffff_ffff_ffff, 0x1_0000_0000_0000_0001)?,
+            2
+        );
+        // 最大のkeyを含む範囲
+        assert_eq!(handle.delete_range_from(u128::max_value() - 1)?, 2);
+        assert!(!handle.contains(u128::max_value())?);
+        assert_eq!(handle.delete_between(1, u128::max_value())?, 2);
+        assert_eq!(handle.delete_between(0, u128::max_value())?, 1);
+        assert!(handle.all_keys().is_empty());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,249 @@
+use schemes;
+
+use std::str::FromStr;
+
+// CLIの`--key`や対話モードで指定されたkey(lumpid)を解釈する
+// 以下の形式を受け付ける
+//   - 10進数: `3735928559`
+//   - `0x`付きの16進数: `0xdeadbeef`
+//   - 32桁の16進数: `000000000000000000000000deadbeef` (`a-f`を1文字以上含むもののみ)
+//   - UUID形式: `550e8400-e29b-41d4-a716-446655440000` (ハイフンを除いた32桁の16進数として扱う)
+//   - スキームに従った構成要素: `frugalos:1:1:5` (src/schemes.rs を参照)
+// 数字のみで構成された32文字の文字列は10進数とも16進数とも読めるため、曖昧としてエラーにする
+pub fn parse_key(s: &str) -> Result<u128, String> {
+    let s = s.trim();
+    if let Some(colon) = s.find(':') {
+        let (name, components) = (&s[..colon], &s[colon + 1..]);
+        return match schemes::find(name) {
+            Some(scheme) => scheme.encode(components),
+            None => Err(format!("`{}` is an unknown lumpid scheme", name)),
+        };
+    }
+    if is_uuid(s) {
+        let digits = s.replace('-', "");
+        return u128::from_str_radix(&digits, 16)
+            .map_err(|_| format!("`{}` is not a valid UUID", s));
+    }
+    let (digits, radix) = if s.starts_with("0x") || s.starts_with("0X") {
+        (&s[2..], 16)
+    } else if s.len() == 32 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        if s.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!(
+                "`{}` is ambiguous (32 digits may be decimal or hex), use a 0x prefix or a UUID for hex",
+                s
+            ));
+        }
+        (s, 16)
+    } else {
+        (s, 10)
+    };
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(format!(
+            "`{}` is not a valid key (expected a decimal number, 0x-prefixed hex, 32 hex digits or a UUID)",
+            s
+        ));
+    }
+    // 桁の検査は済んでいるので、ここで失敗するのは128bitを超える場合のみ
+    u128::from_str_radix(digits, radix).map_err(|_| format!("key `{}` does not fit in 128 bits", s))
+}
+
+// `8-4-4-4-12`桁の16進数をハイフンで区切った形式かどうか
+fn is_uuid(s: &str) -> bool {
+    s.len() == 36
+        && s.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+// 1行に1つのkeyを記述したテキストを解釈する
+// 空行と`#`で始まる行は無視し、不正な行があればその行番号を含むエラーを返す
+pub fn parse_key_list(text: &str) -> Result<Vec<u128>, String> {
+    let mut keys = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        keys.push(parse_key(line).map_err(|e| format!("line {}: {}", i + 1, e))?);
+    }
+    Ok(keys)
+}
+
+// keyを出力する際の形式
+// Dec: 10進数, Hex: `0x`付きの16進数, Uuid: UUID形式 (いずれも`parse_key`でそのまま読み込める)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFormat {
+    Dec,
+    Hex,
+    Uuid,
+}
+impl FromStr for KeyFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dec" => Ok(KeyFormat::Dec),
+            "hex" => Ok(KeyFormat::Hex),
+            "uuid" => Ok(KeyFormat::Uuid),
+            _ => Err(format!(
+                "`{}` is an invalid key format (dec, hex or uuid)",
+                s
+            )),
+        }
+    }
+}
+
+pub fn format_key(key: u128, format: KeyFormat) -> String {
+    match format {
+        KeyFormat::Dec => key.to_string(),
+        KeyFormat::Hex => format!("{:#x}", key),
+        KeyFormat::Uuid => {
+            let hex = format!("{:032x}", key);
+            format!(
+                "{}-{}-{}-{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..]
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimal_keys_work() {
+        assert_eq!(parse_key("0"), Ok(0));
+        assert_eq!(parse_key("3735928559"), Ok(0xdead_beef));
+        assert_eq!(
+            parse_key(&u128::max_value().to_string()),
+            Ok(u128::max_value())
+        );
+    }
+
+    #[test]
+    fn hex_keys_work() {
+        assert_eq!(parse_key("0xdeadbeef"), Ok(0xdead_beef));
+        assert_eq!(parse_key("0XDEADBEEF"), Ok(0xdead_beef));
+        assert_eq!(
+            parse_key("000000000000000000000000deadbeef"),
+            Ok(0xdead_beef)
+        );
+        for &key in &[
+            0,
+            1,
+            0xdead_beef,
+            u128::from(u64::max_value()),
+            u128::max_value(),
+        ] {
+            assert_eq!(parse_key(&format!("{:#x}", key)), Ok(key));
+            assert_eq!(parse_key(&key.to_string()), Ok(key));
+        }
+        assert_eq!(
+            parse_key(&format!("{:032x}", u128::max_value())),
+            Ok(u128::max_value())
+        );
+    }
+
+    #[test]
+    fn ambiguous_keys_are_rejected() {
+        // 数字のみの32文字は10進数としても16進数としても読める
+        for s in &[
+            "00000000000000000000000000000010",
+            "12345678901234567890123456789012",
+        ] {
+            assert!(parse_key(s).unwrap_err().contains("ambiguous"), "{}", s);
+        }
+        assert_eq!(parse_key("0x00000000000000000000000000000010"), Ok(0x10));
+        assert_eq!(parse_key("00000000-0000-0000-0000-000000000010"), Ok(0x10));
+        // 31桁や33桁の数字は曖昧ではないので10進数として扱う
+        assert_eq!(
+            parse_key("1234567890123456789012345678901"),
+            Ok(1_234_567_890_123_456_789_012_345_678_901)
+        );
+        assert_eq!(
+            parse_key("123456789012345678901234567890123"),
+            Ok(123_456_789_012_345_678_901_234_567_890_123)
+        );
+    }
+
+    #[test]
+    fn format_key_works() {
+        assert_eq!(format_key(0xdead_beef, KeyFormat::Dec), "3735928559");
+        assert_eq!(format_key(0xdead_beef, KeyFormat::Hex), "0xdeadbeef");
+        assert_eq!(format_key(0, KeyFormat::Hex), "0x0");
+        assert_eq!(
+            format_key(0xdead_beef, KeyFormat::Uuid),
+            "00000000-0000-0000-0000-0000deadbeef"
+        );
+        assert_eq!("hex".parse(), Ok(KeyFormat::Hex));
+        assert!("oct".parse::<KeyFormat>().is_err());
+        for &key in &[0, 1, u128::max_value()] {
+            for &format in &[KeyFormat::Dec, KeyFormat::Hex, KeyFormat::Uuid] {
+                assert_eq!(parse_key(&format_key(key, format)), Ok(key));
+            }
+        }
+    }
+
+    #[test]
+    fn uuid_keys_work() {
+        assert_eq!(
+            parse_key("550e8400-e29b-41d4-a716-446655440000"),
+            Ok(0x550e_8400_e29b_41d4_a716_4466_5544_0000)
+        );
+        assert_eq!(
+            parse_key("550E8400-E29B-41D4-A716-446655440000"),
+            Ok(0x550e_8400_e29b_41d4_a716_4466_5544_0000)
+        );
+        assert!(parse_key("550e8400e-29b-41d4-a716-446655440000").is_err());
+        assert!(parse_key("550e8400-e29b-41d4-a716-44665544000g").is_err());
+    }
+
+    #[test]
+    fn overflowing_keys_are_rejected() {
+        let too_large = "340282366920938463463374607431768211456"; // u128::MAX + 1
+        assert!(parse_key(too_large).unwrap_err().contains("128 bits"));
+        assert!(parse_key("0x1ffffffffffffffffffffffffffffffff")
+            .unwrap_err()
+            .contains("128 bits"));
+    }
+
+    #[test]
+    fn malformed_keys_are_rejected() {
+        assert!(parse_key("").is_err());
+        assert!(parse_key("0x").is_err());
+        assert!(parse_key("deadbeef").is_err());
+        assert!(parse_key("-1").is_err());
+        assert!(parse_key("12ab").is_err());
+    }
+
+    #[test]
+    fn scheme_keys_work() {
+        assert_eq!(
+            parse_key("frugalos:1:1:5"),
+            Ok(0x0100_0000_0000_0001_0000_0000_0000_0005)
+        );
+        assert!(parse_key("frugalos:1:5").is_err());
+        assert!(parse_key("unknown:1:1:5")
+            .unwrap_err()
+            .contains("unknown lumpid scheme"));
+    }
+
+    #[test]
+    fn key_lists_work() {
+        let text = "# keys to delete\n1\n\n  0x10  \nfrugalos:1:1:5\n";
+        assert_eq!(
+            parse_key_list(text),
+            Ok(vec![1, 0x10, 0x0100_0000_0000_0001_0000_0000_0000_0005])
+        );
+        assert_eq!(parse_key_list(""), Ok(vec![]));
+        assert!(parse_key_list("1\nfoo\n")
+            .unwrap_err()
+            .starts_with("line 2:"));
+    }
+}
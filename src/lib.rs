@@ -1,7 +1,32 @@
 #[macro_use]
 extern crate trackable;
+extern crate base64;
 extern crate cannyls;
+extern crate regex;
+extern crate rustyline;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 #[cfg(test)]
 extern crate tempdir;
+extern crate uuid;
+
+macro_rules! track_io {
+    ($expr:expr) => {
+        $expr.map_err(|e: ::std::io::Error| track!(cannyls::Error::from(e)))
+    };
+}
 
+pub mod bench;
+pub mod command;
+pub mod csv;
+pub mod fixtures;
 pub mod handle;
+pub mod key;
+pub mod nvm;
+pub mod repl;
+pub mod rng;
+pub mod schemes;
+pub mod units;
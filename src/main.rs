@@ -8,24 +8,40 @@ extern crate clap;
 #[macro_use]
 extern crate trackable;
 
+extern crate atty;
 extern crate cannyls;
 extern crate kanils;
-extern crate regex;
 extern crate rustyline;
 
-use kanils::handle::StorageHandle;
+use kanils::bench::{self, BenchRecord, Latencies, Progress, ResultFormat, ValueFill, Values};
+use kanils::command::{self, Expectation, Status, NON_INTERACTIVE_MESSAGE, READ_ONLY_MESSAGE};
+use kanils::csv;
+use kanils::fixtures;
+use kanils::handle::{
+    write_journal_stats, write_lump_stat, write_lump_stats, write_storage_usage, ConflictPolicy,
+    JournalEntryType, JournalFilter, JournalPositions, KeyOrder, ListFormat, StorageHandle,
+};
+use kanils::key::{format_key, parse_key, parse_key_list, KeyFormat};
+use kanils::nvm::{self, temporary_path, ReadOnlyNvm};
+use kanils::repl::{self, ReplHelper};
+use kanils::rng::{Rng, Zipfian};
+use kanils::schemes;
+use kanils::units::{format_bytes, format_size, parse_size};
 
 use cannyls::block::BlockSize;
-use cannyls::lump::LumpId;
-use cannyls::nvm::FileNvm;
+use cannyls::lump::{LumpData, LumpId};
+use cannyls::nvm::{FileNvm, NonVolatileMemory};
 use cannyls::storage::{Storage, StorageBuilder};
 
-use regex::Regex;
-
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str;
 use std::time::SystemTime;
 
@@ -35,60 +51,385 @@ arg_enum! {
     #[derive(Debug)]
     enum Command {
         // capacityバイトの容量を持つlusfストレージを新たに生成する
-        // kanils Create --storage=storage_path --capacity=num
-        // (storage_pathが既に存在する場合には何もしない)
+        // --block-size でブロックサイズを指定できる (デフォルトは512バイト)
+        // --journal-ratio でストレージ全体に対するジャーナル領域の比率を、
+        // --journal-size でジャーナル領域のバイト数を指定できる (両者は同時に指定できない)
+        // (省略した場合はデータ領域に収まるlumpの数から求める)
+        // ストレージは一時ファイルに作成してからrenameするため、中断されてもstorage_pathに作りかけのファイルは残らない
+        // storage_pathが既に存在する場合はエラーとする (--force を指定した場合は作り直す)
+        // kanils Create --storage=storage_path --capacity=num [--block-size=num] [--journal-ratio=num | --journal-size=num] [--force]
         Create,
 
         // lusfストレージ中のデータをダンプする
-        // kanils Dump --storage=storage_path
+        // --format=json を指定した場合はJSON配列として出力する
+        // --min-key, --max-key を指定した場合は keyが[min, max]の範囲(両端を含む)のlumpのみを出力する
+        // --limit を指定した場合は先頭から最大その件数のみを出力する (巨大なストレージを覗く場合に使う)
+        // --offset を指定した場合は先頭からその件数を読み飛ばす (--limit と組み合わせてページ単位で覗ける)
+        // --sort で出力順を指定できる (asc: keyの昇順 (デフォルト), desc: keyの降順)
+        // --offset, --limit は並べ替えた後に適用する
+        // kanils Dump --storage=storage_path [--format=json] [--min-key=lumpid] [--max-key=lumpid] [--sort=asc|desc] [--offset=n] [--limit=n]
         Dump,
 
         // lusfストレージ中に存在するlumpid一覧を出力する
-        // kanils List --storage=storage_path
+        // --format で出力形式を指定できる
+        //   text: LumpId("...") 形式 (デフォルト), plain: 10進数, hex: 32桁の16進数, json: 文字列の配列
+        // --with-size を指定した場合は各lumpidの後に値のバイト長を出力する
+        // --min-key, --max-key を指定した場合は keyが[min, max]の範囲(両端を含む)のlumpidのみを出力する
+        // --limit を指定した場合は先頭から最大その件数のみを、--offset を指定した場合は先頭からその件数を読み飛ばして出力する
+        // --sort で出力順を指定できる (asc: keyの昇順 (デフォルト), desc: keyの降順)
+        // --offset, --limit は並べ替えた後に適用する
+        // kanils List --storage=storage_path [--format=text|plain|hex|json] [--with-size] [--min-key=lumpid] [--max-key=lumpid] [--sort=asc|desc] [--offset=n] [--limit=n]
         List,
 
+        // lusfストレージ中に存在するlumpidのうち、keyが[start, end)の範囲に含まれるものを出力する
+        // startは範囲に含まれ、endは含まれない
+        // kanils ListRange --storage=storage_path --start=lumpid --end=lumpid [--format=text|plain|hex|json]
+        ListRange,
+
         // lusfストレージに、keyをkey, valueをstringとしてkey-value組を追加する
         // 既にkeyが存在する場合は上書きする挙動に注意
-        // kanils Put --storage=storage_path --key=lumpid --data=string
+        // --embed を指定した場合、値が埋め込める大きさ(LumpData::MAX_EMBEDDED_SIZE 以下)であれば
+        // データ領域を確保せずにジャーナル領域に埋め込む
+        // --embedded を指定した場合は必ず埋め込み、埋め込めない大きさであればエラーにする
+        // --value-stdin もしくは --value=- を指定した場合は、標準入力の終わりまでを読み込んでそのままの (UTF-8とは限らない) バイト列を値とする
+        // 値が LumpData::MAX_SIZE より大きい場合はエラーにする
+        // kanils Put --storage=storage_path --key=lumpid (--value=string | --value=- | --value-stdin) [--embed | --embedded]
         Put,
 
+        // lusfストレージの指定したkeyの値の末尾にvalueを連結し、連結後の値のバイト長を出力する
+        // keyが存在しない場合はvalueを値として新たに追加する
+        // 値は Put と同じく --value, --value=-, --value-stdin で指定する (連結後の値はデータ領域に書き込む)
+        // kanils Append --storage=storage_path --key=lumpid (--value=string | --value=- | --value-stdin)
+        Append,
+
         // lusfストレージの指定したkeyを持つ値を取得する
         // 存在しないkeyが指定された場合はその旨が出力される
-        // kanils Get --storage=storage_path --key=lumpid
+        // --keys=1,2,3 のようにカンマで区切って複数のkeyを指定した場合は、1回のオープンで全てのkeyを取得し
+        // 指定した順に `key => 値` (存在しなければ `key => no entry`) を1行ずつ出力する
+        // 存在しないkeyがあれば終了コード1で終了する
+        // --quiet を指定した場合は値を出力せず、Existsと同じく終了コードのみで結果を返す
+        // --raw を指定した場合は値のバイト列だけを (`get =>` や引用符、末尾の改行なしで) 標準出力に書き出す
+        // 存在しないkeyの場合は標準出力には何も書き出さずに終了コード1で終了する
+        // kanils Get --storage=storage_path (--key=lumpid | --keys=lumpid,...) [--quiet]
+        // kanils Get --storage=storage_path --key=lumpid --raw
         Get,
 
+        // lusfストレージから、keyが[start, end)の範囲に含まれるkey-value組を全て出力する
+        // startは範囲に含まれ、endは含まれない
+        // kanils GetRange --storage=storage_path --start=lumpid --end=lumpid
+        GetRange,
+
+        // lusfストレージに指定したkeyが存在するかどうかを、値を読み込まずに調べる
+        // 存在する場合は true を出力して終了コード0、存在しない場合は false を出力して終了コード1で終了する
+        // --quiet を指定した場合は何も出力せず、終了コードのみで結果を返す
+        // kanils Contains --storage=storage_path --key=lumpid [--quiet]
+        Contains,
+
+        // Contains --quiet の別名 (シェルスクリプト向け)
+        // kanils Exists --storage=storage_path --key=lumpid
+        Exists,
+
+        // lusfストレージの指定したkeyを持つ値のバイト長を出力する (格納場所は Stat で確認する)
+        // 存在しないkeyが指定された場合は no entry を出力して終了コード1で終了する
+        // kanils Size --storage=storage_path --key=lumpid
+        Size,
+
+        // lusfストレージの指定したkeyを持つ値のバイト長と格納場所を出力する
+        // 格納場所はジャーナル領域への埋め込みか、データ領域上の位置 (開始ブロックとブロック数) のいずれか
+        // keyが存在しない場合は終了コード1で終了する
+        // kanils Stat --storage=storage_path --key=lumpid
+        Stat,
+
         // lusfストレージの指定したkeyを削除する
-        // 存在しないkeyが指定された場合はその旨が出力される
-        // kanils Delete --storage=storage_path --key=lumpid
+        // 存在しないkeyが指定された場合はその旨を出力して終了コード1で終了する
+        // 実行前に確認を求める (--yes を指定した場合は確認しない)
+        // kanils Delete --storage=storage_path --key=lumpid [--yes]
         Delete,
 
+        // lusfストレージから、keyが[start, end)の範囲に含まれるlumpを一度に削除する
+        // --end を省略した場合は、start以上の全てのkey(u128の最大値を含む)が対象になる
+        // --start, --end の代わりに --min-key, --max-key で両端を含む範囲[min, max]を指定することもできる
+        // 削除された件数と、範囲内でエントリが存在しなかったkeyの数が出力される
+        // --dry-run を指定した場合は削除せずに、削除されるlumpidとその件数を出力する
+        // 実行前に確認を求める (--yes を指定した場合は確認しない)
+        // kanils DeleteRange --storage=storage_path (--start=lumpid [--end=lumpid] | --min-key=lumpid --max-key=lumpid) [--dry-run] [--yes]
+        DeleteRange,
+
+        // lusfストレージ中に存在するlumpの数、値のバイト長の合計と平均を出力する
+        // --format=plain の場合はlumpの数のみを (値を読み込まずに) 出力し、--format=json の場合はJSONで出力する
+        // kanils Count --storage=storage_path [--format=text|plain|json]
+        Count,
+
+        // lusfストレージのデータ領域とジャーナル領域の使用状況を出力する
+        // 断片化の度合いは、空き領域のうち最大の連続した空き領域に含まれない割合として表す
+        // kanils Usage --storage=storage_path
+        Usage,
+
+        // 全てのlumpを読み込み、エラーなく読み込めるかどうかを検査する
+        // 読み込めなかったlumpidと理由を出力し、最後に `N lumps OK, M failed` を出力する
+        // ストレージは常に書き込み権限なしで開く。読み込めないlumpがあれば終了コード3で終了する
+        // kanils Verify --storage=storage_path
+        Verify,
+
         // lusfストレージ中のヘッダ情報を出力する
         // ヘッダ情報についての詳細は https://github.com/frugalos/cannyls/wiki/Storage-Format を参照
-        // kanils Header --storage=storage_path
+        // --format=json を指定した場合は、各項目を1つのJSONオブジェクトとして出力する
+        // kanils Header --storage=storage_path [--format=json]
         Header,
 
+        // ヘッダ情報、lumpの数、データ領域の使用量、ジャーナル領域の使用率をまとめて出力する
+        // --format=json を指定した場合は1つのJSONオブジェクトとして出力する
+        // kanils Info --storage=storage_path [--format=json]
+        Info,
+
+        // lusfストレージの全てのlumpを削除し、ジャーナル領域に対してfull GCを行う
+        // 容量やジャーナル領域の比率は変わらない。削除した件数が出力される
+        // 実行前に確認を求める (--yes を指定した場合は確認しない)
+        // kanils Clear --storage=storage_path [--yes]
+        Clear,
+
         // lusfストレージ中のジャーナル領域の内容を出力する
-        // kanils Journal --storage=storage_path
+        // --format=json を指定した場合は、1行目に各位置 (unreleased_head, head, tail) を、
+        // 続けて1エントリを1行ずつ (レコードの種類, lumpid, 位置, 長さ) JSONで出力する
+        // --key を指定した場合は、そのlumpを対象とするエントリ (範囲に含むDeleteRangeを含む) のみを出力する
+        // --entry-type を指定した場合は、その種類のエントリのみを出力する (deleteはDeleteRangeも含む)
+        // --limit を指定した場合は、条件に合うエントリを先頭から最大その件数のみを出力する
+        // kanils Journal --storage=storage_path [--format=text|json] [--key=lumpid] [--entry-type=put|delete|embed] [--limit=n]
         Journal,
 
+        // ジャーナル領域のリングバッファの使用状況を出力する
+        // 各位置 (unreleased head, head, tail)、容量、headからtailまでの使用中のバイト数、unreleased headからheadまでの解放待ちのバイト数、
+        // 使用率と、レコードの種類 (Put, Embed, Delete, DeleteRange, その他) ごとのエントリ数を出力する
+        // kanils JournalStat --storage=storage_path
+        JournalStat,
+
         // lusfストレージ中のジャーナル領域に対してfull GCを行う
-        // kanils JournalGC --storage=storage_path
+        // GCの前後のジャーナルの位置 (unreleased head, head, tail)、解放されたエントリ数とバイト数、headが進んだバイト数、GC後の使用率を出力する
+        // 実行前に確認を求める (--yes を指定した場合は確認しない)
+        // kanils JournalGC --storage=storage_path [--yes]
         JournalGC,
 
+        // ジャーナルをディスクに同期する (putなどの結果を確実に永続化する)
+        // kanils Sync --storage=storage_path
+        Sync,
+
+        // `key,value` 形式のCSVファイルを読み込み、全ての行を1回のオープンでputする
+        // 不正な行は行番号とともに報告して読み飛ばす
+        // --strict を指定した場合は書き込む前に全ての行を検査し、不正な行があれば何も書き込まずに終了する
+        // kanils Import --storage=storage_path --input=path [--strict]
+        Import,
+
+        // 全てのkey-value組を Import で読み込める `key,value` 形式のCSVファイルに書き出す
+        // UTF-8として解釈できない値は `base64:` を付けてbase64で書き出される
+        // kanils Export --storage=storage_path --output=path
+        Export,
+
+        // `key<TAB>value` 形式のファイルを読み込み、全ての行を1回のオープンでputする
+        // --format=csv を指定した場合は Import と同じ `key,value` 形式のCSVとして読み込む
+        // --sync-interval で指定した件数ごとにジャーナルを同期する (デフォルトは1000件、最後にも必ず同期する)
+        // 不正な行の扱いは Import と同じ。新たに追加した件数と上書きした件数が出力される
+        // kanils BatchPut --storage=storage_path --input=path [--format=tsv|csv] [--sync-interval=num] [--strict]
+        BatchPut,
+
+        // 1行に1つのkeyを記述したファイルを読み込み、全てのkeyを1回のオープンで削除する
+        // 空行と `#` で始まる行は無視する。ジャーナルの同期は最後に一度だけ行う
+        // 削除した件数と、存在しなかった件数が出力される
+        // kanils BatchDelete --storage=storage_path --input=path
+        BatchDelete,
+
+        // ストレージの全てのlumpを、新たに作成したdestのストレージにkeyを保ったままコピーする
+        // コピー元は書き込み権限なしで開き、lumpは1件ずつ読み書きする (空き領域や削除済みの領域はコピーされない)
+        // --capacity, --block-size を省略した場合はstorageと同じ容量、ブロックサイズで作成する
+        // --capacity がコピー元の使用量より小さい場合や、コピーに失敗した場合はdestを残さない
+        // destが既に存在する場合は --force を指定しない限り何もせずに終了する
+        // kanils Copy --storage=storage_path --dest=storage_path [--capacity=num] [--block-size=num] [--force]
+        Copy,
+
+        // データ領域の容量をcapacityバイトに変更したストレージを作成し、全てのlumpを移した上で元のファイルと置き換える
+        // 新しいストレージは一時ファイル (storage_path.tmp) に作成し、成功した場合にのみrenameで置き換える
+        // 途中で失敗した場合、元のファイルには手を加えない
+        // 縮小する場合、使用中のデータが収まらなければエラーになる
+        // kanils Resize --storage=storage_path --capacity=num
+        Resize,
+
+        // 全てのlumpを新たなストレージに詰めて書き直し、データ領域の断片化を解消する
+        // Resizeと同じく一時ファイルに書き直してから置き換えるため、中断されても元のファイルは壊れない
+        // 実行前後の空き領域の状況を出力する
+        // kanils Compact --storage=storage_path
+        Compact,
+
+        // 2つのストレージの内容を比較し、片方にのみ存在するkeyと値が異なるkeyの数を出力する
+        // --verbose を指定した場合は差分のある全てのkeyを出力する
+        // 同一であれば終了コード0、差分があれば終了コード1で終了する
+        // kanils Diff --storage=storage_path --other=storage_path [--verbose]
+        Diff,
+
+        // otherのストレージの全てのlumpをstorageのストレージに書き込む
+        // 両方に存在するkeyは --on-conflict に従って扱う
+        //   fail (error): 何も書き込まずにエラーにする (デフォルト)
+        //   skip: storageの値を残す
+        //   overwrite: otherの値で上書きする
+        // --other の代わりに --from と書いてもよい。ジャーナルは最後に同期する
+        // kanils Merge --storage=storage_path --other=storage_path [--on-conflict=skip|overwrite|fail|error]
+        Merge,
+
+        // sourceのストレージを正として、storageのストレージを修復する
+        // sourceにのみ存在するlumpや値が異なるlumpはsourceからコピー(上書き)される
+        // --delete-extra を指定した場合はstorageにのみ存在するlumpを削除する
+        // --dry-run を指定した場合は修復の計画のみを出力する
+        // kanils Repair --storage=storage_path --source=storage_path [--delete-extra] [--dry-run]
+        Repair,
+
+        // 組み込みのレシピに従って、再現可能なフィクスチャ用のストレージを新たに生成する
+        // 同じレシピとシードからはバイト単位で同一のストレージが生成される
+        // 利用可能なレシピは src/fixtures.rs の RECIPES を参照
+        // kanils GenFixture --storage=storage_path --recipe=name [--seed=num]
+        GenFixture,
+
         // 存在するlusfストレージを開き
         // 対話的に Dump, List, Put, Get, Delete, Header の操作を試すことができる
-        // kanils Open --storage=storage_path
+        // --read-only を指定した場合は書き込み権限なしで開き、変更を伴うコマンドは拒否される
+        // 入力の履歴は ~/.kanils_history (--history-file で変更できる) に保存され、次回の起動時に読み込まれる
+        // --no-history を指定した場合は履歴を読み書きしない
+        // --auto-gc-threshold を指定した場合は、変更を伴うコマンドの後にジャーナル領域の使用率を調べ、
+        // 指定した割合を超えていればジャーナルのfull GCを行ってその旨を出力する (デフォルトでは行わない)
+        // --confirm-deletes を指定した場合は、delete の実行前に確認を求める (--yes を併せて指定した場合は求めない)
+        // 標準入力が端末でない場合は、プロンプトも履歴も使わずに入力の終わりまでコマンドを実行する
+        // (`echo list | kanils Open --storage=storage_path`)
+        // kanils Open --storage=storage_path [--read-only] [--history-file=path] [--no-history] [--auto-gc-threshold=ratio] [--confirm-deletes] [--yes]
         Open,
 
+        // ファイルに書かれた対話モードのコマンドを1行ずつ実行する
+        // 空行と `#` で始まる行(コメント)は読み飛ばす
+        // エラーになった行があればそこで中断する (--continue-on-error を指定した場合は最後まで実行し、終了コード2で終了する)
+        // --expect で `get 5 == "foo"` のような期待を指定すると、スクリプトの実行後に評価され
+        // 期待と異なる結果があれば終了コード1で終了する
+        // 右辺には "値", [key, ...], ok, not_found, error のいずれかを書くことができる
+        // --format=json を指定した場合は各行の実行結果をJSONで出力する
+        // kanils Script --storage=storage_path --file=path [--continue-on-error] [--expect=expectation ...] [--format=text|json]
+        Script,
+
+        // Script と同じく対話モードのコマンドを1行ずつ実行し、各行の出力の先頭に行番号を付ける
+        // --script=- を指定した場合は標準入力からコマンドを読み込む
+        // エラーになった行があればそこで中断する (--keep-going を指定した場合は最後まで実行する)
+        // 全ての行が成功すれば終了コード0、エラーになった行があれば終了コード2で終了する
+        // kanils Run --storage=storage_path --script=path|- [--keep-going]
+        Run,
+
         // 新たにlusfストレージを作成し、1件sizeバイト長データを、count個書き込む
         // 書き込みのみを行う簡易ベンチマークツール
-        // kanils WBench --stoage=storage_path --count=number --size=number
+        // スループット (ops/s) と、1件ごとの所要時間のパーセンタイル (p50, p90, p99, p999, max) を出力する
+        // --embedded を指定した場合は値をジャーナル領域に埋め込んで書き込む (sizeは LumpData::MAX_EMBEDDED_SIZE 以下)
+        // --pattern で書き込むkeyの順序を指定できる (--seed で乱数のシードを指定できる)
+        //   sequential: 0..count の昇順 (デフォルト)
+        //   random: 0..count をシャッフルした順 (keyは重複しない)
+        //   zipfian: 0..count から小さいkeyほど選ばれやすいZipf分布に従って選ぶ (同じkeyへの上書きを含む)
+        // --sync-interval (--sync-every) で指定した件数ごとにジャーナルを同期する (デフォルトは1件ごと、最後にも必ず同期する)
+        // --no-sync を指定した場合は一度も同期しない (ページキャッシュ上での上限性能の目安)
+        // 同期した回数も出力する
+        // --progress-interval を指定した場合は、その件数ごとに書き込んだ件数、割合、スループットを標準エラー出力に表示する
+        // --existing を指定した場合は新たにストレージを作成せず、既存のストレージに書き込む (WRBenchも同様)
+        //   既存のlumpと重ならないよう、最大のkeyの次から count 件のkeyを使う (--overwrite の場合は 0..count を使い、既存のlumpを上書きする)
+        //   データ領域に count 件分の空きがなければ、空いている大きさを示して終了する
+        // kanils WBench --stoage=storage_path --count=number --size=number [--pattern=sequential|random|zipfian] [--seed=num] [--embedded] [--sync-interval=num | --no-sync] [--progress-interval=num] [--existing [--overwrite]]
         WBench,
 
+        // 存在するlusfストレージから、keyが0..countのlump(1件sizeバイト長)を読み込む
+        // 読み込みのみを行う簡易ベンチマークツール (WBenchで作成したストレージを想定)
+        // 存在しないkeyやsizeと長さが異なるlumpがあればエラーになる
+        // --pattern を指定した場合は、ストレージに存在するkeyから count 件を選んで読み込む (--size は不要)
+        //   sequential: keyの昇順に選ぶ (lump数が count より少なければ先頭に戻る)
+        //   random: 一様に選ぶ (--seed で乱数のシードを指定できる)
+        //   zipfian: keyの小さいlumpほど選ばれやすいZipf分布に従って選ぶ
+        // ストレージは常に書き込み権限なしで開く
+        // スループット (ops/s, MB/s) と1件あたりの所要時間 (min, avg, max とパーセンタイル) を出力する
+        // kanils RBench --storage=storage_path --count=number (--size=number | --pattern=sequential|random|zipfian [--seed=num])
+        RBench,
+
         // 新たにlusfストレージを作成し、1件sizeバイト長データを、cout個書き込みつつ
         // 読み込みも行うような、書き込み読み込み混合の簡易ベンチマークツール
-        // kanils WRBench --storage=storage_path --count=number --size=number
+        // --marching-len (--window) で指定した件数 (デフォルトは100件) 書き込むごとに、--pattern に従って書き込み済みのkeyを同じ件数読み込む
+        //   marching: 直前に書き込んだ件数分 (デフォルト)
+        //   sequential: 書き込み済みのkeyを先頭から順に (末尾まで読んだら先頭に戻る)
+        //   random: 書き込み済みのkeyから一様に選ぶ (--seed で乱数のシードを指定できる)
+        //   zipfian: 書き込み済みのkeyから、小さいkeyほど選ばれやすいZipf分布に従って選ぶ
+        // スループット (ops/s) と、書き込みと読み込みそれぞれの所要時間のパーセンタイルを出力する
+        // いずれのベンチマークも、使用したパターンとシードを結果とともに出力する
+        // --fill で書き込む値の内容を指定できる (WBench, WRBench, MixBench)
+        //   zero: 全て0 (デフォルト), random: 書き込むたびに --seed から生成した乱数で埋める, pattern: 0, 1, 2, ... と1ずつ増えるバイト列
+        // また --result-format=json|csv を指定すると、条件と結果 (スループット、パーセンタイル) を1件のレコードとしても出力する
+        // --result-file を指定した場合はそのファイルに追記する (--quiet を指定すると通常の出力を省く)
+        // kanils WRBench --storage=storage_path --count=number --size=number [--pattern=marching|sequential|random|zipfian] [--seed=num] [--marching-len=num] [--existing [--overwrite]]
         WRBench,
+
+        // 新たにlusfストレージを作成し、keyが0..countのlump(1件sizeバイト長)を書き込んだ後 (この準備は計測に含めない)
+        // --read-ratio の割合で読み込みを、残りの割合で上書きを混ぜて --ops 件 (デフォルトは count 件) 実行する
+        // 対象のkeyは --pattern に従って 0..count から選ぶ
+        //   random: 一様に選ぶ (デフォルト)
+        //   zipfian: 小さいkeyほど選ばれやすいZipf分布に従って選ぶ
+        // 書き込みは --sync-interval で指定した件数ごとにジャーナルを同期する (デフォルトは1件ごと、--no-sync で同期しない)
+        // 読み込みと書き込みそれぞれの件数、スループット (ops/s)、所要時間のパーセンタイルを出力する
+        // kanils MixBench --storage=storage_path --count=number --size=number --read-ratio=ratio [--ops=number] [--pattern=random|zipfian] [--seed=num] [--sync-interval=num | --no-sync]
+        MixBench,
+
+        // 新たにlusfストレージを作成し、keyが0..countのlump(1件sizeバイト長)を書き込んだ後 (この準備は計測に含めない)
+        // 全てのlumpを削除する時間と、その後のジャーナルのfull GCの時間をそれぞれ計測する
+        // --pattern で削除する順序を指定できる (sequential: keyの昇順 (デフォルト), random: シャッフルした順)
+        // 削除は --sync-interval で指定した件数ごとにジャーナルを同期する (デフォルトは1件ごと、--no-sync で同期しない)
+        // 削除前、削除後、GC後のジャーナル領域の使用量も出力する
+        // kanils DBench --storage=storage_path --count=number --size=number [--pattern=sequential|random] [--seed=num] [--sync-interval=num | --no-sync]
+        DBench,
+    }
+}
+
+impl Command {
+    // ストレージに変更を加える(もしくは新たに作成する)コマンドかどうか
+    fn is_mutating(&self) -> bool {
+        match *self {
+            Command::Create
+            | Command::Put
+            | Command::Append
+            | Command::Delete
+            | Command::DeleteRange
+            | Command::Clear
+            | Command::JournalGC
+            | Command::Sync
+            | Command::Import
+            | Command::BatchPut
+            | Command::BatchDelete
+            | Command::Resize
+            | Command::Compact
+            | Command::Merge
+            | Command::Repair
+            | Command::GenFixture
+            | Command::WBench
+            | Command::WRBench
+            | Command::MixBench
+            | Command::DBench => true,
+            Command::Dump
+            | Command::List
+            | Command::ListRange
+            | Command::Get
+            | Command::GetRange
+            | Command::Contains
+            | Command::Exists
+            | Command::Size
+            | Command::Stat
+            | Command::Count
+            | Command::Usage
+            | Command::Export
+            | Command::Copy
+            | Command::RBench
+            | Command::Diff
+            | Command::Verify
+            | Command::Header
+            | Command::Info
+            | Command::Journal
+            | Command::JournalStat
+            | Command::Script
+            | Command::Run
+            | Command::Open => false,
+        }
     }
 }
 
@@ -98,131 +439,1120 @@ struct Opt {
     #[structopt(long = "storage", parse(from_os_str))]
     storage_path: PathBuf,
 
-    #[structopt(long = "capacity")]
+    // 書き込み権限なしでストレージを開く
+    #[structopt(long = "read-only")]
+    read_only: bool,
+
+    // 破壊的なコマンドの実行前に確認を求めない
+    #[structopt(long = "yes")]
+    yes: bool,
+
+    // バイト数もしくは `512K`, `256M`, `10G`, `1T` のような2進接頭辞付きの値で指定する
+    #[structopt(long = "capacity", parse(try_from_str = "parse_size"))]
     capacity: Option<u64>,
 
-    #[structopt(long = "key")]
+    // 0より大きく1より小さい値で指定する
+    #[structopt(long = "journal-ratio", parse(try_from_str = "parse_journal_ratio"))]
+    journal_ratio: Option<f64>,
+
+    // capacity以下のバイト数もしくは2進接頭辞付きの値で指定する
+    #[structopt(
+        long = "journal-size",
+        parse(try_from_str = "parse_size"),
+        raw(conflicts_with = r#""journal_ratio""#)
+    )]
+    journal_size: Option<u64>,
+
+    // 512以上32768以下の2の冪で指定する
+    #[structopt(long = "block-size", parse(try_from_str = "parse_block_size"))]
+    block_size: Option<BlockSize>,
+
+    // 10進数、`0x`付きの16進数、32桁の16進数、UUID形式のいずれかで指定する
+    #[structopt(long = "key", parse(try_from_str = "parse_key"))]
     lumpid: Option<u128>,
 
+    // カンマで区切った複数のkey
+    #[structopt(
+        long = "keys",
+        parse(try_from_str = "parse_key"),
+        raw(use_delimiter = "true")
+    )]
+    keys: Vec<u128>,
+
+    #[structopt(long = "start", parse(try_from_str = "parse_key"))]
+    start: Option<u128>,
+
+    #[structopt(long = "end", parse(try_from_str = "parse_key"))]
+    end: Option<u128>,
+
+    #[structopt(long = "min-key", parse(try_from_str = "parse_key"))]
+    min_key: Option<u128>,
+
+    #[structopt(long = "max-key", parse(try_from_str = "parse_key"))]
+    max_key: Option<u128>,
+
     #[structopt(long = "value")]
     data: Option<String>,
 
-    #[structopt(long = "count")]
-    count: Option<u128>,
+    // Putで --value の代わりに標準入力から値を読み込む
+    #[structopt(long = "value-stdin", raw(conflicts_with = r#""data""#))]
+    value_stdin: bool,
+
+    // 値が埋め込める大きさであれば、データ領域ではなくジャーナル領域に埋め込む
+    #[structopt(long = "embed")]
+    embed: bool,
 
-    #[structopt(long = "size")]
+    // 必ずジャーナル領域に埋め込む (埋め込めない大きさであればエラー)
+    #[structopt(long = "embedded", raw(conflicts_with = r#""embed""#))]
+    embedded: bool,
+
+    // ベンチマークで書き込む(読み込む)件数。u64に収まらなければならない
+    #[structopt(long = "count", parse(try_from_str = "parse_count"))]
+    count: Option<u64>,
+
+    // --capacity と同じく単位付きの値で指定できる
+    #[structopt(long = "size", parse(try_from_str = "parse_value_size"))]
     size: Option<usize>,
 
+    #[structopt(long = "with-size")]
+    with_size: bool,
+
+    // 出力するlumpの最大数 (Journalの場合はエントリの最大数)
+    #[structopt(long = "limit")]
+    limit: Option<usize>,
+
+    // List, Dumpで先頭から読み飛ばすlumpの数 (--sort による並べ替えの後に適用する)
+    #[structopt(long = "offset")]
+    offset: Option<usize>,
+
+    // List, Dumpの出力順 (デフォルトはasc)
+    #[structopt(long = "sort", raw(possible_values = r#"&["asc", "desc"]"#))]
+    sort: Option<KeyOrder>,
+
+    // Journalで出力するエントリの種類 (deleteはDeleteRangeも含む)
+    #[structopt(
+        long = "entry-type",
+        raw(possible_values = r#"&["put", "delete", "embed"]"#)
+    )]
+    entry_type: Option<JournalEntryType>,
+
+    // `<lumpid list>` のような囲みや説明の表示を省き、データのみを出力する
+    // Get, Containsの場合は値も出力せず、終了コードのみで結果を返す
+    #[structopt(long = "quiet")]
+    quiet: bool,
+
+    // Getで値をそのまま標準出力に書き出す (--key のみ)
+    #[structopt(long = "raw", raw(conflicts_with_all = r#"&["keys", "quiet"]"#))]
+    raw: bool,
+
+    // keyを数値として出力する際の形式 (dec: 10進数, hex: `0x`付きの16進数, uuid: UUID形式)
+    // Put, Get --keys, Stat, Open の出力に適用される
+    #[structopt(
+        long = "key-format",
+        raw(possible_values = r#"&["dec", "hex", "uuid"]"#)
+    )]
+    key_format: Option<String>,
+
+    #[structopt(long = "source", parse(from_os_str))]
+    source_path: Option<PathBuf>,
+
+    #[structopt(long = "other", parse(from_os_str), raw(alias = r#""from""#))]
+    other_path: Option<PathBuf>,
+
+    #[structopt(
+        long = "on-conflict",
+        raw(possible_values = r#"&["skip", "overwrite", "fail", "error"]"#)
+    )]
+    on_conflict: Option<String>,
+
+    #[structopt(long = "verbose")]
+    verbose: bool,
+
+    #[structopt(long = "dest", parse(from_os_str))]
+    dest_path: Option<PathBuf>,
+
+    #[structopt(long = "force")]
+    force: bool,
+
+    #[structopt(long = "input", parse(from_os_str))]
+    input_path: Option<PathBuf>,
+
+    #[structopt(long = "output", parse(from_os_str))]
+    output_path: Option<PathBuf>,
+
+    #[structopt(long = "strict")]
+    strict: bool,
+
+    #[structopt(
+        long = "sync-interval",
+        raw(alias = r#""sync-every""#),
+        parse(try_from_str = "parse_sync_interval")
+    )]
+    sync_interval: Option<u64>,
+
+    #[structopt(long = "no-sync", raw(conflicts_with = r#""sync_interval""#))]
+    no_sync: bool,
+
+    #[structopt(long = "delete-extra")]
+    delete_extra: bool,
+
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+
+    #[structopt(long = "file", parse(from_os_str))]
+    script_path: Option<PathBuf>,
+
+    // 対話モードの履歴ファイル (デフォルトは ~/.kanils_history)
+    #[structopt(long = "history-file", parse(from_os_str))]
+    history_path: Option<PathBuf>,
+
+    #[structopt(long = "no-history")]
+    no_history: bool,
+
+    // 対話モードで、ジャーナル領域の使用率がこの値を超えたら変更を伴うコマンドの後にGCを行う (0より大きく1より小さい値)
+    #[structopt(
+        long = "auto-gc-threshold",
+        parse(try_from_str = "parse_auto_gc_threshold")
+    )]
+    auto_gc_threshold: Option<f64>,
+
+    // 対話モードで、deleteの実行前に確認を求める (--yes を指定した場合は求めない)
+    #[structopt(long = "confirm-deletes")]
+    confirm_deletes: bool,
+
+    #[structopt(long = "continue-on-error", raw(alias = r#""keep-going""#))]
+    continue_on_error: bool,
+
+    // `-` の場合は標準入力から読み込む
+    #[structopt(long = "script", parse(from_os_str))]
+    run_script_path: Option<PathBuf>,
+
+    #[structopt(long = "expect", raw(number_of_values = "1"))]
+    expect: Vec<String>,
+
+    #[structopt(long = "recipe")]
+    recipe: Option<String>,
+
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+
+    // ベンチマークの結果を機械可読な形式でも出力する (--result-file のみの場合はcsv)
+    #[structopt(long = "result-format", raw(possible_values = r#"&["json", "csv"]"#))]
+    result_format: Option<ResultFormat>,
+
+    // ベンチマークの結果を標準出力ではなくこのファイルに追記する
+    #[structopt(long = "result-file", parse(from_os_str))]
+    result_file: Option<PathBuf>,
+
+    // MixBenchで実行する操作のうち読み込みの割合 (0以上1以下)
+    #[structopt(long = "read-ratio", parse(try_from_str = "parse_read_ratio"))]
+    read_ratio: Option<f64>,
+
+    // MixBenchで実行する操作の数
+    #[structopt(long = "ops")]
+    ops: Option<u64>,
+
+    // ベンチマークで書き込む値の内容 (デフォルトはzero)
+    #[structopt(
+        long = "fill",
+        raw(possible_values = r#"&["zero", "random", "pattern"]"#)
+    )]
+    fill: Option<ValueFill>,
+
+    // WBench, WRBenchで新たにストレージを作成せず、既存のストレージに書き込む
+    #[structopt(long = "existing")]
+    existing: bool,
+
+    // --existing の場合に、既存のlumpと重ならないkeyを選ばず 0..count に書き込む (既存のlumpがあれば上書きする)
+    #[structopt(long = "overwrite", raw(requires = r#""existing""#))]
+    overwrite: bool,
+
+    #[structopt(
+        long = "progress-interval",
+        parse(try_from_str = "parse_progress_interval")
+    )]
+    progress_interval: Option<u64>,
+
+    // WRBenchで一度に書き込み (読み込む) 件数
+    #[structopt(
+        long = "marching-len",
+        raw(alias = r#""window""#),
+        parse(try_from_str = "parse_marching_len")
+    )]
+    marching_len: Option<usize>,
+
+    #[structopt(
+        long = "pattern",
+        raw(possible_values = r#"&["sequential", "marching", "random", "zipfian"]"#)
+    )]
+    pattern: Option<String>,
+
+    // 出力するlumpidを、指定したスキームに従って分解して表示する
+    #[structopt(long = "lumpid-scheme", raw(possible_values = r#"&["frugalos"]"#))]
+    lumpid_scheme: Option<String>,
+
+    #[structopt(
+        long = "format",
+        raw(possible_values = r#"&["text", "plain", "hex", "json", "tsv", "csv"]"#)
+    )]
+    format: Option<String>,
+
     #[structopt(
         raw(
             possible_values = "&Command::variants()",
             requires_ifs = r#"&[
 ("Create", "capacity"),
-("Put", "lumpid"),("Put", "data"),
-("Get", "lumpid"),
+("Put", "lumpid"),
+("Append", "lumpid"),
+("ListRange", "start"),("ListRange", "end"),
+("GetRange", "start"),("GetRange", "end"),
+("Contains", "lumpid"),
+("Exists", "lumpid"),
+("Size", "lumpid"),
+("Stat", "lumpid"),
 ("Delete", "lumpid"),
+("Import", "input_path"),
+("BatchPut", "input_path"),
+("BatchDelete", "input_path"),
+("Export", "output_path"),
+("Copy", "dest_path"),
+("Diff", "other_path"),
+("Merge", "other_path"),
+("Resize", "capacity"),
+("Repair", "source_path"),
+("GenFixture", "recipe"),
+("Script", "script_path"),
+("Run", "run_script_path"),
 ("WBench", "count"),("WBench", "size"),
-("WRBench", "count"),("WRBench", "size")
+("RBench", "count"),
+("WRBench", "count"),("WRBench", "size"),
+("MixBench", "count"),("MixBench", "size"),("MixBench", "read_ratio"),
+("DBench", "count"),("DBench", "size")
 ]"#
         )
     )]
     command: Command,
 }
 
-fn is_valid_characters(data: &str) -> bool {
-    std::str::from_utf8(data.as_bytes()).is_ok()
+fn parse_value_size(s: &str) -> Result<usize, String> {
+    let n = parse_size(s)?;
+    usize::try_from(n).map_err(|_| format!("size {} does not fit in usize", n))
+}
+
+// u128として解釈してから範囲を確かめ、u64に収まらない件数は切り詰めずにエラーとする
+fn parse_count(s: &str) -> Result<u64, String> {
+    let n = s
+        .trim()
+        .parse::<u128>()
+        .map_err(|_| format!("`{}` is not a valid count", s))?;
+    u64::try_from(n).map_err(|_| format!("parameters overflow u64 (count = {})", n))
+}
+
+fn parse_sync_interval(s: &str) -> Result<u64, String> {
+    match s.trim().parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "`{}` is not a valid sync interval (expected a positive integer)",
+            s
+        )),
+    }
+}
+
+fn parse_journal_ratio(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(ratio) if 0.0 < ratio && ratio < 1.0 => Ok(ratio),
+        _ => Err(format!(
+            "`{}` is not a valid journal ratio (expected a number greater than 0 and less than 1)",
+            s
+        )),
+    }
+}
+
+fn parse_auto_gc_threshold(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(ratio) if 0.0 < ratio && ratio < 1.0 => Ok(ratio),
+        _ => Err(format!(
+            "`{}` is not a valid threshold (expected a number greater than 0 and less than 1)",
+            s
+        )),
+    }
 }
 
+fn parse_read_ratio(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(ratio) if 0.0 <= ratio && ratio <= 1.0 => Ok(ratio),
+        _ => Err(format!(
+            "`{}` is not a valid read ratio (expected a number between 0 and 1)",
+            s
+        )),
+    }
+}
+
+fn parse_marching_len(s: &str) -> Result<usize, String> {
+    match s.trim().parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "`{}` is not a valid marching length (expected a positive integer)",
+            s
+        )),
+    }
+}
+
+fn parse_progress_interval(s: &str) -> Result<u64, String> {
+    match s.trim().parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "`{}` is not a valid progress interval (expected a positive integer)",
+            s
+        )),
+    }
+}
+
+fn parse_block_size(s: &str) -> Result<BlockSize, String> {
+    let invalid = || {
+        format!(
+            "`{}` is not a valid block size (expected a power of two between {} and {})",
+            s,
+            BlockSize::MIN,
+            MAX_BLOCK_SIZE
+        )
+    };
+    let n = s.trim().parse::<u16>().map_err(|_| invalid())?;
+    if !n.is_power_of_two() || n < BlockSize::MIN || n > MAX_BLOCK_SIZE {
+        return Err(invalid());
+    }
+    BlockSize::new(n).map_err(|_| invalid())
+}
+
+// u16に収まる最大の2の冪
+const MAX_BLOCK_SIZE: u16 = 1 << 15;
+
+// プロセスの終了コード
+// 0: 成功, 1: keyやファイルが存在しない (差分がある、期待と異なる、中止したなども含む)
+// 2: 引数などの誤り, 3: ストレージの読み書きのエラー
+// スクリプトの実行に失敗した行があった場合は2とする (行ごとのエラーの種類は出力で示す)
+const EXIT_NOT_FOUND: i32 = 1;
+const EXIT_USAGE_ERROR: i32 = 2;
+const EXIT_STORAGE_ERROR: i32 = 3;
+const EXIT_SCRIPT_FAILED: i32 = 2;
+const EXIT_EXPECTATION_FAILED: i32 = 1;
+const EXIT_DIFFERENT: i32 = 1;
+const EXIT_ABORTED: i32 = 1;
+
+// ジャーナル領域の使用量 (バイト)
+fn journal_used_bytes<N: NonVolatileMemory>(storage: &mut Storage<N>) -> u64 {
+    let snapshot = exit_on_error(storage.journal_snapshot(), "read the journal");
+    JournalPositions::new(&snapshot).used_bytes(storage.header())
+}
+
+// WBenchで書き込むkeyの列を --pattern に従って求める
+fn bench_put_keys(pattern: &str, count: u64, rng: &mut Rng) -> Vec<u128> {
+    let mut keys = (0..u128::from(count)).collect::<Vec<_>>();
+    match pattern {
+        "random" => rng.shuffle(&mut keys),
+        "zipfian" => {
+            let zipfian = Zipfian::new(count);
+            for key in &mut keys {
+                *key = u128::from(zipfian.sample(rng));
+            }
+        }
+        _ => {}
+    }
+    keys
+}
+
+// count件の値を書き込めるストレージを新たに作成する (大きさは bench::storage_layout で求める)
+// 大きさが求められない場合は、ファイルを作らずに終了する
 fn create_storage_for_benchmark(
     path: PathBuf,
     count: u64,
     size: u64,
-) -> Result<(Storage<FileNvm>, u64), cannyls::Error> {
-    let total = count * size;
-    let capacity = total * 2;
-    let mut journal_ratio = 0.01f64;
-    if ((capacity as f64 * journal_ratio) as u64) < 256 * count as u64 {
-        journal_ratio = (256 * count as u64) as f64 / capacity as f64;
-    }
-    let nvm: FileNvm = track_try_unwrap!(FileNvm::create(path, capacity as u64));
+    embedded: bool,
+) -> (Storage<FileNvm>, u64) {
+    // ファイルを作る前に、大きさが求められることを確かめる
+    let block_size = u64::from(BlockSize::min().as_u16());
+    let layout = match bench::storage_layout(count, size, embedded, block_size) {
+        Ok(layout) => layout,
+        Err(e) => {
+            eprintln!("cannot create a storage for the benchmark: {}", e);
+            std::process::exit(EXIT_USAGE_ERROR);
+        }
+    };
+    let nvm = exit_on_error(FileNvm::create(path, layout.capacity), "create the storage");
+    let storage = exit_on_error(
+        StorageBuilder::new()
+            .journal_region_ratio(layout.journal_ratio)
+            .create(nvm),
+        "create the storage",
+    );
+    (storage, layout.total)
+}
+
+// --existing の場合に、既存のストレージをベンチマークのために開く
+// count件の値を書き込むだけの空きがデータ領域になければ、空いている大きさを示して終了する
+// 書き込むkeyの開始位置を返す (既存のlumpと重ならないよう最大のkeyの次から。`overwrite`の場合は0から)
+fn open_storage_for_benchmark(
+    path: &Path,
+    count: u64,
+    size: u64,
+    embedded: bool,
+    overwrite: bool,
+) -> (Storage<FileNvm>, u64, u128) {
+    let mut handle = open_storage(path);
+    let usage = exit_on_error(handle.usage(), "compute the usage");
+    let block_size = u64::from(handle.header().block_size.as_u16());
+    let needed = if embedded {
+        Some(0)
+    } else {
+        size.checked_add(block_size - 1)
+            .and_then(|n| count.checked_mul(n / block_size * block_size))
+    };
+    let free = usage.data_region_size - usage.allocated_bytes;
+    match needed {
+        Some(needed) if needed <= free => {}
+        _ => {
+            eprintln!(
+                "not enough free space for {} values of {} bytes: {} has only {} free",
+                count,
+                size,
+                path.display(),
+                format_size(free)
+            );
+            std::process::exit(EXIT_USAGE_ERROR);
+        }
+    }
+    let base = if overwrite {
+        0
+    } else {
+        handle
+            .iter_keys_ordered(0, u128::max_value(), KeyOrder::Desc)
+            .next()
+            .map_or(0, |id| id.as_u128() + 1)
+    };
+    if base.checked_add(u128::from(count)).is_none() {
+        eprintln!("there are not enough unused keys after the existing lumps");
+        std::process::exit(EXIT_USAGE_ERROR);
+    }
+    let total = match count.checked_mul(size) {
+        Some(total) => total,
+        None => {
+            eprintln!(
+                "parameters overflow u64 (count = {}, size = {})",
+                count, size
+            );
+            std::process::exit(EXIT_USAGE_ERROR);
+        }
+    };
+    (handle.into_storage(), total, base)
+}
+
+// Createで明示的に指定されたジャーナル領域の大きさ
+#[derive(Debug, Clone, Copy)]
+enum JournalRegion {
+    Ratio(f64),
+    Size(u64),
+}
+
+// データ領域がcapacityバイト(をブロックサイズに切り上げたもの)のストレージを新たに生成する
+// journalを指定しない場合は、データ領域に収まるlumpの数から求めた比率を用いる
+fn create_storage(
+    path: &Path,
+    capacity: u64,
+    block_size: BlockSize,
+    journal: Option<JournalRegion>,
+) -> Result<Storage<FileNvm>, cannyls::Error> {
+    let block_size_u64 = u64::from(block_size.as_u16());
+
+    let data_region_size = block_size.ceil_align(capacity);
+
+    let journal_header_size = block_size_u64;
+    let journal_record_size =
+        std::cmp::max(block_size_u64 * 2, 20 * (data_region_size / block_size_u64));
+    let journal_region_size = journal_header_size + journal_record_size;
+
+    let header_size = block_size_u64;
+
+    // 比率が指定された場合は、データ領域がcapacityバイトを保つようにジャーナル領域の大きさを求め、大きさの指定と同様に扱う
+    // (ジャーナル領域 : データ領域 = ratio : 1 - ratio)
+    let requested_journal_size = match journal {
+        Some(JournalRegion::Ratio(ratio)) => {
+            Some((data_region_size as f64 * ratio / (1.0 - ratio)).ceil() as u64)
+        }
+        Some(JournalRegion::Size(size)) => Some(size),
+        None => None,
+    };
+    let (total_size, journal_ratio) = match requested_journal_size {
+        Some(size) => {
+            // cannylsはヘッダを除いた領域に対する比率からジャーナル領域を求める
+            let journal_region_size = block_size.ceil_align(size);
+            let body_size = data_region_size + journal_region_size;
+            let journal_ratio = journal_region_size as f64 / body_size as f64;
+            (body_size + header_size, journal_ratio)
+        }
+        None => {
+            let total_size = data_region_size + journal_region_size + header_size;
+            let journal_ratio: f64 =
+                0.01f64.max(journal_region_size as f64 / total_size as f64);
+            (total_size, journal_ratio)
+        }
+    };
+
+    let nvm = track!(FileNvm::create(path, total_size))?;
     track!(
         StorageBuilder::new()
             .journal_region_ratio(journal_ratio)
+            .block_size(block_size)
             .create(nvm)
-    ).map(|s| (s, total as u64))
+    )
+}
+
+// handleの全てのlumpを、データ領域がcapacityバイトの新たなストレージに書き直してpathと置き換える
+// 新たなストレージは一時ファイルに作成し、全てのlumpを書き終えてからrenameする
+// 失敗した場合は一時ファイルを削除し、pathには手を加えない
+fn rewrite_storage(
+    mut handle: StorageHandle,
+    path: &Path,
+    capacity: u64,
+) -> Result<u64, cannyls::Error> {
+    let tmp_path = temporary_path(path);
+    // 以前に中断された際の一時ファイルが残っていれば作り直す
+    let _ = std::fs::remove_file(&tmp_path);
+    let block_size = handle.header().block_size;
+    let result = create_storage(&tmp_path, capacity, block_size, None).and_then(|storage| {
+        handle.copy_to_with_progress(&mut StorageHandle::new(storage), |copied, total| {
+            eprint!("\rcopied {}/{} lumps", copied, total);
+        })
+    });
+    eprintln!();
+    drop(handle);
+    match result {
+        Ok(copied) => {
+            track!(std::fs::rename(&tmp_path, path).map_err(cannyls::Error::from))?;
+            Ok(copied)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+// 破壊的なコマンドを実行する前に、対象のストレージと操作を示して確認を求める
+// --yes が指定されていれば確認しない
+// 標準入力が端末でなければ確認できないため、待ち続けずに終了する
+fn confirm(opt: &Opt, action: &str) {
+    if opt.yes {
+        return;
+    }
+    if !atty::is(atty::Stream::Stdin) {
+        eprintln!("{}", NON_INTERACTIVE_MESSAGE);
+        std::process::exit(EXIT_USAGE_ERROR);
+    }
+    eprint!("{} in {}? [y/N] ", action, opt.storage_path.display());
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    let _ = std::io::stdin().read_line(&mut answer);
+    match answer.trim() {
+        "y" | "Y" | "yes" | "YES" => {}
+        _ => {
+            eprintln!("aborted");
+            std::process::exit(EXIT_ABORTED);
+        }
+    }
 }
 
-fn handle_input(handle: &mut StorageHandle, input: &str) {
-    let put_regex = Regex::new(r"^put\s+([0-9]+)\s+([^\x00]+)$").unwrap();
-    let get_regex = Regex::new(r"^get\s+([0-9]+)$").unwrap();
-    let delete_regex = Regex::new(r"^delete\s*([0-9]+)$").unwrap();
+// lumpを削除するコマンド(clear, delete)を含むスクリプトは、実行前に確認を求める
+fn confirm_script<'a, I: Iterator<Item = &'a str>>(opt: &Opt, commands: I) {
+    let destructive = commands.filter(|c| command::is_destructive(c)).count();
+    if destructive > 0 {
+        confirm(
+            opt,
+            &format!("run a script with {} clear/delete commands", destructive),
+        );
+    }
+}
 
-    if let Some(captured) = put_regex.captures(&input) {
-        let key: u128 = captured.get(1).unwrap().as_str().parse().unwrap();
-        let value: &str = captured.get(2).unwrap().as_str();
+// ストレージを開けなければメッセージを出力して終了する
+// ストレージファイルが存在しなければ、その旨を出力して終了コード1で終了する
+fn ensure_storage_exists(path: &Path) {
+    if !path.exists() {
+        eprintln!("storage file not found: {}", path.display());
+        std::process::exit(EXIT_NOT_FOUND);
+    }
+}
 
-        if is_valid_characters(value) {
-            handle.put(key, value);
+fn open_storage(path: &Path) -> StorageHandle {
+    ensure_storage_exists(path);
+    StorageHandle::create(path).unwrap_or_else(|e| {
+        eprintln!("failed to open {}: {}", path.display(), e);
+        std::process::exit(EXIT_STORAGE_ERROR);
+    })
+}
+
+fn open_storage_read_only(path: &Path) -> StorageHandle<ReadOnlyNvm> {
+    ensure_storage_exists(path);
+    StorageHandle::open_readonly(path).unwrap_or_else(|e| {
+        eprintln!("failed to open {}: {}", path.display(), e);
+        std::process::exit(EXIT_STORAGE_ERROR);
+    })
+}
+
+// エラーであれば何に失敗したかを出力して終了する
+fn exit_on_error<T>(result: Result<T, cannyls::Error>, action: &str) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("failed to {}: {}", action, e);
+        std::process::exit(EXIT_STORAGE_ERROR);
+    })
+}
+
+// --read-only の有無に応じてストレージを開き、`$body`を実行する
+// --lumpid-scheme が指定されていればハンドルに設定する
+macro_rules! with_handle {
+    ($opt:expr, $handle:ident => $body:expr) => {{
+        let scheme = $opt.lumpid_scheme.as_ref().and_then(|s| schemes::find(s));
+        if $opt.read_only {
+            let mut $handle = open_storage_read_only(&$opt.storage_path);
+            $handle.set_lumpid_scheme(scheme);
+            $handle.set_key_format(key_format(&$opt));
+            $handle.set_quiet($opt.quiet);
+            $body
         } else {
-            println!("your input value {} is invalid wrt UTF-8", input);
-        }
-    } else if let Some(captured) = get_regex.captures(&input) {
-        let key: u128 = captured.get(1).unwrap().as_str().parse().unwrap();
-        handle.get(key);
-    } else if let Some(captured) = delete_regex.captures(&input) {
-        let key: u128 = captured.get(1).unwrap().as_str().parse().unwrap();
-        handle.delete(key);
-    } else if input == "list" {
-        handle.print_list_of_lumpids();
-    } else if input == "dump" {
-        handle.print_all_key_value_pairs();
-    } else if input == "header" {
-        handle.print_header_info();
-    } else if input == "journal" {
-        handle.print_journal_info();
-    } else if input == "journal_gc" {
-        handle.journal_gc();
-    } else {
-        println!("`{}` is an invalid command", input);
+            let mut $handle = open_storage(&$opt.storage_path);
+            $handle.set_lumpid_scheme(scheme);
+            $handle.set_key_format(key_format(&$opt));
+            $handle.set_quiet($opt.quiet);
+            $body
+        }
+    }};
+}
+
+// --quiet が指定されていなければ println! と同じく標準出力に出力する
+macro_rules! report {
+    ($quiet:expr, $($arg:tt)*) => {
+        if !$quiet {
+            println!($($arg)*);
+        }
+    };
+}
+
+// --result-format, --result-file に従ってベンチマークの結果を出力する
+// --result-file の場合はファイルに追記する (CSVでは空のファイルにのみ列名の行を書き出す)
+fn write_bench_record(format: Option<ResultFormat>, path: Option<&PathBuf>, record: &BenchRecord) {
+    let format = match (format, path) {
+        (Some(format), _) => format,
+        (None, Some(_)) => ResultFormat::Csv,
+        (None, None) => return,
+    };
+    let result = match path {
+        Some(path) => std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|file| {
+                let header = file.metadata()?.len() == 0;
+                match format {
+                    ResultFormat::Json => record.write_json(file),
+                    ResultFormat::Csv => record.write_csv(header, file),
+                }
+            }),
+        None => {
+            let stdout = std::io::stdout();
+            match format {
+                ResultFormat::Json => record.write_json(stdout.lock()),
+                ResultFormat::Csv => record.write_csv(true, stdout.lock()),
+            }
+        }
+    };
+    if let Err(e) = result {
+        let target = path.map_or_else(|| "stdout".to_owned(), |p| p.display().to_string());
+        eprintln!("failed to write the result to {}: {}", target, e);
+        std::process::exit(EXIT_USAGE_ERROR);
     }
 }
 
-fn main() {
-    let opt = Opt::from_args();
+fn handle_input<N: NonVolatileMemory>(handle: &mut StorageHandle<N>, input: &str) {
+    let result = command::execute(handle, input);
+    let stdout = std::io::stdout();
+    let _ = command::write_text(handle, &result, stdout.lock());
+}
 
-    match opt.command {
-        Command::Create => {
-            let mut data_region_size = opt.capacity.unwrap();
-            println!("passed data region size = {}", data_region_size);
-            let block_size = BlockSize::min();
-            let block_size_u64 = u64::from(block_size.as_u16());
+// `history`が指定されていれば、開始時に履歴を読み込み、終了時に保存する
+// `confirm_deletes`が真の場合は、deleteの実行前に確認を求める
+fn run_repl<N: NonVolatileMemory>(
+    mut handle: StorageHandle<N>,
+    history: Option<PathBuf>,
+    confirm_deletes: bool,
+) {
+    println!("{}", command::help_text());
+    let keys = Rc::new(RefCell::new(handle.all_keys()));
+    let mut rl = Editor::<ReplHelper>::new();
+    rl.set_helper(Some(ReplHelper::new(keys.clone())));
+    if let Some(ref path) = history {
+        repl::load_history(&mut rl, path);
+    }
+    loop {
+        let readline = rl.readline(">> ");
+        match readline {
+            Ok(line) => {
+                rl.add_history_entry(line.as_ref());
+                if line.trim() == "clear" && !handle.is_read_only() {
+                    let question = format!("delete all {} lumps?", keys.borrow().len());
+                    if !repl::confirm(&mut rl, &question) {
+                        println!("aborted");
+                        continue;
+                    }
+                }
+                if confirm_deletes && !handle.is_read_only() {
+                    if let Some(key) = command::delete_target(&line) {
+                        if !repl::confirm(&mut rl, &format!("delete key {}?", key)) {
+                            println!("aborted");
+                            continue;
+                        }
+                    }
+                }
+                handle_input(&mut handle, &line);
+                // 全てのkeyを読み直すため、keyが変わりうるコマンドの後にのみ補完候補を更新する
+                if command::changes_lumps(&line) {
+                    *keys.borrow_mut() = handle.all_keys();
+                }
+            }
+            Err(ReadlineError::Interrupted) => {
+                println!("CTRL-C");
+                break;
+            }
+            Err(ReadlineError::Eof) => {
+                println!("CTRL-D");
+                break;
+            }
+            Err(err) => {
+                println!("Error: {:?}", err);
+                break;
+            }
+        }
+    }
+    if let Some(ref path) = history {
+        if let Err(e) = repl::save_history(&mut rl, path) {
+            eprintln!("failed to save the history to {}: {:?}", path.display(), e);
+        }
+    }
+}
 
-            data_region_size = block_size.ceil_align(data_region_size);
+// --min-key, --max-key のいずれかが指定されていれば、両端を含む範囲を返す
+fn key_bounds(opt: &Opt) -> Option<(u128, u128)> {
+    if opt.min_key.is_none() && opt.max_key.is_none() {
+        return None;
+    }
+    Some((
+        opt.min_key.unwrap_or(0),
+        opt.max_key.unwrap_or_else(u128::max_value),
+    ))
+}
 
-            let journal_header_size = block_size_u64;
-            let journal_record_size =
-                std::cmp::max(block_size_u64 * 2, 20 * (data_region_size / block_size_u64));
-            let journal_region_size = journal_header_size + journal_record_size;
+// 2つのパスが同じファイルを指しているかどうか (どちらかが存在しなければ false)
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
 
-            let header_size = block_size_u64;
+// Put, Append で書き込む値と、その表示用の文字列を求める
+// --value-stdin もしくは --value=- の場合は標準入力の終わりまでを読み込む
+fn read_value(opt: &Opt) -> (Vec<u8>, String) {
+    let from_stdin = opt.value_stdin || opt.data.as_ref().map(|s| s.as_str()) == Some("-");
+    let (value, shown_value) = match opt.data {
+        _ if from_stdin => {
+            let mut value = Vec::new();
+            if let Err(e) = std::io::stdin().read_to_end(&mut value) {
+                eprintln!("failed to read the value from stdin: {}", e);
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let shown_value = format!("read {} bytes from stdin", value.len());
+            (value, shown_value)
+        }
+        Some(ref value) => (value.as_bytes().to_vec(), format!("value={}", value)),
+        None => {
+            eprintln!("{:?} requires --value or --value-stdin", opt.command);
+            std::process::exit(EXIT_USAGE_ERROR);
+        }
+    };
+    if value.len() > LumpData::MAX_SIZE {
+        eprintln!(
+            "the value of {} bytes exceeds the lump size limit ({} bytes)",
+            value.len(),
+            LumpData::MAX_SIZE
+        );
+        std::process::exit(EXIT_USAGE_ERROR);
+    }
+    (value, shown_value)
+}
 
-            let total_size = data_region_size + journal_region_size + header_size;
-            let journal_ratio: f64 = 0.01f64.max(journal_region_size as f64 / total_size as f64);
+fn open_input(path: &Path) -> BufReader<File> {
+    match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            eprintln!("failed to open {}: {}", path.display(), e);
+            std::process::exit(EXIT_USAGE_ERROR);
+        }
+    }
+}
+
+// --key-format を解釈する (省略時は dec)
+fn key_format(opt: &Opt) -> KeyFormat {
+    opt.key_format
+        .as_ref()
+        .map_or(KeyFormat::Dec, |s| s.parse().unwrap())
+}
+
+// --format をlumpid一覧の出力形式として解釈する (省略時は text)
+fn list_format(opt: &Opt) -> ListFormat {
+    opt.format
+        .as_ref()
+        .map_or(Ok(ListFormat::Text), |s| s.parse())
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(EXIT_USAGE_ERROR);
+        })
+}
+
+// DeleteRange で削除する範囲を、両端を含む[min, max]と表示用の文字列として返す
+// 空の範囲の場合は None を返す
+fn delete_range_bounds(opt: &Opt) -> (Option<(u128, u128)>, String) {
+    match (opt.start, key_bounds(opt)) {
+        (Some(start), None) => match opt.end {
+            Some(end) => (
+                end.checked_sub(1)
+                    .filter(|&max| start <= max)
+                    .map(|max| (start, max)),
+                format!("[{:?}, {:?})", LumpId::new(start), LumpId::new(end)),
+            ),
+            None => (
+                Some((start, u128::max_value())),
+                format!("[{:?}, ...]", LumpId::new(start)),
+            ),
+        },
+        (None, Some((min, max))) if opt.end.is_none() => (
+            Some((min, max)).filter(|_| min <= max),
+            format!("[{:?}, {:?}]", LumpId::new(min), LumpId::new(max)),
+        ),
+        _ => {
+            eprintln!("DeleteRange requires either --start [--end] or --min-key/--max-key");
+            std::process::exit(EXIT_USAGE_ERROR);
+        }
+    }
+}
+
+// 範囲内でエントリが存在しなかったkeyの数
+// 範囲の大きさは最大で 2^128 になり u128 に収まらないため、文字列として返す
+fn count_absent(bounds: Option<(u128, u128)>, present: u64) -> String {
+    let (min, max) = match bounds {
+        Some(bounds) => bounds,
+        None => return "0".to_owned(),
+    };
+    match (max - min).checked_add(1) {
+        Some(n) => (n - u128::from(present)).to_string(),
+        None if present > 0 => (u128::max_value() - u128::from(present) + 1).to_string(),
+        None => "340282366920938463463374607431768211456".to_owned(),
+    }
+}
+
+// BatchPut の入力ファイルを --format に従って読み込む
+fn batch_records(opt: &Opt) -> Box<dyn Iterator<Item = std::io::Result<csv::Record>>> {
+    let input = open_input(opt.input_path.as_ref().unwrap());
+    match opt.format.as_ref().map(|s| s.as_str()) {
+        None | Some("tsv") => Box::new(csv::TsvReader::new(input)),
+        Some("csv") => Box::new(csv::Reader::new(input)),
+        Some(format) => {
+            eprintln!(
+                "BatchPut supports only the tsv and csv formats, not {}",
+                format
+            );
+            std::process::exit(EXIT_USAGE_ERROR);
+        }
+    }
+}
+
+// 書き込みを始める前に全てのレコードを検査し、不正なレコードを報告する
+// 全て正しければ true を返す
+fn validate_records<I: Iterator<Item = std::io::Result<csv::Record>>>(records: I) -> bool {
+    let mut valid = true;
+    for record in records {
+        let record = record.unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(EXIT_USAGE_ERROR);
+        });
+        if csv::is_header(&record) {
+            continue;
+        }
+        if let Err(e) = csv::parse_key_value(&record) {
+            eprintln!("line {}: {}", record.line, e);
+            valid = false;
+        }
+    }
+    valid
+}
+
+// スクリプトを実行して期待を評価し、終了コードを返す
+fn run_script<N: NonVolatileMemory>(
+    handle: &mut StorageHandle<N>,
+    script: &str,
+    expectations: &[Expectation],
+    json: bool,
+    continue_on_error: bool,
+) -> i32 {
+    let stdout = std::io::stdout();
+    let succeeded =
+        command::run_script(handle, script, continue_on_error, |handle, line, result| {
+            let mut out = stdout.lock();
+            if json {
+                let _ = command::write_json(handle, result, &mut out);
+            } else {
+                let _ = command::write_text(handle, result, &mut out);
+                if result.status == Status::Error && continue_on_error {
+                    eprintln!("error at line {}", line);
+                } else if result.status == Status::Error {
+                    eprintln!("script aborted at line {}", line);
+                }
+            }
+        });
+    if !succeeded {
+        return EXIT_SCRIPT_FAILED;
+    }
+
+    let mut failed = false;
+    for expectation in expectations {
+        if let Err(e) = expectation.evaluate(handle) {
+            eprintln!("{}", e);
+            failed = true;
+        }
+    }
+    if failed {
+        EXIT_EXPECTATION_FAILED
+    } else {
+        0
+    }
+}
+
+// スクリプトを読み込む (`-` の場合は標準入力から読み込む)
+fn read_script(path: &Path) -> String {
+    let result = if path == Path::new("-") {
+        let mut script = String::new();
+        std::io::stdin().read_to_string(&mut script).map(|_| script)
+    } else {
+        std::fs::read_to_string(path)
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", path.display(), e);
+        std::process::exit(EXIT_USAGE_ERROR);
+    })
+}
+
+// スクリプトを実行し、各行の出力に行番号を付けて出力する
+// 全ての行が成功すれば0を、そうでなければ EXIT_SCRIPT_FAILED を返す
+fn run_numbered<N: NonVolatileMemory>(
+    handle: &mut StorageHandle<N>,
+    script: &str,
+    keep_going: bool,
+) -> i32 {
+    let succeeded = command::run_script(handle, script, keep_going, |handle, line, result| {
+        let mut buf = Vec::new();
+        let _ = command::write_text(handle, result, &mut buf);
+        for output in String::from_utf8_lossy(&buf).lines() {
+            println!("{}: {}", line, output);
+        }
+        if result.status == Status::Error && !keep_going {
+            eprintln!("aborted at line {}", line);
+        }
+    });
+    if succeeded {
+        0
+    } else {
+        EXIT_SCRIPT_FAILED
+    }
+}
+
+// clapは引数の誤りを終了コード1で報告するため、EXIT_USAGE_ERROR で終了し直す
+// --help, --version の場合は標準出力に出力して終了コード0で終了する
+fn parse_args() -> Opt {
+    match Opt::clap().get_matches_safe() {
+        Ok(matches) => Opt::from_clap(&matches),
+        Err(e) => {
+            if e.use_stderr() {
+                eprintln!("{}", e.message);
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            println!("{}", e.message);
+            std::process::exit(0);
+        }
+    }
+}
+
+fn main() {
+    let opt = parse_args();
 
-            let nvm = track_try_unwrap!(FileNvm::create(opt.storage_path, total_size));
-            let storage = track_try_unwrap!(
-                StorageBuilder::new()
-                    .journal_region_ratio(journal_ratio)
-                    .create(nvm)
+    if opt.read_only && opt.command.is_mutating() {
+        eprintln!(
+            "{}: {:?} modifies the storage",
+            READ_ONLY_MESSAGE, opt.command
+        );
+        std::process::exit(EXIT_USAGE_ERROR);
+    }
+
+    match opt.command {
+        Command::Create => {
+            let data_region_size = opt.capacity.unwrap();
+            if opt.storage_path.exists() && !opt.force {
+                eprintln!(
+                    "storage already exists: {} (use --force to overwrite)",
+                    opt.storage_path.display()
+                );
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let journal = match (opt.journal_ratio, opt.journal_size) {
+                (Some(ratio), _) => Some(JournalRegion::Ratio(ratio)),
+                (None, Some(size)) if size == 0 || size > data_region_size => {
+                    eprintln!(
+                        "--journal-size must be greater than 0 and at most the capacity ({} bytes)",
+                        data_region_size
+                    );
+                    std::process::exit(EXIT_USAGE_ERROR);
+                }
+                (None, Some(size)) => Some(JournalRegion::Size(size)),
+                (None, None) => None,
+            };
+            println!(
+                "passed data region size = {}",
+                format_bytes(data_region_size)
+            );
+            match journal {
+                Some(JournalRegion::Ratio(ratio)) => {
+                    println!("requested journal region size ratio = {}", ratio)
+                }
+                Some(JournalRegion::Size(size)) => {
+                    println!("requested journal region size = {}", format_bytes(size))
+                }
+                None => {}
+            }
+            let block_size = opt.block_size.unwrap_or_else(BlockSize::min);
+            let header = exit_on_error(
+                nvm::create_atomically(&opt.storage_path, opt.force, |tmp_path| {
+                    create_storage(tmp_path, data_region_size, block_size, journal)
+                        .map(|storage| storage.header().clone())
+                }),
+                "create the storage",
             );
 
             println!("---------------");
-            let actual_data_region_size = storage.header().data_region_size;
-            let actual_journal_region_size = storage.header().journal_region_size;
-            println!("actual data region size = {}", actual_data_region_size);
+            println!("block size = {}", header.block_size.as_u16());
+            let actual_data_region_size = header.data_region_size;
+            let actual_journal_region_size = header.journal_region_size;
+            println!(
+                "actual data region size = {}",
+                format_bytes(actual_data_region_size)
+            );
             println!(
                 "actual journal region size = {}",
-                actual_journal_region_size
+                format_bytes(actual_journal_region_size)
             );
             println!(
                 "actual journal region size ratio = {}",
@@ -231,120 +1561,1251 @@ fn main() {
             );
         }
         Command::Open => {
-            let nvm = track_try_unwrap!(FileNvm::open(&opt.storage_path));
-            let storage = track_try_unwrap!(StorageBuilder::new().open(nvm));
-
-            let mut handle = StorageHandle::new(storage);
-            let mut rl = Editor::<()>::new();
-            loop {
-                let readline = rl.readline(">> ");
-                match readline {
-                    Ok(line) => {
-                        rl.add_history_entry(line.as_ref());
-                        handle_input(&mut handle, &line);
+            let history = if opt.no_history {
+                None
+            } else {
+                opt.history_path.clone().or_else(repl::default_history_path)
+            };
+            with_handle!(opt, handle => {
+                handle.set_auto_gc_threshold(opt.auto_gc_threshold);
+                if atty::is(atty::Stream::Stdin) {
+                    run_repl(handle, history, opt.confirm_deletes && !opt.yes);
+                } else {
+                    // パイプなどから入力された場合は、プロンプトも履歴も使わずに入力の終わりまで実行する
+                    let stdin = std::io::stdin();
+                    let stdout = std::io::stdout();
+                    if let Err(e) = repl::run_plain(&mut handle, stdin.lock(), stdout.lock(), opt.yes) {
+                        eprintln!("failed to read the commands: {}", e);
+                        std::process::exit(EXIT_USAGE_ERROR);
+                    }
+                }
+            });
+        }
+        Command::ListRange => {
+            let (start, end) = (opt.start.unwrap(), opt.end.unwrap());
+            let format = list_format(&opt);
+            with_handle!(opt, handle => handle.print_range_of_lumpids(start, end, format));
+        }
+        Command::GetRange => {
+            let (start, end) = (opt.start.unwrap(), opt.end.unwrap());
+            let result =
+                with_handle!(opt, handle => handle.print_range_key_value_pairs(start, end));
+            exit_on_error(result, "read the lumps");
+        }
+        Command::Import => {
+            let path = opt.input_path.as_ref().unwrap();
+            if opt.strict && !validate_records(csv::Reader::new(open_input(path))) {
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+
+            let mut handle = open_storage(&opt.storage_path);
+            let result = handle.import_csv(open_input(path), opt.strict, |line, e| {
+                eprintln!("line {}: {} (skipped)", line, e);
+            });
+            match result {
+                Ok(summary) => {
+                    println!("imported = {}", summary.imported);
+                    println!("skipped = {}", summary.skipped);
+                }
+                Err(e) => {
+                    eprintln!("failed to import {}: {}", path.display(), e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
+        }
+        Command::Export => {
+            let path = opt.output_path.as_ref().unwrap();
+            let file = File::create(path).unwrap_or_else(|e| {
+                eprintln!("failed to create {}: {}", path.display(), e);
+                std::process::exit(EXIT_USAGE_ERROR);
+            });
+            let out = std::io::BufWriter::new(file);
+            match with_handle!(opt, handle => handle.export_csv(out)) {
+                Ok(exported) => println!("exported = {}", exported),
+                Err(e) => {
+                    eprintln!("failed to export to {}: {}", path.display(), e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
+        }
+        Command::BatchPut => {
+            if opt.strict && !validate_records(batch_records(&opt)) {
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+
+            let path = opt.input_path.as_ref().unwrap();
+            let sync_interval = opt.sync_interval.unwrap_or(1000);
+            let mut handle = open_storage(&opt.storage_path);
+            let result = handle.put_records(
+                batch_records(&opt),
+                Some(sync_interval),
+                opt.strict,
+                |line, e| eprintln!("line {}: {} (skipped)", line, e),
+            );
+            match result {
+                Ok(summary) => {
+                    println!("inserted = {}", summary.imported - summary.overwritten);
+                    println!("overwritten = {}", summary.overwritten);
+                    println!("skipped = {}", summary.skipped);
+                }
+                Err(e) => {
+                    eprintln!("failed to put the records of {}: {}", path.display(), e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
+        }
+        Command::BatchDelete => {
+            let path = opt.input_path.as_ref().unwrap();
+            let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
+                eprintln!("failed to read {}: {}", path.display(), e);
+                std::process::exit(EXIT_USAGE_ERROR);
+            });
+            let keys = parse_key_list(&text).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(EXIT_USAGE_ERROR);
+            });
+
+            let mut handle = open_storage(&opt.storage_path);
+            match handle.delete_keys(keys) {
+                Ok(summary) => {
+                    println!("deleted = {}", summary.deleted);
+                    println!("absent = {}", summary.absent);
+                }
+                Err(e) => {
+                    eprintln!("failed to delete the keys of {}: {}", path.display(), e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
+        }
+        Command::Run => {
+            let script = read_script(opt.run_script_path.as_ref().unwrap());
+            confirm_script(&opt, script.lines());
+            let keep_going = opt.continue_on_error;
+            let code = with_handle!(opt, handle => run_numbered(&mut handle, &script, keep_going));
+            std::process::exit(code);
+        }
+        Command::Script => {
+            let script = read_script(opt.script_path.as_ref().unwrap());
+            let expectations = opt
+                .expect
+                .iter()
+                .map(|s| s.parse::<Expectation>())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(EXIT_USAGE_ERROR);
+                });
+            confirm_script(
+                &opt,
+                script
+                    .lines()
+                    .chain(expectations.iter().map(|e| e.command())),
+            );
+            let json = opt.format.as_ref().map(|s| s.as_str()) == Some("json");
+            let continue_on_error = opt.continue_on_error;
+            let code = with_handle!(opt, handle => {
+                run_script(&mut handle, &script, &expectations, json, continue_on_error)
+            });
+            std::process::exit(code);
+        }
+        Command::Get => {
+            if opt.keys.is_empty() && opt.lumpid.is_none() {
+                eprintln!("Get requires --key or --keys");
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            if opt.quiet {
+                let keys = if opt.keys.is_empty() {
+                    vec![opt.lumpid.unwrap()]
+                } else {
+                    opt.keys.clone()
+                };
+                let result = with_handle!(opt, handle => handle.get_many(&keys));
+                match result {
+                    Ok(ref values) if values.iter().all(|v| v.1.is_some()) => {}
+                    Ok(_) => std::process::exit(EXIT_NOT_FOUND),
+                    Err(e) => {
+                        eprintln!("failed to get the keys: {}", e);
+                        std::process::exit(EXIT_STORAGE_ERROR);
                     }
-                    Err(ReadlineError::Interrupted) => {
-                        println!("CTRL-C");
-                        break;
+                }
+                return;
+            }
+            if !opt.keys.is_empty() {
+                match with_handle!(opt, handle => handle.print_many(&opt.keys)) {
+                    Ok(0) => {}
+                    Ok(_) => std::process::exit(EXIT_NOT_FOUND),
+                    Err(e) => {
+                        eprintln!("failed to get the keys: {}", e);
+                        std::process::exit(EXIT_STORAGE_ERROR);
                     }
-                    Err(ReadlineError::Eof) => {
-                        println!("CTRL-D");
-                        break;
+                }
+                return;
+            }
+            let key = opt.lumpid.unwrap();
+            if opt.raw {
+                let stdout = std::io::stdout();
+                let result = with_handle!(opt, handle => handle.write_raw(key, stdout.lock()));
+                match result {
+                    Ok(Some(_)) => {}
+                    Ok(None) => {
+                        eprintln!("no entry for key {}", key);
+                        std::process::exit(EXIT_NOT_FOUND);
                     }
-                    Err(err) => {
-                        println!("Error: {:?}", err);
-                        break;
+                    Err(e) => {
+                        eprintln!("failed to get key {}: {}", key, e);
+                        std::process::exit(EXIT_STORAGE_ERROR);
                     }
                 }
+                return;
+            }
+            let result = with_handle!(opt, handle => handle.get(key));
+            match result {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(EXIT_NOT_FOUND),
+                Err(e) => {
+                    eprintln!("failed to get key {}: {}", key, e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
             }
         }
-        Command::Get => {
-            let mut handle = StorageHandle::create(&opt.storage_path);
-            handle.get(opt.lumpid.unwrap());
+        Command::Contains | Command::Exists => {
+            let key = opt.lumpid.unwrap();
+            let quiet = match opt.command {
+                Command::Exists => true,
+                _ => opt.quiet,
+            };
+            let exists = with_handle!(opt, handle => handle.exists(key));
+            report!(quiet, "{}", exists);
+            if !exists {
+                std::process::exit(EXIT_NOT_FOUND);
+            }
+        }
+        Command::Size => {
+            let key = opt.lumpid.unwrap();
+            match with_handle!(opt, handle => handle.lump_size(key)) {
+                Ok(Some(size)) => println!("{}", size),
+                Ok(None) => {
+                    println!("no entry");
+                    std::process::exit(EXIT_NOT_FOUND);
+                }
+                Err(e) => {
+                    eprintln!("failed to get the size of key {}: {}", key, e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
+        }
+        Command::Stat => {
+            let key = opt.lumpid.unwrap();
+            match with_handle!(opt, handle => handle.stat(key)) {
+                Ok(Some(stat)) => {
+                    let stdout = std::io::stdout();
+                    let shown = format_key(key, key_format(&opt));
+                    let _ = write_lump_stat(&shown, &stat, stdout.lock());
+                }
+                Ok(None) => {
+                    println!("no entry");
+                    std::process::exit(EXIT_NOT_FOUND);
+                }
+                Err(e) => {
+                    eprintln!("failed to stat key {}: {}", key, e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
         }
         Command::Put => {
-            let mut handle = StorageHandle::create(&opt.storage_path);
-            handle.put(opt.lumpid.unwrap(), &opt.data.unwrap());
+            let key = opt.lumpid.unwrap();
+            let (value, shown_value) = read_value(&opt);
+            let mut handle = open_storage(&opt.storage_path);
+            let embed = opt.embedded || (opt.embed && value.len() <= LumpData::MAX_EMBEDDED_SIZE);
+            let result = if embed {
+                handle.put_embedded(key, &value)
+            } else {
+                handle.put_bytes(key, &value)
+            };
+            let embedded = if embed { " (embedded)" } else { "" };
+            let shown = format_key(key, key_format(&opt));
+            if exit_on_error(result, "put") {
+                println!("put key={}, {}{}", shown, shown_value, embedded);
+            } else {
+                println!("[overwrite] put key={}, {}{}", shown, shown_value, embedded);
+            }
+        }
+        Command::Append => {
+            let key = opt.lumpid.unwrap();
+            let (value, shown_value) = read_value(&opt);
+            let mut handle = open_storage(&opt.storage_path);
+            let total = exit_on_error(handle.append(key, &value), "append");
+            let shown = format_key(key, key_format(&opt));
+            println!(
+                "append key={}, {} => total length = {}",
+                shown, shown_value, total
+            );
         }
         Command::Journal => {
-            let mut handle = StorageHandle::create(&opt.storage_path);
-            handle.print_journal_info();
+            let json = opt.format.as_ref().map(|s| s.as_str()) == Some("json");
+            let filter = JournalFilter {
+                key: opt.lumpid,
+                entry_type: opt.entry_type,
+                limit: opt.limit,
+            };
+            let result = with_handle!(opt, handle => handle.print_journal_info(&filter, json));
+            exit_on_error(result, "read the journal");
+        }
+        Command::Clear => {
+            confirm(&opt, "delete all lumps");
+            let mut handle = open_storage(&opt.storage_path);
+            match handle.clear() {
+                Ok(deleted) => println!("cleared = {}", deleted),
+                Err(e) => {
+                    eprintln!("failed to clear: {}", e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
         }
         Command::JournalGC => {
-            let mut handle = StorageHandle::create(&opt.storage_path);
-            handle.journal_gc();
+            confirm(&opt, "run journal GC");
+            let mut handle = open_storage(&opt.storage_path);
+            println!("run journal full GC ...");
+            let report = exit_on_error(handle.journal_gc_with_stats(), "run journal GC");
+            println!("journal full GC succeeded!");
+            println!("{}", report);
+        }
+        Command::Sync => {
+            let mut handle = open_storage(&opt.storage_path);
+            exit_on_error(handle.try_journal_sync(), "sync the journal");
+            println!("journal sync succeeded!");
         }
         Command::List => {
-            let mut handle = StorageHandle::create(&opt.storage_path);
-            handle.print_list_of_lumpids();
+            let format = list_format(&opt);
+            let bounds = key_bounds(&opt);
+            let (min, max) = bounds.unwrap_or((0, u128::max_value()));
+            let order = opt.sort.unwrap_or(KeyOrder::Asc);
+            let offset = opt.offset.unwrap_or(0);
+            let result = with_handle!(opt, handle => {
+                if opt.with_size {
+                    handle.print_sizes_between(min, max, order, offset, opt.limit, format)
+                } else {
+                    handle.print_keys_between(min, max, order, offset, opt.limit, format);
+                    Ok(())
+                }
+            });
+            exit_on_error(result, "list the lumps");
         }
         Command::Delete => {
-            let mut handle = StorageHandle::create(&opt.storage_path);
-            handle.delete(opt.lumpid.unwrap());
+            let key = opt.lumpid.unwrap();
+            confirm(&opt, &format!("delete {:?}", LumpId::new(key)));
+            let mut handle = open_storage(&opt.storage_path);
+            let result = exit_on_error(handle.delete_key(key), "delete");
+            println!("delete result => {:?}", result);
+            if !result {
+                std::process::exit(EXIT_NOT_FOUND);
+            }
+        }
+        Command::DeleteRange => {
+            let (bounds, range) = delete_range_bounds(&opt);
+            if opt.dry_run {
+                let mut handle = open_storage_read_only(&opt.storage_path);
+                handle.set_quiet(opt.quiet);
+                let ids = bounds.map_or_else(Vec::new, |(min, max)| handle.list_between(min, max));
+                handle.print_lumpids(&ids, ListFormat::Text);
+                println!("would delete = {}", ids.len());
+                println!("no entry = {}", count_absent(bounds, ids.len() as u64));
+                return;
+            }
+
+            confirm(&opt, &format!("delete all lumps in {}", range));
+            let deleted = match bounds {
+                Some((min, max)) => exit_on_error(
+                    open_storage(&opt.storage_path).delete_between(min, max),
+                    "delete the range",
+                ),
+                None => 0,
+            };
+            println!("deleted = {}", deleted);
+            println!("no entry = {}", count_absent(bounds, deleted));
         }
         Command::Dump => {
-            let mut handle = StorageHandle::create(&opt.storage_path);
-            handle.print_all_key_value_pairs();
+            let format = opt.format.as_ref().map(|s| s.as_str());
+            if format == Some("plain") || format == Some("hex") {
+                eprintln!("Dump supports only the text and json formats");
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let (min, max) = key_bounds(&opt).unwrap_or((0, u128::max_value()));
+            let json = format == Some("json");
+            let order = opt.sort.unwrap_or(KeyOrder::Asc);
+            let offset = opt.offset.unwrap_or(0);
+            let result = with_handle!(opt, handle => {
+                if json {
+                    let stdout = std::io::stdout();
+                    handle.dump_json_between(min, max, order, offset, opt.limit, stdout.lock())
+                } else {
+                    handle.print_entries_between(min, max, order, offset, opt.limit)
+                }
+            });
+            if let Err(e) = result {
+                eprintln!("failed to dump: {}", e);
+                std::process::exit(EXIT_STORAGE_ERROR);
+            }
+        }
+        Command::Copy => {
+            let dest_path = opt.dest_path.as_ref().unwrap();
+            if same_file(&opt.storage_path, dest_path) {
+                eprintln!("cannot copy {:?} onto itself", dest_path);
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            if dest_path.exists() && !opt.force {
+                eprintln!(
+                    "{:?} already exists (use --force to overwrite it)",
+                    dest_path
+                );
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            // コピー元には書き込まない
+            let mut handle = open_storage_read_only(&opt.storage_path);
+            let capacity = opt.capacity.unwrap_or(handle.header().data_region_size);
+            let block_size = opt.block_size.unwrap_or(handle.header().block_size);
+            // コピー先のブロックサイズで切り上げた大きさと比べる
+            let needed = exit_on_error(
+                handle.required_data_bytes(block_size),
+                "compute the size of the lumps",
+            );
+            if block_size.ceil_align(capacity) < needed {
+                eprintln!(
+                    "capacity {} is smaller than the {} bytes needed to copy {:?}",
+                    capacity, needed, opt.storage_path
+                );
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+
+            let now = SystemTime::now();
+            // 一時ファイルにコピーしてから置き換えるため、失敗しても既存の --dest は残る
+            let result = nvm::create_atomically(dest_path, opt.force, |tmp_path| {
+                let storage = track!(create_storage(tmp_path, capacity, block_size, None))?;
+                handle.copy_to(&mut StorageHandle::new(storage))
+            });
+            match result {
+                Ok(copied) => {
+                    if let Ok(elapsed) = now.elapsed() {
+                        println!("copied = {}, elapsed = {:?}", copied, elapsed);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("failed to copy to {:?}: {}", dest_path, e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
         }
+        Command::Resize => {
+            let capacity = opt.capacity.unwrap();
+            let mut handle = open_storage(&opt.storage_path);
+            let in_use = match handle.usage() {
+                Ok(usage) => usage.allocated_bytes,
+                Err(e) => {
+                    eprintln!("failed to compute the usage: {}", e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            };
+            if handle.header().block_size.ceil_align(capacity) < in_use {
+                eprintln!(
+                    "cannot resize to {} bytes: {} bytes are currently in use",
+                    capacity, in_use
+                );
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let old_capacity = handle.header().data_region_size;
+            match rewrite_storage(handle, &opt.storage_path, capacity) {
+                Ok(copied) => println!(
+                    "resized {} -> {} bytes, copied = {}",
+                    old_capacity, capacity, copied
+                ),
+                Err(e) => {
+                    eprintln!(
+                        "failed to resize (the original storage is unchanged): {}",
+                        e
+                    );
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
+        }
+        Command::Compact => {
+            let mut handle = open_storage(&opt.storage_path);
+            let before = match handle.usage() {
+                Ok(usage) => usage,
+                Err(e) => {
+                    eprintln!("failed to compute the usage: {}", e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            };
+            let capacity = handle.header().data_region_size;
+            if let Err(e) = rewrite_storage(handle, &opt.storage_path, capacity) {
+                eprintln!(
+                    "failed to compact (the original storage is unchanged): {}",
+                    e
+                );
+                std::process::exit(EXIT_STORAGE_ERROR);
+            }
+            let after = exit_on_error(open_storage(&opt.storage_path).usage(), "compute the usage");
+            for &(label, usage) in &[("before", &before), ("after", &after)] {
+                println!(
+                    "{:<6}: free bytes = {}, largest free extent = {}, fragmentation = {:.2}%",
+                    label,
+                    usage.free_bytes(),
+                    usage.largest_free_extent,
+                    usage.fragmentation() * 100.0
+                );
+            }
+        }
+        Command::Verify => {
+            let mut handle = open_storage_read_only(&opt.storage_path);
+            let summary = handle.verify(|id, e| println!("{:?}: {}", id, e));
+            println!("{} lumps OK, {} failed", summary.ok, summary.failed);
+            if summary.failed > 0 {
+                std::process::exit(EXIT_STORAGE_ERROR);
+            }
+        }
+        Command::Diff => {
+            // 比較対象のストレージには書き込まない
+            let mut other = open_storage_read_only(opt.other_path.as_ref().unwrap());
+            let diff = match with_handle!(opt, handle => handle.diff(&mut other)) {
+                Ok(diff) => diff,
+                Err(e) => {
+                    eprintln!("failed to compare the storages: {}", e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            };
+            if opt.verbose {
+                for key in &diff.only_in_self {
+                    println!("only in storage {:?}", key);
+                }
+                for key in &diff.only_in_other {
+                    println!("only in other {:?}", key);
+                }
+                for key in &diff.differing {
+                    println!("differ {:?}", key);
+                }
+            }
+            println!(
+                "only in storage = {}, only in other = {}, differing = {}",
+                diff.only_in_self.len(),
+                diff.only_in_other.len(),
+                diff.differing.len()
+            );
+            if !diff.is_empty() {
+                std::process::exit(EXIT_DIFFERENT);
+            }
+        }
+        Command::Merge => {
+            let policy = opt
+                .on_conflict
+                .as_ref()
+                .map_or(ConflictPolicy::Fail, |s| s.parse().unwrap());
+            let other_path = opt.other_path.as_ref().unwrap();
+            if same_file(&opt.storage_path, other_path) {
+                eprintln!("cannot merge {:?} into itself", other_path);
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let mut handle = open_storage(&opt.storage_path);
+            let mut other = open_storage_read_only(other_path);
+            match handle.merge_from(&mut other, policy) {
+                Ok(summary) => println!(
+                    "merged = {}, skipped = {}, overwritten = {}",
+                    summary.merged, summary.skipped, summary.overwritten
+                ),
+                Err(e) => {
+                    eprintln!("failed to merge: {}", e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
+        }
+        Command::Repair => {
+            let source_path = opt.source_path.as_ref().unwrap();
+            if same_file(&opt.storage_path, source_path) {
+                eprintln!("cannot repair {:?} from itself", source_path);
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let mut handle = open_storage(&opt.storage_path);
+            // 正しいとされるレプリカには書き込まない
+            let mut source = open_storage_read_only(source_path);
+            let plan = exit_on_error(
+                handle.repair_plan(&mut source, opt.delete_extra),
+                "compare the storages",
+            );
+            if opt.dry_run {
+                for key in &plan.copy {
+                    println!("copy {:?}", key);
+                }
+                for key in &plan.overwrite {
+                    println!("overwrite {:?}", key);
+                }
+                for key in &plan.delete {
+                    println!("delete {:?}", key);
+                }
+            } else {
+                exit_on_error(handle.apply_repair(&mut source, &plan), "repair");
+            }
+            println!(
+                "{}copied = {}, overwritten = {}, deleted = {}",
+                if opt.dry_run { "[dry run] " } else { "" },
+                plan.copy.len(),
+                plan.overwrite.len(),
+                plan.delete.len()
+            );
+        }
+        Command::GenFixture => {
+            let name = opt.recipe.unwrap();
+            let recipe = match fixtures::find(&name) {
+                Some(recipe) => recipe,
+                None => {
+                    eprintln!("unknown recipe `{}`; available recipes are:", name);
+                    for r in fixtures::RECIPES {
+                        eprintln!("  {:<16} {}", r.name, r.description);
+                    }
+                    std::process::exit(EXIT_USAGE_ERROR);
+                }
+            };
+            if opt.storage_path.exists() {
+                eprintln!("{:?} already exists", opt.storage_path);
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let seed = opt.seed.unwrap_or(0);
+            let nvm = exit_on_error(
+                FileNvm::create(&opt.storage_path, recipe.capacity),
+                "create the storage",
+            );
+            let mut handle = exit_on_error(
+                fixtures::generate(recipe, seed, nvm),
+                "generate the fixture",
+            );
+            println!(
+                "generated fixture `{}` (seed = {}): {} lumps",
+                recipe.name,
+                seed,
+                handle.count()
+            );
+        }
+        Command::Count => {
+            let format = list_format(&opt);
+            if format == ListFormat::Hex {
+                eprintln!("Count supports only the text, plain and json formats");
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            if format == ListFormat::Plain {
+                println!("{}", with_handle!(opt, handle => handle.count()));
+                return;
+            }
+            match with_handle!(opt, handle => handle.lump_stats()) {
+                Ok(stats) => {
+                    let stdout = std::io::stdout();
+                    let _ = write_lump_stats(&stats, format, stdout.lock());
+                }
+                Err(e) => {
+                    eprintln!("failed to count lumps: {}", e);
+                    std::process::exit(EXIT_STORAGE_ERROR);
+                }
+            }
+        }
+        Command::JournalStat => match with_handle!(opt, handle => handle.journal_stats()) {
+            Ok(stats) => {
+                let stdout = std::io::stdout();
+                let _ = write_journal_stats(&stats, stdout.lock());
+            }
+            Err(e) => {
+                eprintln!("failed to read the journal: {}", e);
+                std::process::exit(EXIT_STORAGE_ERROR);
+            }
+        },
+        Command::Usage => match with_handle!(opt, handle => handle.usage()) {
+            Ok(usage) => {
+                let stdout = std::io::stdout();
+                let _ = write_storage_usage(&usage, stdout.lock());
+            }
+            Err(e) => {
+                eprintln!("failed to compute the usage: {}", e);
+                std::process::exit(EXIT_STORAGE_ERROR);
+            }
+        },
         Command::Header => {
-            let mut handle = StorageHandle::create(&opt.storage_path);
-            handle.print_header_info();
+            let json = opt.format.as_ref().map(|s| s.as_str()) == Some("json");
+            with_handle!(opt, handle => handle.print_header_info(json));
+        }
+        Command::Info => {
+            let json = opt.format.as_ref().map(|s| s.as_str()) == Some("json");
+            let result = with_handle!(opt, handle => handle.print_storage_info(json));
+            exit_on_error(result, "read the storage");
         }
         Command::WBench => {
             let count = opt.count.unwrap();
             let size = opt.size.unwrap();
-            let (mut storage, total) =
-                create_storage_for_benchmark(opt.storage_path, count as u64, size as u64).unwrap();
-            let tmp_vec: Vec<u8> = vec![0; size];
+            if size == 0 {
+                eprintln!("--size must be greater than 0");
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            if opt.embedded && size > LumpData::MAX_EMBEDDED_SIZE {
+                eprintln!(
+                    "--size must be at most {} with --embedded",
+                    LumpData::MAX_EMBEDDED_SIZE
+                );
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let pattern = opt.pattern.as_ref().map_or("sequential", |s| s.as_str());
+            if pattern == "marching" {
+                eprintln!("WBench supports only the sequential, random and zipfian patterns");
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let (mut storage, total, base) = if opt.existing {
+                open_storage_for_benchmark(
+                    &opt.storage_path,
+                    count,
+                    size as u64,
+                    opt.embedded,
+                    opt.overwrite,
+                )
+            } else {
+                let (storage, total) = create_storage_for_benchmark(
+                    opt.storage_path,
+                    count,
+                    size as u64,
+                    opt.embedded,
+                );
+                (storage, total, 0)
+            };
+            let seed = opt.seed.unwrap_or(0);
+            let value_fill = opt.fill.unwrap_or(ValueFill::Zero);
+            let mut values = Values::new(value_fill, size, seed);
+            let keys = bench_put_keys(pattern, count, &mut Rng::new(seed));
+            report!(opt.quiet, "pattern = {}, seed = {}", pattern, seed);
+
+            let sync_interval = opt.sync_interval.unwrap_or(1);
+            let mut syncs = 0;
+
+            let mut progress = opt
+                .progress_interval
+                .map(|n| Progress::new(n, count, atty::is(atty::Stream::Stderr)));
 
             let now = SystemTime::now();
 
-            for i in 0..count {
-                let lump_id = LumpId::new(i);
-                let lump_data =
-                    track_try_unwrap!(storage.allocate_lump_data_with_bytes(tmp_vec.as_ref()));
-                storage.put(&lump_id, &lump_data).unwrap();
-                storage.journal_sync().unwrap();
+            let mut latencies = Latencies::new(true);
+            for (i, key) in keys.into_iter().enumerate() {
+                let lump_id = LumpId::new(base + key);
+                let lump_data = if opt.embedded {
+                    exit_on_error(
+                        LumpData::new_embedded(values.next_value().to_vec()),
+                        "create the lump data",
+                    )
+                } else {
+                    exit_on_error(
+                        storage.allocate_lump_data_with_bytes(values.next_value()),
+                        "allocate the lump data",
+                    )
+                };
+                let sync = !opt.no_sync && (i as u64 + 1) % sync_interval == 0;
+                latencies.measure(|| {
+                    exit_on_error(storage.put(&lump_id, &lump_data), "put");
+                    if sync {
+                        exit_on_error(storage.journal_sync(), "sync the journal");
+                    }
+                });
+                if sync {
+                    syncs += 1;
+                }
+                if let Some(ref mut progress) = progress {
+                    progress.update(i as u64 + 1);
+                }
+            }
+            // 最後の半端な件数も、計測を終える前に同期する
+            if !opt.no_sync && count % sync_interval != 0 {
+                exit_on_error(storage.journal_sync(), "sync the journal");
+                syncs += 1;
+            }
+            if let Some(ref mut progress) = progress {
+                progress.finish();
             }
 
-            if let Ok(elapsed) = now.elapsed() {
-                println!("total = {}Byte, elapsed = {:?}", total, elapsed);
+            let elapsed = now.elapsed().unwrap_or_default();
+            report!(
+                opt.quiet,
+                "total = {}Byte ({}), elapsed = {:?}, throughput = {:.2}ops/s",
+                total,
+                format_size(total),
+                elapsed,
+                bench::ops_per_sec(latencies.len() as u64, elapsed)
+            );
+            report!(opt.quiet, "syncs = {}", syncs);
+            let put_latency = latencies.summary();
+            if let Some(summary) = put_latency {
+                report!(opt.quiet, "put latency: {}", summary);
             }
+            let record = BenchRecord {
+                bench: "WBench",
+                count,
+                size: Some(size),
+                pattern: pattern.to_owned(),
+                seed,
+                fill: Some(value_fill),
+                sync_interval: if opt.no_sync {
+                    None
+                } else {
+                    Some(sync_interval)
+                },
+                block_size: storage.header().block_size.as_u16(),
+                ops: latencies.len() as u64,
+                elapsed,
+                put_latency,
+                get_latency: None,
+                delete_latency: None,
+            };
+            write_bench_record(opt.result_format, opt.result_file.as_ref(), &record);
+        }
+        Command::RBench => {
+            let count = opt.count.unwrap();
+            let mut handle = open_storage_read_only(&opt.storage_path);
+            let keys = match opt.pattern.as_ref().map(|s| s.as_str()) {
+                None if opt.size.is_none() => {
+                    eprintln!("RBench requires --size or --pattern");
+                    std::process::exit(EXIT_USAGE_ERROR);
+                }
+                None => (0..u128::from(count)).collect(),
+                Some("marching") => {
+                    eprintln!("RBench supports only the sequential and random patterns");
+                    std::process::exit(EXIT_USAGE_ERROR);
+                }
+                Some(pattern) => {
+                    let ids = handle.all_keys();
+                    if ids.is_empty() {
+                        eprintln!("there are no lumps to read");
+                        std::process::exit(EXIT_NOT_FOUND);
+                    }
+                    let seed = opt.seed.unwrap_or(0);
+                    report!(opt.quiet, "pattern = {}, seed = {}", pattern, seed);
+                    let mut rng = Rng::new(seed);
+                    let zipfian = Zipfian::new(ids.len() as u64);
+                    (0..count)
+                        .map(|i| {
+                            let i = match pattern {
+                                "random" => rng.gen_range(0, ids.len() as u64) as usize,
+                                "zipfian" => zipfian.sample(&mut rng) as usize,
+                                _ => (i % ids.len() as u64) as usize,
+                            };
+                            ids[i].as_u128()
+                        })
+                        .collect::<Vec<_>>()
+                }
+            };
+
+            let now = SystemTime::now();
+            let mut latencies = Latencies::new(true);
+            let mut total = 0;
+            for key in keys {
+                match latencies.measure(|| handle.get_bytes(key)) {
+                    Ok(Some(value)) => {
+                        if let Some(size) = opt.size {
+                            if value.len() != size {
+                                eprintln!(
+                                    "{:?} has {} bytes (expected {})",
+                                    LumpId::new(key),
+                                    value.len(),
+                                    size
+                                );
+                                std::process::exit(EXIT_USAGE_ERROR);
+                            }
+                        }
+                        total += value.len() as u64;
+                    }
+                    Ok(None) => {
+                        eprintln!("{:?} does not exist", LumpId::new(key));
+                        std::process::exit(EXIT_NOT_FOUND);
+                    }
+                    Err(e) => {
+                        eprintln!("failed to read {:?}: {}", LumpId::new(key), e);
+                        std::process::exit(EXIT_STORAGE_ERROR);
+                    }
+                }
+            }
+
+            let elapsed = now.elapsed().unwrap_or_default();
+            report!(
+                opt.quiet,
+                "total = {}Byte ({}), elapsed = {:?}, throughput = {:.2}ops/s, {:.2}MB/s",
+                total,
+                format_size(total),
+                elapsed,
+                bench::ops_per_sec(latencies.len() as u64, elapsed),
+                bench::ops_per_sec(total, elapsed) / 1_000_000.0
+            );
+            let get_latency = latencies.summary();
+            if let Some(summary) = get_latency {
+                report!(
+                    opt.quiet,
+                    "get latency: min = {:?}, avg = {:?}, max = {:?}",
+                    summary.min,
+                    summary.mean,
+                    summary.max
+                );
+                report!(opt.quiet, "get latency: {}", summary);
+            }
+            let record = BenchRecord {
+                bench: "RBench",
+                count,
+                size: opt.size,
+                pattern: opt
+                    .pattern
+                    .clone()
+                    .unwrap_or_else(|| "sequential".to_owned()),
+                seed: opt.seed.unwrap_or(0),
+                fill: None,
+                sync_interval: None,
+                block_size: handle.header().block_size.as_u16(),
+                ops: latencies.len() as u64,
+                elapsed,
+                put_latency: None,
+                get_latency,
+                delete_latency: None,
+            };
+            write_bench_record(opt.result_format, opt.result_file.as_ref(), &record);
         }
         Command::WRBench => {
             let count = opt.count.unwrap();
             let size = opt.size.unwrap();
-            let (mut storage, total) =
-                create_storage_for_benchmark(opt.storage_path, count as u64, size as u64).unwrap();
-            let tmp_vec: Vec<u8> = vec![0; size];
+            if size == 0 {
+                eprintln!("--size must be greater than 0");
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let (mut storage, total, base) = if opt.existing {
+                open_storage_for_benchmark(
+                    &opt.storage_path,
+                    count,
+                    size as u64,
+                    false,
+                    opt.overwrite,
+                )
+            } else {
+                let (storage, total) =
+                    create_storage_for_benchmark(opt.storage_path, count, size as u64, false);
+                (storage, total, 0)
+            };
 
             let now = SystemTime::now();
 
-            // access pattern: marching
-            let marching_len = 100;
+            let pattern = opt.pattern.as_ref().map_or("marching", |s| s.as_str());
+            let seed = opt.seed.unwrap_or(0);
+            let value_fill = opt.fill.unwrap_or(ValueFill::Zero);
+            let mut values = Values::new(value_fill, size, seed);
+            let mut rng = Rng::new(seed);
+            let mut zipfian = Zipfian::new(0);
+            let mut cursor = 0;
+            report!(opt.quiet, "pattern = {}, seed = {}", pattern, seed);
+
+            let mut put_latencies = Latencies::new(true);
+            let mut get_latencies = Latencies::new(true);
+
+            let marching_len = opt.marching_len.unwrap_or(100);
+            if marching_len as u64 > count {
+                eprintln!(
+                    "warning: --marching-len ({}) exceeds --count ({}), so nothing will be read",
+                    marching_len, count
+                );
+            }
             let mut c = 0;
             let mut keystore = Vec::with_capacity(marching_len);
-            for i in 0..count {
-                let lump_id = LumpId::new(i);
-                let lump_data =
-                    track_try_unwrap!(storage.allocate_lump_data_with_bytes(tmp_vec.as_ref()));
-                storage.put(&lump_id, &lump_data).unwrap();
+            for i in 0..u128::from(count) {
+                let lump_id = LumpId::new(base + i);
+                let lump_data = exit_on_error(
+                    storage.allocate_lump_data_with_bytes(values.next_value()),
+                    "allocate the lump data",
+                );
+                put_latencies.measure(|| exit_on_error(storage.put(&lump_id, &lump_data), "put"));
                 if c < marching_len - 1 {
                     keystore.push(lump_id);
                     c += 1;
                 } else {
                     // c == marching_len - 1
-                    for k in &keystore {
-                        let _ = storage.get(k);
+                    match pattern {
+                        "sequential" => {
+                            for _ in 0..marching_len {
+                                let k = LumpId::new(base + cursor);
+                                let _ = get_latencies.measure(|| storage.get(&k));
+                                cursor = if cursor < i { cursor + 1 } else { 0 };
+                            }
+                        }
+                        "random" => {
+                            for _ in 0..marching_len {
+                                let k = rng.next_u128() % (i + 1);
+                                let k = LumpId::new(base + k);
+                                let _ = get_latencies.measure(|| storage.get(&k));
+                            }
+                        }
+                        "zipfian" => {
+                            zipfian.grow(i as u64 + 1);
+                            for _ in 0..marching_len {
+                                let k = u128::from(zipfian.sample(&mut rng));
+                                let k = LumpId::new(base + k);
+                                let _ = get_latencies.measure(|| storage.get(&k));
+                            }
+                        }
+                        _ => {
+                            for k in &keystore {
+                                let _ = get_latencies.measure(|| storage.get(k));
+                            }
+                        }
                     }
                     keystore.clear();
                     c = 0;
                 }
             }
 
-            if let Ok(elapsed) = now.elapsed() {
-                println!("total = {}Byte, elapsed = {:?}", total, elapsed);
+            let elapsed = now.elapsed().unwrap_or_default();
+            let ops = (put_latencies.len() + get_latencies.len()) as u64;
+            report!(
+                opt.quiet,
+                "total = {}Byte ({}), elapsed = {:?}, throughput = {:.2}ops/s",
+                total,
+                format_size(total),
+                elapsed,
+                bench::ops_per_sec(ops, elapsed)
+            );
+            let (put_latency, get_latency) = (put_latencies.summary(), get_latencies.summary());
+            if let Some(summary) = put_latency {
+                report!(opt.quiet, "put latency: {}", summary);
+            }
+            if let Some(summary) = get_latency {
+                report!(opt.quiet, "get latency: {}", summary);
+            }
+            let record = BenchRecord {
+                bench: "WRBench",
+                count,
+                size: Some(size),
+                pattern: pattern.to_owned(),
+                seed,
+                fill: Some(value_fill),
+                sync_interval: None,
+                block_size: storage.header().block_size.as_u16(),
+                ops,
+                elapsed,
+                put_latency,
+                get_latency,
+                delete_latency: None,
+            };
+            write_bench_record(opt.result_format, opt.result_file.as_ref(), &record);
+        }
+        Command::MixBench => {
+            let count = opt.count.unwrap();
+            let size = opt.size.unwrap();
+            if size == 0 || count == 0 {
+                eprintln!("--size and --count must be greater than 0");
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let pattern = opt.pattern.as_ref().map_or("random", |s| s.as_str());
+            if pattern != "random" && pattern != "zipfian" {
+                eprintln!("MixBench supports only the random and zipfian patterns");
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let read_ratio = opt.read_ratio.unwrap();
+            let ops = opt.ops.unwrap_or(count);
+            // 準備の書き込みに加えて、全ての操作が書き込みであってもジャーナル領域が足りるようにする
+            let (mut storage, _) = create_storage_for_benchmark(
+                opt.storage_path,
+                count.saturating_add(ops),
+                size as u64,
+                false,
+            );
+            let seed = opt.seed.unwrap_or(0);
+            let value_fill = opt.fill.unwrap_or(ValueFill::Zero);
+            let mut values = Values::new(value_fill, size, seed);
+            for key in 0..count {
+                let lump_data = exit_on_error(
+                    storage.allocate_lump_data_with_bytes(values.next_value()),
+                    "allocate the lump data",
+                );
+                exit_on_error(
+                    storage.put(&LumpId::new(u128::from(key)), &lump_data),
+                    "put",
+                );
             }
+            exit_on_error(storage.journal_sync(), "sync the journal");
+
+            let mut rng = Rng::new(seed);
+            let zipfian = Zipfian::new(count);
+            report!(
+                opt.quiet,
+                "pattern = {}, seed = {}, read ratio = {}",
+                pattern,
+                seed,
+                read_ratio
+            );
+            let sync_interval = opt.sync_interval.unwrap_or(1);
+
+            let now = SystemTime::now();
+
+            let mut put_latencies = Latencies::new(true);
+            let mut get_latencies = Latencies::new(true);
+            let mut puts = 0;
+            for _ in 0..ops {
+                let key = match pattern {
+                    "zipfian" => zipfian.sample(&mut rng),
+                    _ => rng.gen_range(0, count),
+                };
+                let lump_id = LumpId::new(u128::from(key));
+                if rng.next_f64() < read_ratio {
+                    let _ = get_latencies.measure(|| storage.get(&lump_id));
+                } else {
+                    let lump_data = exit_on_error(
+                        storage.allocate_lump_data_with_bytes(values.next_value()),
+                        "allocate the lump data",
+                    );
+                    puts += 1;
+                    let sync = !opt.no_sync && puts % sync_interval == 0;
+                    put_latencies.measure(|| {
+                        exit_on_error(storage.put(&lump_id, &lump_data), "put");
+                        if sync {
+                            exit_on_error(storage.journal_sync(), "sync the journal");
+                        }
+                    });
+                }
+            }
+            if !opt.no_sync && puts % sync_interval != 0 {
+                exit_on_error(storage.journal_sync(), "sync the journal");
+            }
+
+            let elapsed = now.elapsed().unwrap_or_default();
+            report!(
+                opt.quiet,
+                "elapsed = {:?}, throughput = {:.2}ops/s",
+                elapsed,
+                bench::ops_per_sec(ops, elapsed)
+            );
+            report!(
+                opt.quiet,
+                "gets = {}, throughput = {:.2}ops/s",
+                get_latencies.len(),
+                bench::ops_per_sec(get_latencies.len() as u64, elapsed)
+            );
+            report!(
+                opt.quiet,
+                "puts = {}, throughput = {:.2}ops/s",
+                put_latencies.len(),
+                bench::ops_per_sec(put_latencies.len() as u64, elapsed)
+            );
+            let (put_latency, get_latency) = (put_latencies.summary(), get_latencies.summary());
+            if let Some(summary) = get_latency {
+                report!(opt.quiet, "get latency: {}", summary);
+            }
+            if let Some(summary) = put_latency {
+                report!(opt.quiet, "put latency: {}", summary);
+            }
+            let record = BenchRecord {
+                bench: "MixBench",
+                count,
+                size: Some(size),
+                pattern: pattern.to_owned(),
+                seed,
+                fill: Some(value_fill),
+                sync_interval: if opt.no_sync {
+                    None
+                } else {
+                    Some(sync_interval)
+                },
+                block_size: storage.header().block_size.as_u16(),
+                ops,
+                elapsed,
+                put_latency,
+                get_latency,
+                delete_latency: None,
+            };
+            write_bench_record(opt.result_format, opt.result_file.as_ref(), &record);
+        }
+        Command::DBench => {
+            let count = opt.count.unwrap();
+            let size = opt.size.unwrap();
+            if size == 0 {
+                eprintln!("--size must be greater than 0");
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let pattern = opt.pattern.as_ref().map_or("sequential", |s| s.as_str());
+            if pattern != "sequential" && pattern != "random" {
+                eprintln!("DBench supports only the sequential and random patterns");
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+            let (mut storage, total) =
+                create_storage_for_benchmark(opt.storage_path, count, size as u64, false);
+            let seed = opt.seed.unwrap_or(0);
+            let mut values = Values::new(ValueFill::Zero, size, seed);
+            for key in 0..u128::from(count) {
+                let lump_data = exit_on_error(
+                    storage.allocate_lump_data_with_bytes(values.next_value()),
+                    "allocate the lump data",
+                );
+                exit_on_error(storage.put(&LumpId::new(key), &lump_data), "put");
+            }
+            exit_on_error(storage.journal_sync(), "sync the journal");
+            let keys = bench_put_keys(pattern, count, &mut Rng::new(seed));
+            report!(opt.quiet, "pattern = {}, seed = {}", pattern, seed);
+            let journal_before = journal_used_bytes(&mut storage);
+
+            let sync_interval = opt.sync_interval.unwrap_or(1);
+            let now = SystemTime::now();
+            let mut latencies = Latencies::new(true);
+            for (i, key) in keys.into_iter().enumerate() {
+                let lump_id = LumpId::new(key);
+                let sync = !opt.no_sync && (i as u64 + 1) % sync_interval == 0;
+                latencies.measure(|| {
+                    exit_on_error(storage.delete(&lump_id), "delete");
+                    if sync {
+                        exit_on_error(storage.journal_sync(), "sync the journal");
+                    }
+                });
+            }
+            if !opt.no_sync && count % sync_interval != 0 {
+                exit_on_error(storage.journal_sync(), "sync the journal");
+            }
+            let elapsed = now.elapsed().unwrap_or_default();
+            let journal_after_delete = journal_used_bytes(&mut storage);
+
+            let now = SystemTime::now();
+            exit_on_error(storage.journal_gc(), "run journal GC");
+            let gc_elapsed = now.elapsed().unwrap_or_default();
+            let journal_after_gc = journal_used_bytes(&mut storage);
+
+            report!(
+                opt.quiet,
+                "total = {}Byte ({}), delete elapsed = {:?}, throughput = {:.2}ops/s",
+                total,
+                format_size(total),
+                elapsed,
+                bench::ops_per_sec(latencies.len() as u64, elapsed)
+            );
+            let delete_latency = latencies.summary();
+            if let Some(summary) = delete_latency {
+                report!(opt.quiet, "delete latency: {}", summary);
+            }
+            report!(opt.quiet, "journal GC elapsed = {:?}", gc_elapsed);
+            report!(
+                opt.quiet,
+                "journal used bytes: before delete = {}, after delete = {} (+{}), after GC = {}",
+                journal_before,
+                journal_after_delete,
+                journal_after_delete.saturating_sub(journal_before),
+                journal_after_gc
+            );
+            let record = BenchRecord {
+                bench: "DBench",
+                count,
+                size: Some(size),
+                pattern: pattern.to_owned(),
+                seed,
+                fill: None,
+                sync_interval: if opt.no_sync {
+                    None
+                } else {
+                    Some(sync_interval)
+                },
+                block_size: storage.header().block_size.as_u16(),
+                ops: latencies.len() as u64,
+                elapsed,
+                put_latency: None,
+                get_latency: None,
+                delete_latency,
+            };
+            write_bench_record(opt.result_format, opt.result_file.as_ref(), &record);
         }
     }
 }
@@ -0,0 +1,207 @@
+use cannyls;
+use cannyls::block::BlockSize;
+use cannyls::nvm::NonVolatileMemory;
+use cannyls::ErrorKind;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// `path`に`.tmp`を付けたパスを返す
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+// `create`で一時ファイルを作成し、成功した場合にのみ`path`へrenameする
+// 途中で失敗(中断)しても`path`に作りかけのファイルが残らないようにするため
+// `path`が既に存在する場合は、`overwrite`が真であれば置き換え、偽であればエラーとする
+pub fn create_atomically<T, F>(path: &Path, overwrite: bool, create: F) -> Result<T, cannyls::Error>
+where
+    F: FnOnce(&Path) -> Result<T, cannyls::Error>,
+{
+    track_assert!(
+        overwrite || !path.exists(),
+        ErrorKind::InvalidInput,
+        "storage already exists: {}",
+        path.display()
+    );
+    let tmp_path = temporary_path(path);
+    // 以前に中断された際の一時ファイルが残っていれば作り直す
+    let _ = fs::remove_file(&tmp_path);
+    match create(&tmp_path) {
+        Ok(created) => {
+            if let Err(e) = fs::rename(&tmp_path, path) {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(track!(cannyls::Error::from(e)));
+            }
+            Ok(created)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(track!(e))
+        }
+    }
+}
+
+// 書き込み権限なしでファイルを開くNVM
+// 書き込みは全て`PermissionDenied`で失敗する
+#[derive(Debug)]
+pub struct ReadOnlyNvm {
+    file: File,
+    start: u64,
+    end: u64,
+    position: u64,
+}
+
+impl ReadOnlyNvm {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, cannyls::Error> {
+        let file = track_io!(File::open(path))?;
+        let end = track_io!(file.metadata())?.len();
+        Ok(ReadOnlyNvm {
+            file,
+            start: 0,
+            end,
+            position: 0,
+        })
+    }
+}
+
+impl Read for ReadOnlyNvm {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.capacity().saturating_sub(self.position);
+        let len = ::std::cmp::min(buf.len() as u64, remaining) as usize;
+        self.file
+            .seek(SeekFrom::Start(self.start + self.position))?;
+        let read = self.file.read(&mut buf[..len])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for ReadOnlyNvm {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "storage opened read-only",
+        ))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for ReadOnlyNvm {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::Current(offset) => self.position as i64 + offset,
+            SeekFrom::End(offset) => self.capacity() as i64 + offset,
+        };
+        if position < 0 || position as u64 > self.capacity() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek position is out of range",
+            ));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+impl NonVolatileMemory for ReadOnlyNvm {
+    fn sync(&mut self) -> Result<(), cannyls::Error> {
+        Ok(())
+    }
+    fn position(&self) -> u64 {
+        self.position
+    }
+    fn capacity(&self) -> u64 {
+        self.end - self.start
+    }
+    fn block_size(&self) -> BlockSize {
+        BlockSize::min()
+    }
+    fn split(self, position: u64) -> Result<(Self, Self), cannyls::Error> {
+        track_assert!(position <= self.capacity(), ErrorKind::InvalidInput);
+        let left = ReadOnlyNvm {
+            file: track_io!(self.file.try_clone())?,
+            start: self.start,
+            end: self.start + position,
+            position: 0,
+        };
+        let right = ReadOnlyNvm {
+            file: self.file,
+            start: self.start + position,
+            end: self.end,
+            position: 0,
+        };
+        Ok((left, right))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tempdir::TempDir;
+    use trackable::result::TestResult;
+
+    use super::*;
+
+    #[test]
+    fn read_only_nvm_rejects_writes() -> TestResult {
+        let dir = track_io!(TempDir::new("kanils_test"))?;
+        let path = dir.path().join("test.bin");
+        track_io!(fs::write(&path, b"0123456789"))?;
+
+        let nvm = track!(ReadOnlyNvm::open(&path))?;
+        let (mut left, mut right) = track!(nvm.split(4))?;
+        let mut buf = Vec::new();
+        track_io!(left.read_to_end(&mut buf))?;
+        assert_eq!(buf, b"0123");
+        buf.clear();
+        track_io!(right.read_to_end(&mut buf))?;
+        assert_eq!(buf, b"456789");
+
+        assert!(right.write(b"x").is_err());
+        assert_eq!(track_io!(fs::read(&path))?, b"0123456789");
+        Ok(())
+    }
+
+    #[test]
+    fn create_atomically_works() -> TestResult {
+        let dir = track_io!(TempDir::new("kanils_test"))?;
+        let path = dir.path().join("test.lusf");
+        let write = |bytes: &'static [u8]| {
+            move |tmp: &Path| {
+                assert!(tmp.to_string_lossy().ends_with(".tmp"));
+                track_io!(fs::write(tmp, bytes))
+            }
+        };
+
+        track!(create_atomically(&path, false, write(b"first")))?;
+        assert_eq!(track_io!(fs::read(&path))?, b"first");
+
+        // 既に存在する場合は、overwriteを指定しない限り何もしない
+        assert!(create_atomically(&path, false, write(b"second")).is_err());
+        assert_eq!(track_io!(fs::read(&path))?, b"first");
+
+        track!(create_atomically(&path, true, write(b"second")))?;
+        assert_eq!(track_io!(fs::read(&path))?, b"second");
+
+        // 作成の途中で失敗した場合は、元のファイルに手を加えず、一時ファイルも残さない
+        let fail_halfway = |tmp: &Path| -> Result<(), cannyls::Error> {
+            track_io!(fs::write(tmp, b"half"))?;
+            track_panic!(ErrorKind::Other, "injected error");
+        };
+        assert!(create_atomically(&path, true, fail_halfway).is_err());
+        assert_eq!(track_io!(fs::read(&path))?, b"second");
+        assert!(!temporary_path(&path).exists());
+
+        let fresh = dir.path().join("fresh.lusf");
+        assert!(create_atomically(&fresh, false, fail_halfway).is_err());
+        assert!(!fresh.exists());
+        assert!(!temporary_path(&fresh).exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+// 対話モード(Open)の行編集のための rustyline の Helper と履歴ファイルの扱い
+// 行頭ではコマンド名を、`get`/`delete` の引数ではストレージに存在するkeyを補完する
+use cannyls::lump::LumpId;
+use cannyls::nvm::NonVolatileMemory;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::{Editor, Helper};
+
+use command::{self, COMMANDS, NON_INTERACTIVE_MESSAGE};
+use handle::StorageHandle;
+
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// 引数としてkeyを補完するコマンド
+const KEY_COMMANDS: &[&str] = &["get", "exists", "stat", "get_file", "delete", "journal"];
+
+pub struct ReplHelper {
+    // 補完候補とするkey
+    // 対話モードのループ側で、変更を伴うコマンドを実行するたびに更新する
+    keys: Rc<RefCell<Vec<LumpId>>>,
+}
+impl ReplHelper {
+    pub fn new(keys: Rc<RefCell<Vec<LumpId>>>) -> Self {
+        ReplHelper { keys }
+    }
+}
+impl Completer for ReplHelper {
+    type Candidate = String;
+    fn complete(&self, line: &str, pos: usize) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete_line(line, pos, &self.keys.borrow()))
+    }
+}
+impl Hinter for ReplHelper {}
+impl Highlighter for ReplHelper {}
+impl Helper for ReplHelper {}
+
+// 標準入力が端末でない場合(パイプなど)に使う、プロンプトも履歴もない入力ループ
+// 入力の終わりまで1行ずつコマンドを実行し、実行結果を`out`に書き出す
+// 対話モードと同じく、エラーになった行があっても続ける
+// 確認を求められないため、`yes`でなければlumpを削除するコマンドは実行しない
+pub fn run_plain<N, R, W>(
+    handle: &mut StorageHandle<N>,
+    input: R,
+    mut out: W,
+    yes: bool,
+) -> io::Result<()>
+where
+    N: NonVolatileMemory,
+    R: BufRead,
+    W: Write,
+{
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !yes && command::is_destructive(line) {
+            writeln!(out, "{}", NON_INTERACTIVE_MESSAGE)?;
+            continue;
+        }
+        let result = command::execute(handle, line);
+        command::write_text(handle, &result, &mut out)?;
+    }
+    out.flush()
+}
+
+// `question [y/N] ` と表示して確認を求め、yが入力された場合にのみ true を返す
+// Ctrl-C, Ctrl-D で入力が終わった場合は拒否したものとみなす
+pub fn confirm<H: Helper>(editor: &mut Editor<H>, question: &str) -> bool {
+    match editor.readline(&format!("{} [y/N] ", question)) {
+        Ok(answer) => match answer.trim() {
+            "y" | "Y" | "yes" | "YES" => true,
+            _ => false,
+        },
+        Err(_) => false,
+    }
+}
+
+// 履歴ファイルの既定の場所 (`~/.kanils_history`)
+// ホームディレクトリが分からない場合は None を返す
+pub fn default_history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".kanils_history"))
+}
+
+// 履歴ファイルを読み込む
+// 初回の起動時などファイルが存在しない場合もあるため、読み込めなくてもエラーにはしない (読み込めたかどうかを返す)
+pub fn load_history<H: Helper>(editor: &mut Editor<H>, path: &Path) -> bool {
+    editor.load_history(path).is_ok()
+}
+
+// 履歴ファイルに保存する
+// 書き込めないディレクトリが指定されている場合などはエラーを返す (対話モードは終了させない)
+pub fn save_history<H: Helper>(editor: &mut Editor<H>, path: &Path) -> rustyline::Result<()> {
+    editor.save_history(path)
+}
+
+// カーソル位置`pos`までの入力に対して、置き換えを始める位置と候補を返す
+// keyの候補は10進数で表す
+pub fn complete_line(line: &str, pos: usize, keys: &[LumpId]) -> (usize, Vec<String>) {
+    let line = &line[..pos];
+    let word_start = line
+        .rfind(char::is_whitespace)
+        .map_or(0, |i| i + line[i..].chars().next().unwrap().len_utf8());
+    let word = &line[word_start..];
+    let preceding = line[..word_start].split_whitespace().collect::<Vec<_>>();
+
+    let mut candidates = match preceding.as_slice() {
+        [] => COMMANDS
+            .iter()
+            .filter_map(|c| c.0.split_whitespace().next())
+            .filter(|name| name.starts_with(word))
+            .map(|name| name.to_owned())
+            .collect::<Vec<_>>(),
+        [command] if KEY_COMMANDS.contains(command) => keys
+            .iter()
+            .map(|id| id.as_u128().to_string())
+            .filter(|key| key.starts_with(word))
+            .collect(),
+        _ => Vec::new(),
+    };
+    candidates.sort();
+    candidates.dedup();
+    (word_start, candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cannyls::nvm::MemoryNvm;
+    use cannyls::storage::Storage;
+    use tempdir::TempDir;
+    use trackable::result::TestResult;
+
+    #[test]
+    fn complete_line_works() {
+        let keys = [LumpId::new(12), LumpId::new(3), LumpId::new(120)];
+
+        assert_eq!(complete_line("", 0, &keys).1.len(), COMMANDS.len());
+        assert_eq!(
+            complete_line("li", 2, &keys),
+            (0, vec!["list".to_owned(), "list_range".to_owned()])
+        );
+        assert_eq!(complete_line("journal_gc", 7, &keys).1.len(), 3);
+        assert_eq!(
+            complete_line("get 1", 5, &keys),
+            (4, vec!["12".to_owned(), "120".to_owned()])
+        );
+        assert_eq!(complete_line("delete  ", 8, &keys).1.len(), 3);
+        assert_eq!(complete_line("put 1", 5, &keys), (4, Vec::new()));
+        assert_eq!(complete_line("get 12 1", 8, &keys), (7, Vec::new()));
+    }
+
+    #[test]
+    fn run_plain_works() -> TestResult {
+        let storage = track!(Storage::create(MemoryNvm::new(vec![0; 4_000_000])))?;
+        let mut handle = StorageHandle::new(storage);
+
+        let input = "put 1 foo\n\nget x\nput 2 bar\nclear\ndelete 1\nlist\n";
+        let mut out = Vec::new();
+        track_io!(run_plain(&mut handle, input.as_bytes(), &mut out, false))?;
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains(">> "));
+        assert!(out.contains("`x` is not a valid key"));
+        assert_eq!(out.matches(NON_INTERACTIVE_MESSAGE).count(), 2);
+        assert!(out.ends_with(
+            "<lumpid list>\n\
+             LumpId(\"00000000000000000000000000000001\")\n\
+             LumpId(\"00000000000000000000000000000002\")\n\
+             </lumpid list>\n"
+        ));
+
+        let mut out = Vec::new();
+        track_io!(run_plain(
+            &mut handle,
+            "delete 1\n".as_bytes(),
+            &mut out,
+            true
+        ))?;
+        assert_eq!(handle.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn history_round_trips() {
+        let dir = TempDir::new("kanils_test").unwrap();
+        let path = dir.path().join("history");
+
+        let mut editor = Editor::<()>::new();
+        assert!(!load_history(&mut editor, &path));
+        editor.add_history_entry("put 5 foo");
+        editor.add_history_entry("get 5");
+        assert!(save_history(&mut editor, &path).is_ok());
+
+        let mut editor = Editor::<()>::new();
+        assert!(load_history(&mut editor, &path));
+        assert!(save_history(&mut editor, &path).is_ok());
+        let saved = ::std::fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("put 5 foo"));
+        assert!(saved.contains("get 5"));
+
+        // 存在しないディレクトリへの保存はエラーになるが、panicはしない
+        let missing = dir.path().join("missing").join("history");
+        assert!(save_history(&mut editor, &missing).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+// シードから決定的に値を生成する擬似乱数生成器 (SplitMix64)
+// フィクスチャやベンチマークを同じシードで再現できるようにするために用いる
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u128(&mut self) -> u128 {
+        (u128::from(self.next_u64()) << 64) | u128::from(self.next_u64())
+    }
+
+    // [low, high) の範囲の値を返す
+    pub fn gen_range(&mut self, low: u64, high: u64) -> u64 {
+        assert!(low < high);
+        low + self.next_u64() % (high - low)
+    }
+
+    // [0, 1) の範囲の浮動小数点数を返す
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Fisher-Yatesのシャッフル
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.gen_range(0, i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let x = self.next_u64();
+            for (i, b) in chunk.iter_mut().enumerate() {
+                *b = (x >> (i * 8)) as u8;
+            }
+        }
+    }
+}
+
+// [0, n) の値を、小さい値ほど選ばれやすいZipf分布 (theta = 0.99) に従って生成する
+// YCSBと同じくGrayらの方法を用い、nを増やす場合は差分のみを計算する
+#[derive(Debug, Clone)]
+pub struct Zipfian {
+    n: u64,
+    zetan: f64,
+    zeta2: f64,
+}
+impl Zipfian {
+    const THETA: f64 = 0.99;
+
+    pub fn new(n: u64) -> Self {
+        let mut zipfian = Zipfian {
+            n: 0,
+            zetan: 0.0,
+            zeta2: 1.0 + 0.5f64.powf(Self::THETA),
+        };
+        zipfian.grow(n);
+        zipfian
+    }
+
+    // 値の範囲を [0, n) に広げる
+    pub fn grow(&mut self, n: u64) {
+        for i in self.n..n {
+            self.zetan += 1.0 / ((i + 1) as f64).powf(Self::THETA);
+        }
+        self.n = self.n.max(n);
+    }
+
+    pub fn sample(&self, rng: &mut Rng) -> u64 {
+        assert!(self.n > 0);
+        let u = rng.next_f64();
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < self.zeta2 {
+            return 1;
+        }
+        let n = self.n as f64;
+        let alpha = 1.0 / (1.0 - Self::THETA);
+        let eta = (1.0 - (2.0 / n).powf(1.0 - Self::THETA)) / (1.0 - self.zeta2 / self.zetan);
+        ((n * (eta * u - eta + 1.0).powf(alpha)) as u64).min(self.n - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_yields_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+    }
+
+    #[test]
+    fn gen_range_stays_in_bounds() {
+        let mut rng = Rng::new(0);
+        for _ in 0..1000 {
+            let x = rng.gen_range(10, 20);
+            assert!(10 <= x && x < 20);
+        }
+    }
+
+    #[test]
+    fn shuffle_is_a_permutation() {
+        let mut items = (0..100).collect::<Vec<u32>>();
+        Rng::new(7).shuffle(&mut items);
+        assert_ne!(items, (0..100).collect::<Vec<_>>());
+        items.sort();
+        assert_eq!(items, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn zipfian_is_skewed() {
+        let mut rng = Rng::new(0);
+        let mut zipfian = Zipfian::new(1);
+        assert_eq!(zipfian.sample(&mut rng), 0);
+
+        zipfian.grow(1000);
+        let mut counts = vec![0; 1000];
+        for _ in 0..10_000 {
+            counts[zipfian.sample(&mut rng) as usize] += 1;
+        }
+        // 先頭の値が最も多く選ばれ、後半の値は少ししか選ばれない
+        assert!(counts[0] > counts[1]);
+        assert!(counts[0] > 1000);
+        assert!(counts[500..].iter().sum::<u32>() < 1500);
+    }
+}
@@ -0,0 +1,167 @@
+// 上位のシステムがLumpIdに埋め込んでいる構成要素を扱うための仕組み
+// `--lumpid-scheme` で指定されたスキームに従ってkeyを分解して表示し、
+// `<スキーム名>:<構成要素>` の形式のkeyを組み立てる
+use std::fmt;
+
+pub trait LumpIdScheme: Sync {
+    fn name(&self) -> &'static str;
+
+    // `<スキーム名>:` を除いた部分からkeyを組み立てる
+    fn encode(&self, components: &str) -> Result<u128, String>;
+
+    // keyを構成要素に分解し、人が読める形式にする
+    fn decode(&self, key: u128) -> String;
+}
+
+pub static SCHEMES: &[&'static dyn LumpIdScheme] = &[&Frugalos];
+
+pub fn find(name: &str) -> Option<&'static dyn LumpIdScheme> {
+    SCHEMES.iter().find(|s| s.name() == name).cloned()
+}
+
+// frugalosのLumpIdの構成 (上位ビットから順に)
+//   - 8bit: 種別 (namespace)
+//   - 56bit: デバイス (ローカルノード) のID
+//   - 64bit: オブジェクトのシーケンス番号 (バージョンやRaftのログのインデックス)
+// `frugalos:<type>:<device>:<seq>` の形式で指定する
+// 構成はfrugalosの以下の関数に従う
+//   - frugalos_segment/src/config.rs の `make_lump_id`: 種別1 (オブジェクトの内容)、seqはオブジェクトのバージョン
+//   - frugalos_raft/src/storage/mod.rs の `make_lump_id`: 種別0 (Raft)、seqの上位8bitがRaftのlumpの種類
+//     (0: ログエントリ, 1: ログプレフィクスのインデックス, 2: ログプレフィクス, 3: HardState)、下位56bitがインデックス
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrugalosLumpId {
+    pub kind: u8,
+    pub device: u64,
+    pub seq: u64,
+}
+impl FrugalosLumpId {
+    pub const MAX_DEVICE: u64 = (1 << 56) - 1;
+
+    pub fn from_u128(key: u128) -> Self {
+        FrugalosLumpId {
+            kind: (key >> 120) as u8,
+            device: (key >> 64) as u64 & Self::MAX_DEVICE,
+            seq: key as u64,
+        }
+    }
+
+    pub fn to_u128(&self) -> u128 {
+        (u128::from(self.kind) << 120) | (u128::from(self.device) << 64) | u128::from(self.seq)
+    }
+}
+impl fmt::Display for FrugalosLumpId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "type={}, device={}, seq={}",
+            self.kind, self.device, self.seq
+        )
+    }
+}
+
+pub struct Frugalos;
+impl LumpIdScheme for Frugalos {
+    fn name(&self) -> &'static str {
+        "frugalos"
+    }
+
+    fn encode(&self, components: &str) -> Result<u128, String> {
+        let invalid = || {
+            format!(
+                "`frugalos:{}` is not a valid key (expected frugalos:<type>:<device>:<seq>)",
+                components
+            )
+        };
+        let parts = components.split(':').collect::<Vec<_>>();
+        if parts.len() != 3 {
+            return Err(invalid());
+        }
+        let kind = parts[0].parse::<u8>().map_err(|_| invalid())?;
+        let device = parts[1].parse::<u64>().map_err(|_| invalid())?;
+        let seq = parts[2].parse::<u64>().map_err(|_| invalid())?;
+        if device > FrugalosLumpId::MAX_DEVICE {
+            return Err(format!(
+                "device `{}` does not fit in 56 bits (frugalos:{})",
+                device, components
+            ));
+        }
+        Ok(FrugalosLumpId { kind, device, seq }.to_u128())
+    }
+
+    fn decode(&self, key: u128) -> String {
+        FrugalosLumpId::from_u128(key).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frugalos_round_trip_works() {
+        // frugalos_segmentが保存するオブジェクトの内容: ローカルノード0x0102上のバージョン5
+        let key = 0x0100_0000_0000_0102_0000_0000_0000_0005;
+        let id = FrugalosLumpId::from_u128(key);
+        assert_eq!(
+            id,
+            FrugalosLumpId {
+                kind: 1,
+                device: 0x0102,
+                seq: 5
+            }
+        );
+        assert_eq!(id.to_u128(), key);
+        assert_eq!(Frugalos.encode("1:258:5"), Ok(key));
+        assert_eq!(Frugalos.decode(key), "type=1, device=258, seq=5");
+
+        // frugalos_raftが保存するログエントリ: ローカルノード0x0102上のインデックス42
+        let key = 0x0000_0000_0000_0102_0000_0000_0000_002a;
+        assert_eq!(
+            FrugalosLumpId::from_u128(key),
+            FrugalosLumpId {
+                kind: 0,
+                device: 0x0102,
+                seq: 42
+            }
+        );
+        assert_eq!(Frugalos.encode("0:258:42"), Ok(key));
+
+        // frugalos_raftが保存するHardState: seqの上位8bitがlumpの種類(3)になる
+        let key = 0x0000_0000_0000_0102_0300_0000_0000_0000;
+        assert_eq!(
+            FrugalosLumpId::from_u128(key),
+            FrugalosLumpId {
+                kind: 0,
+                device: 0x0102,
+                seq: 3 << 56
+            }
+        );
+        assert_eq!(
+            Frugalos.decode(key),
+            format!("type=0, device=258, seq={}", 3u64 << 56)
+        );
+
+        for &key in &[
+            0,
+            u128::max_value(),
+            0x00ff_ffff_ffff_ffff_ffff_ffff_ffff_ffff,
+            0xff00_0000_0000_0000_0000_0000_0000_0000,
+        ] {
+            let id = FrugalosLumpId::from_u128(key);
+            assert_eq!(id.to_u128(), key);
+            let components = format!("{}:{}:{}", id.kind, id.device, id.seq);
+            assert_eq!(Frugalos.encode(&components), Ok(key));
+        }
+    }
+
+    #[test]
+    fn frugalos_rejects_invalid_components() {
+        assert!(Frugalos.encode("1:1").is_err());
+        assert!(Frugalos.encode("1:1:5:0").is_err());
+        assert!(Frugalos.encode("256:1:5").is_err());
+        assert!(Frugalos.encode("1:72057594037927936:5").is_err()); // 2^56
+        assert!(Frugalos.encode("1:x:5").is_err());
+        assert!(find("frugalos").is_some());
+        assert!(find("unknown").is_none());
+    }
+}
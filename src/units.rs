@@ -0,0 +1,160 @@
+// バイト数の指定に使える単位
+// `K`, `KiB` のような2進接頭辞 (1K = 1024) と、`KB` のような10進接頭辞 (1KB = 1000) を受け付ける
+const UNITS: &[(char, u32)] = &[('K', 10), ('M', 20), ('G', 30), ('T', 40)];
+
+// 小数部の桁数の上限 (u128で計算するため)
+const MAX_FRACTION_DIGITS: usize = 18;
+
+// `4096`, `512K`, `10GiB`, `1.5GiB`, `100MB` などの文字列をバイト数に変換する
+// 接尾辞の大文字小文字は区別しない (`K`, `KiB` は2進接頭辞、`KB` は10進接頭辞として扱う)
+// 小数は単位を掛けた結果がバイト単位で割り切れる場合のみ受け付ける
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let number_len = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, suffix) = s.split_at(number_len);
+    let mut parts = number.splitn(2, '.');
+    let integer = parts.next().unwrap();
+    let fraction = parts.next().unwrap_or("");
+    if integer.is_empty() || number.ends_with('.') || fraction.contains('.') {
+        return Err(format!(
+            "`{}` is not a valid size (expected a number optionally followed by K, M, G or T)",
+            s
+        ));
+    }
+    if fraction.len() > MAX_FRACTION_DIGITS {
+        return Err(format!("size `{}` has too many fractional digits", s));
+    }
+    let overflow = || format!("size `{}` does not fit in 64 bits", s);
+    let integer = integer.parse::<u64>().map_err(|_| overflow())?;
+
+    let unit = parse_unit(suffix).ok_or_else(|| {
+        format!(
+            "`{}` has an invalid size suffix (expected K, M, G or T, optionally followed by iB or B)",
+            s
+        )
+    })?;
+
+    // 整数部と小数部を分けてu128で計算し、最後にu64に収まるかを確かめる
+    let scale = 10u128.pow(fraction.len() as u32);
+    let fraction = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse::<u128>().unwrap()
+    };
+    let fraction_bytes = fraction * u128::from(unit);
+    if fraction_bytes % scale != 0 {
+        return Err(format!("size `{}` is not a whole number of bytes", s));
+    }
+    let bytes = u128::from(integer) * u128::from(unit) + fraction_bytes / scale;
+    if bytes > u128::from(u64::max_value()) {
+        return Err(overflow());
+    }
+    Ok(bytes as u64)
+}
+
+// 接尾辞が表す1単位のバイト数を返す (不正な接尾辞の場合は None)
+fn parse_unit(suffix: &str) -> Option<u64> {
+    let suffix = suffix.to_ascii_uppercase();
+    if suffix.is_empty() || suffix == "B" {
+        return Some(1);
+    }
+    let mut chars = suffix.chars();
+    let prefix = chars.next().unwrap();
+    let (i, &(_, shift)) = UNITS.iter().enumerate().find(|&(_, u)| u.0 == prefix)?;
+    match chars.as_str() {
+        "" | "IB" => Some(1 << shift),
+        "B" => Some(1000u64.pow(i as u32 + 1)),
+        _ => None,
+    }
+}
+
+// バイト数を `1.5 KiB` のような読みやすい形式にする (1024未満は `512 B`)
+pub fn format_size(bytes: u64) -> String {
+    match UNITS.iter().rev().find(|u| bytes >= 1 << u.1) {
+        Some(&(prefix, shift)) => {
+            format!("{:.1} {}iB", bytes as f64 / (1u64 << shift) as f64, prefix)
+        }
+        None => format!("{} B", bytes),
+    }
+}
+
+// バイト数に読みやすい形式を添える (`1536 (1.5 KiB)`、1024未満はバイト数のみ)
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes < 1024 {
+        bytes.to_string()
+    } else {
+        format!("{} ({})", bytes, format_size(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_works() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("256m"), Ok(256 * 1024 * 1024));
+        assert_eq!(parse_size("10G"), Ok(10_737_418_240));
+        assert_eq!(parse_size("1T"), Ok(1 << 40));
+        assert_eq!(parse_size("1TiB"), Ok(1 << 40));
+        assert_eq!(parse_size("10GiB"), Ok(10 << 30));
+        assert_eq!(parse_size("512mib"), Ok(512 << 20));
+        assert_eq!(parse_size(" 7 "), Ok(7));
+        assert_eq!(parse_size("12B"), Ok(12));
+
+        // 10進接頭辞
+        assert_eq!(parse_size("2KB"), Ok(2000));
+        assert_eq!(parse_size("100MB"), Ok(100_000_000));
+        assert_eq!(parse_size("10gb"), Ok(10_000_000_000));
+        assert_eq!(parse_size("1TB"), Ok(1_000_000_000_000));
+
+        // 小数
+        assert_eq!(parse_size("1.5GiB"), Ok(3 << 29));
+        assert_eq!(parse_size("0.5K"), Ok(512));
+        assert_eq!(parse_size("2.25MB"), Ok(2_250_000));
+        assert_eq!(parse_size("3.0"), Ok(3));
+        assert!(parse_size("1.5").is_err());
+        assert!(parse_size("0.001K").is_err());
+        assert!(parse_size("1.0000000000000000001K").is_err());
+
+        // 不正な入力
+        assert!(parse_size("").is_err());
+        assert!(parse_size("K").is_err());
+        assert!(parse_size(".5K").is_err());
+        assert!(parse_size("1.K").is_err());
+        assert!(parse_size("1.2.3K").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("10KK").is_err());
+        assert!(parse_size("10KiBB").is_err());
+        assert!(parse_size("10 K").is_err());
+        assert!(parse_size("-1").is_err());
+
+        // オーバーフロー
+        assert!(parse_size("16777216T").is_err());
+        assert!(parse_size("18446744073709551616").is_err());
+        assert!(parse_size("18446744073709551615").is_ok());
+        assert!(parse_size("16777215.9999999TiB").is_err());
+        assert!(parse_size("18446745TB").is_err());
+    }
+
+    #[test]
+    fn format_size_works() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(256 * 1024 * 1024), "256.0 MiB");
+        assert_eq!(format_size(3 << 40), "3.0 TiB");
+    }
+
+    #[test]
+    fn format_bytes_works() {
+        assert_eq!(format_bytes(512), "512");
+        assert_eq!(format_bytes(2048), "2048 (2.0 KiB)");
+        assert_eq!(format_bytes(10 << 30), "10737418240 (10.0 GiB)");
+    }
+}